@@ -7,7 +7,7 @@ use crate::gfx::shape2d::{Fill, Rotation, Shape, Stroke};
 use crate::gfx::{Rgba8, ZDepth};
 
 use crate::util::vector_angle;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::f32::consts::PI;
 use std::fmt;
 
@@ -37,6 +37,11 @@ pub enum BrushMode {
     XSym,
     /// Y-Symmetry mode.
     YSym,
+    /// Diagonal symmetry mode, mirroring across the 45 degree line through the
+    /// symmetry center.
+    DSym,
+    /// N-way radial symmetry mode, around the symmetry center.
+    Radial(u8),
     /// X-Ray mode.
     XRay,
     /// Confine stroke to a straight line from the starting point
@@ -44,6 +49,12 @@ pub enum BrushMode {
         /// snap angle (degrees)
         Option<u32>,
     ),
+    /// Paint with a dithering pattern.
+    Dither(Pattern),
+    /// Shade pixels with the next color of their palette ramp.
+    Shade,
+    /// Only paint over opaque pixels, preserving their alpha.
+    LockAlpha,
 }
 
 impl fmt::Display for BrushMode {
@@ -54,13 +65,181 @@ impl fmt::Display for BrushMode {
             Self::Perfect => "perfect".fmt(f),
             Self::XSym => "xsym".fmt(f),
             Self::YSym => "ysym".fmt(f),
+            Self::DSym => "dsym".fmt(f),
+            Self::Radial(n) => write!(f, "radial {}", n),
             Self::XRay => "xray".fmt(f),
             Self::Line(Some(snap)) => write!(f, "{} degree snap line", snap),
             Self::Line(None) => write!(f, "line"),
+            Self::Dither(pattern) => write!(f, "dither {}", pattern),
+            Self::Shade => "shade".fmt(f),
+            Self::LockAlpha => "lock-alpha".fmt(f),
         }
     }
 }
 
+/// Dithering pattern. Patterns are anchored to the view, so that adjacent
+/// strokes tile seamlessly.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub enum Pattern {
+    /// Checkerboard pattern.
+    Checker,
+    /// 2x2 ordered dithering, with a level from `0` to `4`.
+    Bayer2(u8),
+    /// 4x4 ordered dithering, with a level from `0` to `16`.
+    Bayer4(u8),
+    /// Custom 8x8 pattern. Each byte is a row, starting from the top, and
+    /// each bit is a pixel, starting from the left.
+    Custom(u64),
+}
+
+impl Pattern {
+    const BAYER2: [[u8; 2]; 2] = [[0, 2], [3, 1]];
+    pub(crate) const BAYER4: [[u8; 4]; 4] =
+        [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+    /// Check whether the pattern is set at the given view coordinates, ie.
+    /// whether the pixel should be painted with the foreground color.
+    pub fn is_set(&self, x: i32, y: i32) -> bool {
+        match self {
+            Self::Checker => (x + y).rem_euclid(2) == 0,
+            Self::Bayer2(level) => {
+                Self::BAYER2[y.rem_euclid(2) as usize][x.rem_euclid(2) as usize] < *level
+            }
+            Self::Bayer4(level) => {
+                Self::BAYER4[y.rem_euclid(4) as usize][x.rem_euclid(4) as usize] < *level
+            }
+            Self::Custom(bits) => {
+                let row = 7 - y.rem_euclid(8);
+                let col = x.rem_euclid(8);
+
+                (bits >> (63 - (row * 8 + col))) & 1 == 1
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Checker => "checker".fmt(f),
+            Self::Bayer2(level) => write!(f, "bayer2 {}", level),
+            Self::Bayer4(level) => write!(f, "bayer4 {}", level),
+            Self::Custom(bits) => write!(f, "{:016x}", bits),
+        }
+    }
+}
+
+/// A geometric figure, drawn with the shape tools.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Figure {
+    /// Rectangle outline.
+    Rectangle,
+    /// Filled rectangle.
+    FilledRectangle,
+    /// Ellipse outline.
+    Ellipse,
+    /// Filled ellipse.
+    FilledEllipse,
+}
+
+impl Figure {
+    /// Check whether the figure is filled.
+    pub fn is_filled(&self) -> bool {
+        matches!(self, Self::FilledRectangle | Self::FilledEllipse)
+    }
+}
+
+impl fmt::Display for Figure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rectangle => "rectangle".fmt(f),
+            Self::FilledRectangle => "filled rectangle".fmt(f),
+            Self::Ellipse => "ellipse".fmt(f),
+            Self::FilledEllipse => "filled ellipse".fmt(f),
+        }
+    }
+}
+
+/// A multi-color brush stamp, eg. captured from a selection.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Stamp {
+    /// Stamp width in pixels.
+    pub width: u32,
+    /// Stamp height in pixels.
+    pub height: u32,
+    /// Stamp pixels, stored top row first, like image files.
+    pub pixels: Vec<Rgba8>,
+}
+
+impl Stamp {
+    /// Create a new stamp from a pixel buffer.
+    pub fn new(width: u32, height: u32, pixels: Vec<Rgba8>) -> Self {
+        debug_assert_eq!(pixels.len(), (width * height) as usize);
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Iterate over the non-transparent pixels of the stamp, relative to the
+    /// stamp center, with the y-axis pointing up.
+    pub fn iter(&self) -> impl Iterator<Item = (Vector2<i32>, Rgba8)> + '_ {
+        let (w, h) = (self.width as i32, self.height as i32);
+
+        self.pixels
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.a > 0)
+            .map(move |(i, c)| {
+                let (x, y) = (i as i32 % w, h - 1 - i as i32 / w);
+                (Vector2::new(x - w / 2, y - h / 2), *c)
+            })
+    }
+}
+
+/// Orientation of a brush head, as a matrix applied to stamp offsets.
+#[derive(PartialEq, Copy, Clone, Debug)]
+struct Orientation([[f32; 2]; 2]);
+
+impl Orientation {
+    const IDENTITY: Self = Self([[1., 0.], [0., 1.]]);
+    const MIRROR_X: Self = Self([[-1., 0.], [0., 1.]]);
+    const MIRROR_Y: Self = Self([[1., 0.], [0., -1.]]);
+    const MIRROR_DIAGONAL: Self = Self([[0., 1.], [1., 0.]]);
+
+    /// Counter-clockwise rotation by the given angle, in radians.
+    fn rotation(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self([[cos, -sin], [sin, cos]])
+    }
+
+    /// Return the orientation obtained by applying `other` after `self`.
+    fn then(self, other: Self) -> Self {
+        let (a, b) = (other.0, self.0);
+        let mut m = [[0.; 2]; 2];
+
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = a[i][0] * b[0][j] + a[i][1] * b[1][j];
+            }
+        }
+        Self(m)
+    }
+
+    /// Orient an offset, rounding to the nearest pixel.
+    fn apply(&self, v: Vector2<i32>) -> Vector2<i32> {
+        let [[a, b], [c, d]] = self.0;
+        let (x, y) = (v.x as f32, v.y as f32);
+
+        Vector2::new(
+            (a * x + b * y).round() as i32,
+            (c * x + d * y).round() as i32,
+        )
+    }
+}
+
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Align {
     Center,
@@ -78,9 +257,21 @@ pub struct Brush {
     pub stroke: Vec<Point2<i32>>,
     /// Current stroke color.
     pub color: Rgba8,
+    /// Color painted on the unset cells of the dithering pattern, if any.
+    pub dither_color: Option<Rgba8>,
+    /// Multi-color stamp, if any. Replaces the square brush head.
+    pub stamp: Option<Stamp>,
+    /// Number of palette ramp steps pixels are shaded by in shading mode.
+    /// Negative values shade towards the start of the ramp.
+    pub shade_step: i32,
+    /// Center of the symmetry modes, relative to the bottom-left corner of
+    /// each frame, in half-pixel units. Defaults to the frame center.
+    pub symmetry: Option<Point2<i32>>,
 
     /// Currently active brush modes.
     modes: BTreeSet<BrushMode>,
+    /// Position at which drawing started.
+    origin: Point2<i32>,
     /// Current brush position.
     curr: Point2<i32>,
     /// Previous brush position.
@@ -94,7 +285,12 @@ impl Default for Brush {
             state: BrushState::NotDrawing,
             stroke: Vec::with_capacity(32),
             color: Rgba8::TRANSPARENT,
+            dither_color: None,
+            stamp: None,
+            shade_step: 1,
+            symmetry: None,
             modes: BTreeSet::new(),
+            origin: Point2::new(0, 0),
             curr: Point2::new(0, 0),
             prev: Point2::new(0, 0),
         }
@@ -115,13 +311,33 @@ impl Brush {
                 self.unset(line_mode);
             }
         }
+        if let BrushMode::Dither(_) = m {
+            // only one dithering pattern may be active at a time
+            if let Some(dither_mode) = self.dither_mode() {
+                self.unset(dither_mode);
+            }
+        }
+        if let BrushMode::Radial(_) = m {
+            // only one radial symmetry may be active at a time
+            if let Some(radial_mode) = self.radial_mode() {
+                self.unset(radial_mode);
+            }
+        }
         self.modes.insert(m)
     }
 
     /// De-activate the given brush mode.
     pub fn unset(&mut self, m: BrushMode) -> bool {
-        match self.line_mode() {
-            Some(line_mode) if matches!(m, BrushMode::Line(_)) => self.modes.remove(&line_mode),
+        match (self.line_mode(), self.dither_mode(), self.radial_mode()) {
+            (Some(line_mode), _, _) if matches!(m, BrushMode::Line(_)) => {
+                self.modes.remove(&line_mode)
+            }
+            (_, Some(dither_mode), _) if matches!(m, BrushMode::Dither(_)) => {
+                self.modes.remove(&dither_mode)
+            }
+            (_, _, Some(radial_mode)) if matches!(m, BrushMode::Radial(_)) => {
+                self.modes.remove(&radial_mode)
+            }
             _ => self.modes.remove(&m),
         }
     }
@@ -158,6 +374,7 @@ impl Brush {
         self.state = BrushState::DrawStarted(extent);
         self.color = color;
         self.stroke = Vec::with_capacity(32);
+        self.origin = *p;
         self.draw(p);
     }
 
@@ -170,6 +387,31 @@ impl Brush {
             .next()
     }
 
+    /// If a dither mode is active, return it
+    fn dither_mode(&self) -> Option<BrushMode> {
+        self.modes
+            .iter()
+            .filter(|mode| matches!(mode, BrushMode::Dither(_)))
+            .cloned()
+            .next()
+    }
+
+    /// If a radial symmetry mode is active, return it
+    pub fn radial_mode(&self) -> Option<BrushMode> {
+        self.modes
+            .iter()
+            .filter(|mode| matches!(mode, BrushMode::Radial(_)))
+            .cloned()
+            .next()
+    }
+
+    /// Return the symmetry center within a frame of the given size.
+    pub fn symmetry_center(&self, fw: u32, fh: u32) -> Point2<f32> {
+        self.symmetry
+            .map(|c| Point2::new(c.x as f32 / 2., c.y as f32 / 2.))
+            .unwrap_or_else(|| Point2::new(fw as f32 / 2., fh as f32 / 2.))
+    }
+
     /// Draw. Called while input is pressed.
     pub fn draw(&mut self, p: ViewCoords<i32>) {
         self.prev = if let BrushState::DrawStarted(_) = self.state {
@@ -216,6 +458,86 @@ impl Brush {
         }
     }
 
+    /// Draw a figure spanning from the point at which drawing started, to the
+    /// given point. If `constrain` is set, the figure's width and height are
+    /// made equal, eg. to draw a square or circle.
+    pub fn draw_figure(&mut self, figure: Figure, p: ViewCoords<i32>, constrain: bool) {
+        let origin = self.origin;
+        let mut end = *p;
+
+        if constrain {
+            let (dx, dy) = (end.x - origin.x, end.y - origin.y);
+            let d = i32::max(dx.abs(), dy.abs());
+
+            end = Point2::new(
+                origin.x + if dx < 0 { -d } else { d },
+                origin.y + if dy < 0 { -d } else { d },
+            );
+        }
+        self.prev = self.curr;
+        self.curr = end;
+        self.stroke.clear();
+
+        match figure {
+            Figure::Rectangle | Figure::FilledRectangle => {
+                Brush::rectangle(origin, end, &mut self.stroke)
+            }
+            Figure::Ellipse | Figure::FilledEllipse => {
+                Brush::ellipse(origin, end, &mut self.stroke)
+            }
+        }
+        if figure.is_filled() {
+            Brush::fill(&mut self.stroke);
+        }
+        self.stroke.sort_by_key(|p| (p.y, p.x));
+        self.stroke.dedup();
+
+        if let BrushState::DrawStarted(extent) = self.state {
+            self.state = BrushState::Drawing(extent);
+        }
+    }
+
+    /// Draw a bezier curve. Takes the start and end of the curve, followed
+    /// by zero, one or two control points. The curve is drawn with
+    /// pixel-perfect filtering.
+    pub fn draw_curve(&mut self, points: &[Point2<i32>]) {
+        let mut curve = Vec::new();
+        Brush::curve(points, &mut curve);
+
+        self.stroke = Brush::filter(&curve);
+
+        if let BrushState::DrawStarted(extent) = self.state {
+            self.state = BrushState::Drawing(extent);
+        }
+    }
+
+    /// Draw a polygon through the given vertices. Open polygons are drawn as a
+    /// path from the first vertex to the last. Only closed polygons can be filled.
+    pub fn draw_polygon(&mut self, vertices: &[Point2<i32>], closed: bool, filled: bool) {
+        self.stroke.clear();
+
+        if closed {
+            Brush::polygon(vertices, &mut self.stroke);
+
+            if filled {
+                Brush::fill_polygon(vertices, &mut self.stroke);
+            }
+        } else {
+            for edge in vertices.windows(2) {
+                Brush::line(edge[0], edge[1], &mut self.stroke);
+            }
+            if let [p] = vertices {
+                self.stroke.push(*p);
+            }
+        }
+        self.stroke.sort_by_key(|p| (p.y, p.x));
+        self.stroke.dedup();
+
+        if let BrushState::DrawStarted(extent) = self.state {
+            self.state = BrushState::Drawing(extent);
+        }
+    }
+
     /// Stop drawing. Called when input is released.
     pub fn stop_drawing(&mut self) {
         match self.state {
@@ -228,34 +550,87 @@ impl Brush {
 
     /// Expand a point into all brush heads.
     pub fn expand(&self, p: ViewCoords<i32>, extent: ViewExtent) -> Vec<ViewCoords<i32>> {
-        let mut pixels = vec![*p];
+        self.heads(p, extent)
+            .iter()
+            .map(|(p, _)| ViewCoords::new(p.x, p.y))
+            .collect()
+    }
+
+    /// Expand a point into all brush heads, along with the orientation of
+    /// each head. Heads produced by symmetry modes are mirrored or rotated.
+    fn heads(&self, p: ViewCoords<i32>, extent: ViewExtent) -> Vec<(Point2<i32>, Orientation)> {
+        let mut heads = vec![(*p, Orientation::IDENTITY)];
         let ViewExtent { fw, fh, nframes } = extent;
+        let center = self.symmetry_center(fw, fh);
+        // Twice the symmetry center, which is always a whole number, since the
+        // center may lie on a pixel edge or in the middle of a pixel.
+        let (cx2, cy2) = (
+            (center.x * 2.).round() as i32,
+            (center.y * 2.).round() as i32,
+        );
+        let frame_x = |x: i32| x.div_euclid(fw as i32) * fw as i32;
 
         if self.is_set(BrushMode::XSym) {
-            for p in pixels.clone() {
-                let frame_index = p.x / fw as i32;
+            for (p, dir) in heads.clone() {
+                let fx = frame_x(p.x);
 
-                pixels.push(Point2::new(
-                    (frame_index + 1) * fw as i32 - (p.x - frame_index * fw as i32) - 1,
-                    p.y,
+                heads.push((
+                    Point2::new(2 * fx + cx2 - p.x - 1, p.y),
+                    dir.then(Orientation::MIRROR_X),
                 ));
             }
         }
         if self.is_set(BrushMode::YSym) {
-            for p in pixels.clone() {
-                pixels.push(Point2::new(p.x, fh as i32 - p.y - 1));
+            for (p, dir) in heads.clone() {
+                heads.push((
+                    Point2::new(p.x, cy2 - p.y - 1),
+                    dir.then(Orientation::MIRROR_Y),
+                ));
+            }
+        }
+        if self.is_set(BrushMode::DSym) {
+            for (p, dir) in heads.clone() {
+                let fx = frame_x(p.x);
+                let d = (cx2 - cy2).div_euclid(2);
+
+                heads.push((
+                    Point2::new(fx + d + p.y, p.x - fx - d),
+                    dir.then(Orientation::MIRROR_DIAGONAL),
+                ));
+            }
+        }
+        if let Some(BrushMode::Radial(n)) = self.radial_mode() {
+            for (p, dir) in heads.clone() {
+                let fx = frame_x(p.x);
+                // Position of the pixel center, relative to the symmetry center.
+                let dx = (p.x - fx) as f32 + 0.5 - center.x;
+                let dy = p.y as f32 + 0.5 - center.y;
+
+                for i in 1..n {
+                    let rotation = Orientation::rotation(2. * PI * i as f32 / n as f32);
+                    let [[a, b], [c, d]] = rotation.0;
+                    let (x, y) = (a * dx + b * dy, c * dx + d * dy);
+
+                    heads.push((
+                        Point2::new(
+                            fx + (center.x + x - 0.5).round() as i32,
+                            (center.y + y - 0.5).round() as i32,
+                        ),
+                        dir.then(rotation),
+                    ));
+                }
             }
         }
         if self.is_set(BrushMode::Multi) {
-            for p in pixels.clone() {
+            for (p, dir) in heads.clone() {
                 let frame_index = p.x / fw as i32;
                 for i in 0..nframes as i32 - frame_index {
                     let offset = Vector2::new((i as u32 * fw) as i32, 0);
-                    pixels.push(p + offset);
+                    heads.push((p + offset, dir));
                 }
             }
         }
-        pixels.iter().map(|p| ViewCoords::new(p.x, p.y)).collect()
+        heads
     }
 
     /// Return the brush's output strokes as shapes.
@@ -264,6 +639,31 @@ impl Brush {
             BrushState::DrawStarted(extent)
             | BrushState::Drawing(extent)
             | BrushState::DrawEnded(extent) => {
+                if self.stamp.is_some() {
+                    return self
+                        .stroke
+                        .iter()
+                        .flat_map(|p| self.stamp_pixels(ViewCoords::new(p.x, p.y), extent))
+                        .map(|(p, color)| {
+                            let (x, y) = (p.x as f32, p.y as f32);
+                            // When erasing, the stamp is only used as a mask.
+                            let fill = if self.is_set(BrushMode::Erase) {
+                                fill
+                            } else {
+                                Fill::Solid(color.into())
+                            };
+
+                            Shape::Rectangle(
+                                Rect::new(x, y, x + 1., y + 1.) * scale,
+                                ZDepth::ZERO,
+                                Rotation::ZERO,
+                                stroke,
+                                fill,
+                            )
+                        })
+                        .collect();
+                }
+
                 let mut pixels = Vec::new();
 
                 for p in &self.stroke {
@@ -271,6 +671,11 @@ impl Brush {
                         self.expand(ViewCoords::new(p.x, p.y), extent).as_slice(),
                     );
                 }
+
+                if let Some(BrushMode::Dither(pattern)) = self.dither_mode() {
+                    return self.dither(pattern, &pixels, stroke, fill, scale);
+                }
+
                 pixels
                     .iter()
                     .map(|p| {
@@ -289,6 +694,102 @@ impl Brush {
         }
     }
 
+    /// Return the pixels covered by the brush's output, without duplicates.
+    /// Ignores the brush stamp.
+    pub fn pixels(&self) -> Vec<Point2<i32>> {
+        match self.state {
+            BrushState::DrawStarted(extent)
+            | BrushState::Drawing(extent)
+            | BrushState::DrawEnded(extent) => {
+                let heads: Vec<_> = self
+                    .stroke
+                    .iter()
+                    .flat_map(|p| self.expand(ViewCoords::new(p.x, p.y), extent))
+                    .collect();
+
+                self.cover(&heads)
+                    .into_iter()
+                    .map(|(x, y)| Point2::new(x, y))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Return the pixels covered by the given brush heads.
+    fn cover(&self, heads: &[ViewCoords<i32>]) -> BTreeSet<(i32, i32)> {
+        let size = self.size as i32;
+        let mut pixels = BTreeSet::new();
+
+        for head in heads {
+            for dy in 0..size {
+                for dx in 0..size {
+                    pixels.insert((head.x - size / 2 + dx, head.y - size / 2 + dy));
+                }
+            }
+        }
+        pixels
+    }
+
+    /// Return the dithered output of the given brush heads. Since the pattern
+    /// is anchored to the view, every pixel covered by the heads is painted
+    /// individually.
+    fn dither(
+        &self,
+        pattern: Pattern,
+        heads: &[ViewCoords<i32>],
+        stroke: Stroke,
+        fill: Fill,
+        scale: f32,
+    ) -> Vec<Shape> {
+        self.cover(heads)
+            .into_iter()
+            .filter_map(|(x, y)| {
+                let fill = if pattern.is_set(x, y) {
+                    fill
+                } else if let (Some(color), false) =
+                    (self.dither_color, self.is_set(BrushMode::Erase))
+                {
+                    Fill::Solid(color.into())
+                } else {
+                    return None;
+                };
+                let (x, y) = (x as f32, y as f32);
+
+                Some(Shape::Rectangle(
+                    Rect::new(x, y, x + 1., y + 1.) * scale,
+                    ZDepth::ZERO,
+                    Rotation::ZERO,
+                    stroke,
+                    fill,
+                ))
+            })
+            .collect()
+    }
+
+    /// Return the pixels painted by the brush stamp at the given position,
+    /// for all brush heads. Returns nothing if the brush has no stamp.
+    pub fn stamp_pixels(
+        &self,
+        p: ViewCoords<i32>,
+        extent: ViewExtent,
+    ) -> Vec<(ViewCoords<i32>, Rgba8)> {
+        let stamp = if let Some(stamp) = &self.stamp {
+            stamp
+        } else {
+            return Vec::new();
+        };
+        let mut pixels = Vec::new();
+
+        for (head, dir) in self.heads(p, extent) {
+            for (offset, color) in stamp.iter() {
+                let p = head + dir.apply(offset);
+                pixels.push((ViewCoords::new(p.x, p.y), color));
+            }
+        }
+        pixels
+    }
+
     /// Return the shape that should be painted when the brush is at the given
     /// position with the given parameters. Takes an `Origin` which describes
     /// whether to align the position to the bottom-left of the shape, or the
@@ -353,6 +854,203 @@ impl Brush {
         }
     }
 
+    /// Draw a line, quadratic or cubic bezier curve, depending on the number
+    /// of control points following the start and end of the curve.
+    pub fn curve(points: &[Point2<i32>], canvas: &mut Vec<Point2<i32>>) {
+        let (p0, p1) = match points {
+            [p0, p1, ..] => (*p0, *p1),
+            _ => return,
+        };
+        let controls: Vec<Point2<f32>> = points[2..]
+            .iter()
+            .map(|p| Point2::new(p.x as f32, p.y as f32))
+            .collect();
+        let (a, b) = (
+            Point2::new(p0.x as f32, p0.y as f32),
+            Point2::new(p1.x as f32, p1.y as f32),
+        );
+        let at = |t: f32| -> Point2<f32> {
+            let u = 1. - t;
+
+            match controls.as_slice() {
+                [] => Point2::new(u * a.x + t * b.x, u * a.y + t * b.y),
+                [c] => Point2::new(
+                    u * u * a.x + 2. * u * t * c.x + t * t * b.x,
+                    u * u * a.y + 2. * u * t * c.y + t * t * b.y,
+                ),
+                [c1, c2, ..] => Point2::new(
+                    u * u * u * a.x
+                        + 3. * u * u * t * c1.x
+                        + 3. * u * t * t * c2.x
+                        + t * t * t * b.x,
+                    u * u * u * a.y
+                        + 3. * u * u * t * c1.y
+                        + 3. * u * t * t * c2.y
+                        + t * t * t * b.y,
+                ),
+            }
+        };
+        // The length of the control polygon is an upper bound on the length of
+        // the curve, so sampling twice per unit of length leaves no gaps.
+        let mut polygon = vec![a];
+        polygon.extend(controls.iter().cloned());
+        polygon.push(b);
+
+        let length: f32 = polygon
+            .windows(2)
+            .map(|w| ((w[1].x - w[0].x).powi(2) + (w[1].y - w[0].y).powi(2)).sqrt())
+            .sum();
+        let steps = (length * 2.).ceil().max(1.) as u32;
+        let mut prev = p0;
+
+        canvas.push(p0);
+
+        for i in 1..=steps {
+            let p = at(i as f32 / steps as f32);
+            let p = Point2::new(p.x.round() as i32, p.y.round() as i32);
+
+            if p != prev {
+                let mut segment = Vec::new();
+                Brush::line(prev, p, &mut segment);
+                canvas.extend(segment.into_iter().skip(1));
+                prev = p;
+            }
+        }
+    }
+
+    /// Draw the outline of the rectangle spanning the two given corners.
+    pub fn rectangle(p0: Point2<i32>, p1: Point2<i32>, canvas: &mut Vec<Point2<i32>>) {
+        let (x0, x1) = (i32::min(p0.x, p1.x), i32::max(p0.x, p1.x));
+        let (y0, y1) = (i32::min(p0.y, p1.y), i32::max(p0.y, p1.y));
+
+        for x in x0..=x1 {
+            canvas.push(Point2::new(x, y0));
+            canvas.push(Point2::new(x, y1));
+        }
+        for y in y0 + 1..y1 {
+            canvas.push(Point2::new(x0, y));
+            canvas.push(Point2::new(x1, y));
+        }
+    }
+
+    /// Draw the outline of the closed polygon through the given vertices.
+    pub fn polygon(vertices: &[Point2<i32>], canvas: &mut Vec<Point2<i32>>) {
+        for (i, v) in vertices.iter().enumerate() {
+            Brush::line(*v, vertices[(i + 1) % vertices.len()], canvas);
+        }
+    }
+
+    /// Fill the inside of the closed polygon through the given vertices, row by row,
+    /// using the even-odd rule. The outline isn't always included, and should be drawn
+    /// separately.
+    pub fn fill_polygon(vertices: &[Point2<i32>], canvas: &mut Vec<Point2<i32>>) {
+        let (y1, y2) = match (
+            vertices.iter().map(|v| v.y).min(),
+            vertices.iter().map(|v| v.y).max(),
+        ) {
+            (Some(y1), Some(y2)) => (y1, y2),
+            _ => return,
+        };
+
+        for y in y1..=y2 {
+            let mut crossings: Vec<f32> = Vec::new();
+
+            for (i, p) in vertices.iter().enumerate() {
+                let q = vertices[(i + 1) % vertices.len()];
+
+                if (p.y > y) != (q.y > y) {
+                    let t = (y - p.y) as f32 / (q.y - p.y) as f32;
+                    crossings.push(p.x as f32 + t * (q.x - p.x) as f32);
+                }
+            }
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            for pair in crossings.chunks_exact(2) {
+                for x in pair[0].ceil() as i32..=pair[1].floor() as i32 {
+                    canvas.push(Point2::new(x, y));
+                }
+            }
+        }
+    }
+
+    /// Draw the outline of the ellipse inscribed in the rectangle spanning the two
+    /// given corners. Uses a variant of the midpoint ellipse algorithm which supports
+    /// even diameters.
+    pub fn ellipse(p0: Point2<i32>, p1: Point2<i32>, canvas: &mut Vec<Point2<i32>>) {
+        let (mut x0, mut x1) = (i32::min(p0.x, p1.x) as i64, i32::max(p0.x, p1.x) as i64);
+        let (mut y0, mut y1) = (i32::min(p0.y, p1.y) as i64, i32::max(p0.y, p1.y) as i64);
+
+        let a = x1 - x0;
+        let b = y1 - y0;
+        let b1 = b & 1;
+
+        let mut dx = 4 * (1 - a) * b * b;
+        let mut dy = 4 * (b1 + 1) * a * a;
+        let mut err = dx + dy + b1 * a * a;
+
+        y0 += (b + 1) / 2;
+        y1 = y0 - b1;
+
+        let aa = 8 * a * a;
+        let bb = 8 * b * b;
+
+        let mut plot = |x: i64, y: i64| canvas.push(Point2::new(x as i32, y as i32));
+
+        loop {
+            plot(x1, y0);
+            plot(x0, y0);
+            plot(x0, y1);
+            plot(x1, y1);
+
+            let e2 = 2 * err;
+            if e2 <= dy {
+                y0 += 1;
+                y1 -= 1;
+                dy += aa;
+                err += dy;
+            }
+            if e2 >= dx || 2 * err > dy {
+                x0 += 1;
+                x1 -= 1;
+                dx += bb;
+                err += dx;
+            }
+            if x0 > x1 {
+                break;
+            }
+        }
+
+        // Finish the tips of flat ellipses.
+        while y0 - y1 <= b {
+            plot(x0 - 1, y0);
+            plot(x1 + 1, y0);
+            plot(x0 - 1, y1);
+            plot(x1 + 1, y1);
+
+            y0 += 1;
+            y1 -= 1;
+        }
+    }
+
+    /// Fill the given closed outline, by joining the outermost points of each row.
+    fn fill(canvas: &mut Vec<Point2<i32>>) {
+        let mut rows: BTreeMap<i32, (i32, i32)> = BTreeMap::new();
+
+        for p in canvas.iter() {
+            let (x0, x1) = rows.entry(p.y).or_insert((p.x, p.x));
+
+            *x0 = i32::min(*x0, p.x);
+            *x1 = i32::max(*x1, p.x);
+        }
+        canvas.clear();
+
+        for (y, (x0, x1)) in rows {
+            for x in x0..=x1 {
+                canvas.push(Point2::new(x, y));
+            }
+        }
+    }
+
     /// Paint a circle into a pixel buffer.
     #[allow(dead_code)]
     fn paint(
@@ -522,4 +1220,177 @@ mod test {
             assert_eq!(canvas, brush15);
         }
     }
+
+    #[test]
+    fn test_stamp_symmetry() {
+        let r = Rgba8::RED;
+        let g = Rgba8::GREEN;
+        let extent = ViewExtent::new(8, 8, 1);
+
+        let mut brush = Brush::default();
+        brush.stamp = Some(Stamp::new(2, 1, vec![r, g]));
+        brush.set(BrushMode::XSym);
+
+        let pixels = brush.stamp_pixels(ViewCoords::new(2, 0), extent);
+
+        assert_eq!(
+            pixels,
+            vec![
+                (ViewCoords::new(1, 0), r),
+                (ViewCoords::new(2, 0), g),
+                (ViewCoords::new(6, 0), r),
+                (ViewCoords::new(5, 0), g),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_polygon() {
+        let extent = ViewExtent::new(8, 8, 1);
+        let triangle = [Point2::new(0, 0), Point2::new(4, 0), Point2::new(0, 4)];
+        let mut brush = Brush::default();
+
+        brush.start_drawing(ViewCoords::new(0, 0), Rgba8::WHITE, extent);
+
+        brush.draw_polygon(&triangle[..2], false, false);
+        assert_eq!(brush.stroke.len(), 5, "open polygons are drawn as a path");
+
+        brush.draw_polygon(&triangle, true, false);
+        assert_eq!(brush.stroke.len(), 12);
+        assert!(brush.stroke.contains(&Point2::new(2, 2)));
+        assert!(!brush.stroke.contains(&Point2::new(1, 1)));
+
+        brush.draw_polygon(&triangle, true, true);
+        assert_eq!(brush.stroke.len(), 15);
+        assert!(brush
+            .stroke
+            .iter()
+            .all(|p| p.x >= 0 && p.y >= 0 && p.x + p.y <= 4));
+    }
+
+    #[test]
+    fn test_symmetry() {
+        let extent = ViewExtent::new(8, 8, 2);
+        let mut brush = Brush::default();
+
+        brush.symmetry = Some(Point2::new(4, 8));
+        brush.set(BrushMode::XSym);
+        assert_eq!(
+            brush.expand(ViewCoords::new(9, 1), extent),
+            vec![ViewCoords::new(9, 1), ViewCoords::new(10, 1)]
+        );
+        brush.unset(BrushMode::XSym);
+
+        brush.set(BrushMode::DSym);
+        assert_eq!(
+            brush.expand(ViewCoords::new(0, 5), extent),
+            vec![ViewCoords::new(0, 5), ViewCoords::new(3, 2)]
+        );
+        brush.unset(BrushMode::DSym);
+
+        brush.symmetry = None;
+        brush.set(BrushMode::Radial(3));
+        brush.set(BrushMode::Radial(4));
+        assert!(!brush.is_set(BrushMode::Radial(3)));
+        assert_eq!(
+            brush.expand(ViewCoords::new(5, 4), extent),
+            vec![
+                ViewCoords::new(5, 4),
+                ViewCoords::new(3, 5),
+                ViewCoords::new(2, 3),
+                ViewCoords::new(4, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_curve() {
+        let (p0, p1) = (Point2::new(0, 0), Point2::new(12, 4));
+
+        let mut line = Vec::new();
+        let mut curve = Vec::new();
+        Brush::line(p0, p1, &mut line);
+        Brush::curve(&[p0, p1], &mut curve);
+        assert_eq!(curve, line);
+
+        for controls in &[
+            vec![Point2::new(0, 8)],
+            vec![Point2::new(0, 8), Point2::new(4, -6)],
+        ] {
+            let mut points = vec![p0, p1];
+            points.extend_from_slice(controls);
+
+            let mut curve = Vec::new();
+            Brush::curve(&points, &mut curve);
+            let curve = Brush::filter(&curve);
+
+            assert_eq!(curve.first(), Some(&p0));
+            assert_eq!(curve.last(), Some(&p1));
+
+            for w in curve.windows(2) {
+                let d = w[1] - w[0];
+                assert!(d.x.abs() <= 1 && d.y.abs() <= 1, "curve has a gap");
+            }
+            for w in curve.windows(3) {
+                let l_corner = (w[0].y == w[1].y && w[2].x == w[1].x)
+                    || (w[0].x == w[1].x && w[2].y == w[1].y);
+                assert!(!l_corner, "curve has an L-corner at {:?}", w[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ellipse() {
+        let mut canvas = Vec::new();
+        Brush::ellipse(Point2::new(0, 0), Point2::new(4, 4), &mut canvas);
+
+        canvas.sort_by_key(|p| (p.y, p.x));
+        canvas.dedup();
+
+        #[rustfmt::skip]
+        let expected = vec![
+            (1, 0), (2, 0), (3, 0),
+            (0, 1), (4, 1),
+            (0, 2), (4, 2),
+            (0, 3), (4, 3),
+            (1, 4), (2, 4), (3, 4),
+        ];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(x, y)| Point2::new(x, y))
+            .collect();
+
+        assert_eq!(canvas, expected);
+    }
+
+    #[test]
+    fn test_filled_rectangle() {
+        let mut canvas = Vec::new();
+        Brush::rectangle(Point2::new(3, 2), Point2::new(0, 0), &mut canvas);
+        assert_eq!(canvas.len(), 10);
+
+        Brush::fill(&mut canvas);
+        assert_eq!(canvas.len(), 12);
+    }
+
+    #[test]
+    fn test_dither_patterns() {
+        let count = |pattern: Pattern| {
+            (0..8)
+                .flat_map(|y| (0..8).map(move |x| (x, y)))
+                .filter(|(x, y)| pattern.is_set(*x, *y))
+                .count()
+        };
+
+        assert_eq!(count(Pattern::Checker), 32);
+        assert_eq!(count(Pattern::Bayer2(1)), 16);
+        assert_eq!(count(Pattern::Bayer4(4)), 16);
+        assert_eq!(count(Pattern::Bayer4(16)), 64);
+        assert_eq!(count(Pattern::Custom(0xff00000000000000)), 8);
+
+        // The top row of the pattern is anchored to the top of each 8x8 tile.
+        assert!(Pattern::Custom(0xff00000000000000).is_set(3, 7));
+        assert!(Pattern::Custom(0xff00000000000000).is_set(3, -1));
+        assert!(!Pattern::Custom(0xff00000000000000).is_set(3, 0));
+    }
 }
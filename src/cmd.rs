@@ -1,9 +1,17 @@
 use crate::autocomplete::{self, Autocomplete, FileCompleter, FileCompleterOpts};
-use crate::brush::BrushMode;
+use crate::brush::{BrushMode, Figure};
+use crate::color::Color;
+use crate::editor::{Change, Channel, Target};
+use crate::fx::{Connectivity, Placement};
+use crate::gradient::Dithering;
 use crate::history::History;
+use crate::mask::MaskOp;
 use crate::parser::*;
 use crate::platform;
-use crate::session::{Direction, Input, Mode, PanState, Tool, VisualState};
+use crate::quantize::Metric;
+use crate::session::{
+    CurveState, Direction, Input, LassoState, Mode, PanState, PolygonState, Tool, VisualState,
+};
 
 use memoir::traits::Parse;
 use memoir::*;
@@ -36,10 +44,20 @@ pub enum Axis {
 pub enum Command {
     // Brush
     Brush,
+    BrushCapture,
+    BrushLoad(String),
+    BrushSave(String),
     BrushSet(BrushMode),
     BrushToggle(BrushMode),
     BrushSize(Op),
     BrushUnset(BrushMode),
+    BrushSymmetry(Option<(f32, f32)>),
+    CurveFinish,
+    CurveCancel,
+    PolygonFinish,
+    PolygonCancel,
+    ColorEdit(Option<Target>),
+    ColorChange(Channel, Change),
 
     #[allow(dead_code)]
     Crop(Rect<u32>),
@@ -50,6 +68,8 @@ pub enum Command {
     Edit(Vec<String>),
     EditFrames(Vec<String>),
     Export(Option<u32>, String),
+    ExportCycle(Option<u32>, String),
+    ExportVariants(Option<u32>, String, Vec<String>),
     Write(Option<String>),
     WriteFrames(Option<String>),
     WriteQuit,
@@ -68,22 +88,39 @@ pub enum Command {
     FrameResize(u32, u32),
 
     // Palette
-    PaletteAdd(Rgba8),
+    PaletteAdd(Color),
     PaletteClear,
-    PaletteGradient(Rgba8, Rgba8, usize),
+    PaletteCycleAdd(usize, usize, u64),
+    PaletteCycleClear,
+    PaletteGradient(Color, Color, usize),
+    PaletteInsert(Option<usize>, Color),
+    PaletteList,
+    PaletteMove(Option<usize>, usize),
+    PaletteNew(String),
+    PaletteQuantize(usize),
+    PaletteRamp(Color, usize),
+    PaletteRampAdd(usize, usize),
+    PaletteRampClear,
+    PaletteRemap(Metric, Dithering),
+    PaletteRemove(Option<usize>),
     PaletteSample,
+    PaletteStats,
+    PaletteSet(Option<usize>, Color, bool),
     PaletteSort,
+    PaletteSwap(Option<usize>, usize),
+    PaletteUse(String),
+    PaletteVariant(Option<String>),
     PaletteWrite(String),
 
     // Navigation
     Pan(i32, i32),
     Zoom(Op),
 
-    PaintColor(Rgba8, i32, i32),
+    PaintColor(Color, i32, i32),
     PaintForeground(i32, i32),
     PaintBackground(i32, i32),
     PaintPalette(usize, i32, i32),
-    PaintLine(Rgba8, i32, i32, i32, i32),
+    PaintLine(Color, i32, i32, i32, i32),
 
     // Selection
     SelectionMove(i32, i32),
@@ -93,10 +130,24 @@ pub enum Command {
     SelectionPaste,
     SelectionYank,
     SelectionCut,
-    SelectionFill(Option<Rgba8>),
+    SelectionFill(Option<Color>),
     SelectionErase,
+    SelectionMask,
+    SelectionColor(Option<Color>, MaskOp),
     SelectionJump(Direction),
     SelectionFlip(Axis),
+    SelectionGradient(Axis),
+    SelectionRotate(f32),
+    SelectionScale(f32, f32),
+
+    // Effects
+    Outline(Placement, Connectivity),
+    Shadow(i32, i32, Option<Color>),
+    RemoveOrphans,
+
+    // Text
+    Text(String, Option<(i32, i32)>),
+    TextFont(Option<String>, Option<(u32, u32)>),
 
     // Settings
     Set(String, Value),
@@ -106,7 +157,9 @@ pub enum Command {
     MapClear,
 
     Slice(Option<usize>),
-    Fill(Option<Rgba8>),
+    Fill(Option<Color>),
+    Replace(Color, Color, u8),
+    ReplaceAll(Color, Color, u8),
 
     SwapColors,
 
@@ -120,6 +173,9 @@ pub enum Command {
     // View
     ViewCenter,
     ViewNext,
+    ViewPalette(Option<String>),
+    ViewPaletteClear,
+    ViewIndexed(bool),
     ViewPrev,
 
     Noop,
@@ -142,13 +198,39 @@ impl Command {
                 | Self::SelectionOffset(_, _)
         )
     }
+
+    /// Return the color arguments of the command, so that references can be
+    /// resolved before it runs.
+    pub fn colors_mut(&mut self) -> Vec<&mut Color> {
+        match self {
+            Self::PaletteAdd(c)
+            | Self::PaletteInsert(_, c)
+            | Self::PaletteRamp(c, _)
+            | Self::PaletteSet(_, c, _)
+            | Self::PaintColor(c, _, _)
+            | Self::PaintLine(c, _, _, _, _) => vec![c],
+            Self::PaletteGradient(a, b, _) | Self::Replace(a, b, _) | Self::ReplaceAll(a, b, _) => {
+                vec![a, b]
+            }
+            Self::SelectionFill(c)
+            | Self::SelectionColor(c, _)
+            | Self::Shadow(_, _, c)
+            | Self::Fill(c) => c.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
 }
 
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Brush => write!(f, "Reset brush"),
+            Self::BrushCapture => write!(f, "Capture brush from selection"),
+            Self::BrushLoad(_) => write!(f, "Load brush from a file"),
+            Self::BrushSave(_) => write!(f, "Save brush to a file"),
             Self::BrushSet(m) => write!(f, "Set brush mode to `{}`", m),
+            Self::BrushSymmetry(Some((x, y))) => write!(f, "Set symmetry center to {},{}", x, y),
+            Self::BrushSymmetry(None) => write!(f, "Reset symmetry center to frame center"),
             Self::BrushToggle(m) => write!(f, "Toggle `{}` brush mode", m),
             Self::BrushSize(Op::Incr) => write!(f, "Increase brush size"),
             Self::BrushSize(Op::Decr) => write!(f, "Decrease brush size"),
@@ -175,6 +257,12 @@ impl fmt::Display for Command {
             Self::Noop => write!(f, "No-op"),
             Self::PaletteAdd(c) => write!(f, "Add {color} to palette", color = c),
             Self::PaletteClear => write!(f, "Clear palette"),
+            Self::PaletteCycleAdd(from, to, delay) => write!(
+                f,
+                "Cycle palette colors {} to {} every {}ms",
+                from, to, delay
+            ),
+            Self::PaletteCycleClear => write!(f, "Clear color cycles"),
             Self::PaletteGradient(cs, ce, n) => write!(
                 f,
                 "Create {} colors gradient from {} to {}",
@@ -182,8 +270,27 @@ impl fmt::Display for Command {
                 colorstart = cs,
                 colorend = ce
             ),
+            Self::PaletteInsert(_, c) => write!(f, "Insert {} into palette", c),
+            Self::PaletteList => write!(f, "List palettes"),
+            Self::PaletteMove(_, to) => write!(f, "Move palette color to index {}", to),
+            Self::PaletteNew(name) => write!(f, "Create palette `{}`", name),
+            Self::PaletteQuantize(n) => write!(f, "Quantize view to {} palette colors", n),
+            Self::PaletteRamp(base, n) => write!(f, "Add {} colors ramp through {}", n, base),
+            Self::PaletteRampAdd(from, to) => {
+                write!(f, "Add shading ramp from palette index {} to {}", from, to)
+            }
+            Self::PaletteRampClear => write!(f, "Clear shading ramps"),
+            Self::PaletteRemap(..) => write!(f, "Remap view to palette colors"),
+            Self::PaletteRemove(_) => write!(f, "Remove palette color"),
             Self::PaletteSample => write!(f, "Sample palette from view"),
+            Self::PaletteStats => write!(f, "Show palette color usage"),
+            Self::PaletteSet(_, c, false) => write!(f, "Set palette color to {}", c),
+            Self::PaletteSet(_, c, true) => write!(f, "Set palette color and pixels to {}", c),
             Self::PaletteSort => write!(f, "Sort palette colors"),
+            Self::PaletteSwap(_, b) => write!(f, "Swap palette color with index {}", b),
+            Self::PaletteUse(name) => write!(f, "Use palette `{}`", name),
+            Self::PaletteVariant(Some(name)) => write!(f, "Preview palette variant `{}`", name),
+            Self::PaletteVariant(None) => write!(f, "Stop previewing palette variant"),
             Self::Pan(x, 0) if *x > 0 => write!(f, "Pan workspace right"),
             Self::Pan(x, 0) if *x < 0 => write!(f, "Pan workspace left"),
             Self::Pan(0, y) if *y > 0 => write!(f, "Pan workspace up"),
@@ -192,11 +299,34 @@ impl fmt::Display for Command {
             Self::Quit => write!(f, "Quit active view"),
             Self::QuitAll => write!(f, "Quit all views"),
             Self::Redo => write!(f, "Redo view edit"),
+            Self::Replace(from, to, _) => write!(f, "Replace {} with {}", from, to),
+            Self::ReplaceAll(from, to, _) => {
+                write!(f, "Replace {} with {} in all views", from, to)
+            }
             Self::FrameResize(_, _) => write!(f, "Resize active view frame"),
             Self::Tool(Tool::Pan(_)) => write!(f, "Pan tool"),
             Self::Tool(Tool::Brush) => write!(f, "Brush tool"),
             Self::Tool(Tool::Sampler) => write!(f, "Color sampler tool"),
             Self::Tool(Tool::FloodFill) => write!(f, "Flood fill tool"),
+            Self::Tool(Tool::Shape(fig)) => write!(f, "Shape tool ({})", fig),
+            Self::Tool(Tool::Gradient(_)) => write!(f, "Gradient tool"),
+            Self::Tool(Tool::Lasso(_)) => write!(f, "Lasso tool"),
+            Self::Tool(Tool::Wand) => write!(f, "Magic wand tool"),
+            Self::Tool(Tool::Text(_)) => write!(f, "Text tool"),
+            Self::Tool(Tool::Curve(_)) => write!(f, "Curve tool"),
+            Self::CurveFinish => write!(f, "Paint the curve being drawn"),
+            Self::CurveCancel => write!(f, "Discard the curve being drawn"),
+            Self::Tool(Tool::Polygon(PolygonState { filled: false, .. })) => {
+                write!(f, "Polygon tool")
+            }
+            Self::Tool(Tool::Polygon(PolygonState { filled: true, .. })) => {
+                write!(f, "Filled polygon tool")
+            }
+            Self::PolygonFinish => write!(f, "Paint the polygon being drawn"),
+            Self::PolygonCancel => write!(f, "Discard the polygon being drawn"),
+            Self::ColorEdit(None) => write!(f, "Toggle color editor"),
+            Self::ColorEdit(Some(t)) => write!(f, "Edit color {}", t),
+            Self::ColorChange(ch, c) => write!(f, "Change color channel `{}` by {}", ch, c),
             Self::ToolPrev => write!(f, "Switch to previous tool"),
             Self::Set(s, v) => write!(f, "Set {setting} to {val}", setting = s, val = v),
             Self::Slice(Some(n)) => write!(f, "Slice view into {} frame(s)", n),
@@ -207,6 +337,11 @@ impl fmt::Display for Command {
             Self::Undo => write!(f, "Undo view edit"),
             Self::ViewCenter => write!(f, "Center active view"),
             Self::ViewNext => write!(f, "Go to next view"),
+            Self::ViewPalette(Some(name)) => write!(f, "Use palette `{}` with active view", name),
+            Self::ViewPalette(None) => write!(f, "Use current palette with active view"),
+            Self::ViewPaletteClear => write!(f, "Stop using a palette with active view"),
+            Self::ViewIndexed(true) => write!(f, "Store active view pixels as palette indices"),
+            Self::ViewIndexed(false) => write!(f, "Store active view pixels as RGBA colors"),
             Self::ViewPrev => write!(f, "Go to previous view"),
             Self::Write(None) => write!(f, "Write view to disk"),
             Self::Write(Some(_)) => write!(f, "Write view to disk as..."),
@@ -234,8 +369,26 @@ impl fmt::Display for Command {
                 write!(f, "Move selection backward by one frame")
             }
             Self::SelectionErase => write!(f, "Erase selection contents"),
+            Self::SelectionMask => write!(f, "Toggle selection mask"),
+            Self::SelectionColor(None, _) => write!(f, "Select pixels of the foreground color"),
+            Self::SelectionColor(Some(c), _) => write!(f, "Select pixels of color {}", c),
             Self::SelectionFlip(Axis::Horizontal) => write!(f, "Flip selection horizontally"),
             Self::SelectionFlip(Axis::Vertical) => write!(f, "Flip selection vertically"),
+            Self::SelectionGradient(Axis::Horizontal) => {
+                write!(f, "Fill selection with a horizontal gradient")
+            }
+            Self::SelectionGradient(Axis::Vertical) => {
+                write!(f, "Fill selection with a vertical gradient")
+            }
+            Self::SelectionRotate(angle) => write!(f, "Rotate selection by {}°", angle),
+            Self::SelectionScale(x, y) => write!(f, "Scale selection by {}x{}", x, y),
+            Self::Outline(Placement::Outside, _) => write!(f, "Outline opaque pixels"),
+            Self::Outline(Placement::Inside, _) => write!(f, "Outline edge of opaque pixels"),
+            Self::Shadow(x, y, _) => write!(f, "Cast drop shadow at {},{}", x, y),
+            Self::RemoveOrphans => write!(f, "Remove orphan pixels"),
+            Self::Text(_, _) => write!(f, "Paint text"),
+            Self::TextFont(None, _) => write!(f, "Reset text font"),
+            Self::TextFont(Some(_), _) => write!(f, "Load text font from a file"),
             Self::PaintColor(_, x, y) => write!(f, "Paint {:2},{:2}", x, y),
             _ => write!(f, "..."),
         }
@@ -245,8 +398,20 @@ impl fmt::Display for Command {
 impl From<Command> for String {
     fn from(cmd: Command) -> Self {
         match cmd {
-            Command::Brush => format!("brush"),
+            Command::Brush => format!("brush/reset"),
+            Command::BrushCapture => format!("brush/capture"),
+            Command::BrushLoad(path) => format!("brush/load {}", path),
+            Command::BrushSave(path) => format!("brush/save {}", path),
             Command::BrushSet(m) => format!("brush/set {}", m),
+            Command::BrushSymmetry(Some((x, y))) => format!("brush/symmetry {} {}", x, y),
+            Command::BrushSymmetry(None) => format!("brush/symmetry"),
+            Command::CurveFinish => format!("curve/finish"),
+            Command::CurveCancel => format!("curve/cancel"),
+            Command::PolygonFinish => format!("polygon/finish"),
+            Command::PolygonCancel => format!("polygon/cancel"),
+            Command::ColorEdit(None) => format!("color/edit"),
+            Command::ColorEdit(Some(t)) => format!("color/edit {}", t),
+            Command::ColorChange(ch, c) => format!("color/{} {}", ch, c),
             Command::BrushSize(Op::Incr) => format!("brush/size +"),
             Command::BrushSize(Op::Decr) => format!("brush/size -"),
             Command::BrushSize(Op::Set(s)) => format!("brush/size {}", s),
@@ -255,6 +420,8 @@ impl From<Command> for String {
             Command::Edit(_) => unimplemented!(),
             Command::Fill(Some(c)) => format!("v/fill {}", c),
             Command::Fill(None) => format!("v/fill"),
+            Command::Replace(from, to, t) => format!("replace {} {} {}", from, to, t),
+            Command::ReplaceAll(from, to, t) => format!("replace/all {} {} {}", from, to, t),
             Command::ForceQuit => format!("q!"),
             Command::ForceQuitAll => format!("qa!"),
             Command::Map(_) => format!("map <key> <command> {{<command>}}"),
@@ -264,12 +431,50 @@ impl From<Command> for String {
             Command::FrameRemove => format!("f/remove"),
             Command::Export(None, path) => format!("export {}", path),
             Command::Export(Some(s), path) => format!("export @{}x {}", s, path),
+            Command::ExportCycle(None, path) => format!("export/cycle {}", path),
+            Command::ExportCycle(Some(s), path) => format!("export/cycle @{}x {}", s, path),
+            Command::ExportVariants(None, path, targets) => {
+                format!("export/variants {} {}", path, targets.join(" "))
+            }
+            Command::ExportVariants(Some(s), path, targets) => {
+                format!("export/variants @{}x {} {}", s, path, targets.join(" "))
+            }
             Command::Noop => format!(""),
             Command::PaletteAdd(c) => format!("p/add {}", c),
             Command::PaletteClear => format!("p/clear"),
             Command::PaletteWrite(_) => format!("p/write"),
             Command::PaletteSample => format!("p/sample"),
+            Command::PaletteStats => format!("p/stats"),
             Command::PaletteGradient(cs, ce, n) => format!("p/gradient {} {} {}", cs, ce, n),
+            Command::PaletteRamp(base, n) => format!("p/ramp {} {}", base, n),
+            Command::PaletteRampAdd(from, to) => format!("p/ramp/add {} {}", from, to),
+            Command::PaletteRampClear => format!("p/ramp/clear"),
+            Command::PaletteCycleAdd(from, to, delay) => {
+                format!("p/cycle/add {} {} {}", from, to, delay)
+            }
+            Command::PaletteCycleClear => format!("p/cycle/clear"),
+            Command::PaletteRemap(metric, dithering) => format!("p/remap {} {}", metric, dithering),
+            Command::PaletteQuantize(n) => format!("p/quantize {}", n),
+            Command::PaletteInsert(None, c) => format!("p/insert {}", c),
+            Command::PaletteInsert(Some(i), c) => format!("p/insert {} {}", i, c),
+            Command::PaletteRemove(None) => format!("p/remove"),
+            Command::PaletteRemove(Some(i)) => format!("p/remove {}", i),
+            Command::PaletteMove(None, to) => format!("p/move {}", to),
+            Command::PaletteMove(Some(from), to) => format!("p/move {} {}", from, to),
+            Command::PaletteSwap(None, b) => format!("p/swap {}", b),
+            Command::PaletteSwap(Some(a), b) => format!("p/swap {} {}", a, b),
+            Command::PaletteUse(name) => format!("p/use {}", name),
+            Command::PaletteVariant(Some(name)) => format!("p/variant {}", name),
+            Command::PaletteVariant(None) => format!("p/variant"),
+            Command::PaletteNew(name) => format!("p/new {}", name),
+            Command::PaletteList => format!("p/list"),
+            Command::PaletteSet(i, c, recolor) => {
+                let name = if recolor { "p/set!" } else { "p/set" };
+                match i {
+                    Some(i) => format!("{} {} {}", name, i, c),
+                    None => format!("{} {}", name, c),
+                }
+            }
             Command::Pan(x, y) => format!("pan {} {}", x, y),
             Command::Quit => format!("q"),
             Command::Redo => format!("redo"),
@@ -283,10 +488,28 @@ impl From<Command> for String {
             Command::Undo => format!("undo"),
             Command::ViewCenter => format!("v/center"),
             Command::ViewNext => format!("v/next"),
+            Command::ViewPalette(Some(name)) => format!("v/palette {}", name),
+            Command::ViewPalette(None) => format!("v/palette"),
+            Command::ViewPaletteClear => format!("v/palette/clear"),
+            Command::ViewIndexed(true) => format!("v/indexed"),
+            Command::ViewIndexed(false) => format!("v/rgba"),
             Command::ViewPrev => format!("v/prev"),
             Command::Write(None) => format!("w"),
             Command::Write(Some(path)) => format!("w {}", path),
             Command::WriteQuit => format!("wq"),
+            Command::Outline(placement, connectivity) => {
+                format!("fx/outline {} {}", placement, connectivity)
+            }
+            Command::Shadow(x, y, None) => format!("fx/shadow {} {}", x, y),
+            Command::Shadow(x, y, Some(c)) => format!("fx/shadow {} {} {}", x, y, c),
+            Command::RemoveOrphans => format!("fx/orphans"),
+            Command::Text(text, None) => format!("text \"{}\"", text),
+            Command::Text(text, Some((x, y))) => format!("text \"{}\" {} {}", text, x, y),
+            Command::TextFont(None, _) => format!("text/font"),
+            Command::TextFont(Some(path), None) => format!("text/font {}", path),
+            Command::TextFont(Some(path), Some((w, h))) => {
+                format!("text/font {} {} {}", path, w, h)
+            }
             Command::Zoom(Op::Incr) => format!("v/zoom +"),
             Command::Zoom(Op::Decr) => format!("v/zoom -"),
             Command::Zoom(Op::Set(z)) => format!("v/zoom {}", z),
@@ -369,6 +592,9 @@ pub enum Value {
     Str(String),
     Ident(String),
     Rgba8(Rgba8),
+    /// A reference to the foreground, background or a palette color,
+    /// resolved before the value is used.
+    ColorRef(Target),
 }
 
 impl Value {
@@ -408,7 +634,7 @@ impl Value {
             Self::U32Tuple(_, _) => "two positive integers, eg. 32, 48",
             Self::F32Tuple(_, _) => "two floats , eg. 32.17, 48.29",
             Self::Str(_) => "string, eg. \"fnord\"",
-            Self::Rgba8(_) => "color, eg. #ffff00",
+            Self::Rgba8(_) | Self::ColorRef(_) => "color, eg. #ffff00",
             Self::Ident(_) => "identifier, eg. fnord",
         }
     }
@@ -452,6 +678,7 @@ impl fmt::Display for Value {
             Value::F32Tuple(x, y) => write!(f, "{},{}", x, y),
             Value::Str(s) => s.fmt(f),
             Value::Rgba8(c) => c.fmt(f),
+            Value::ColorRef(t) => Color::Ref(*t).fmt(f),
             Value::Ident(i) => i.fmt(f),
         }
     }
@@ -460,7 +687,10 @@ impl fmt::Display for Value {
 impl Parse for Value {
     fn parser() -> Parser<Self> {
         let str_val = quoted().map(Value::Str).label("<string>");
-        let rgba8_val = color().map(Value::Rgba8);
+        let rgba8_val = color().map(|c| match c {
+            Color::Rgba(rgba) => Value::Rgba8(rgba),
+            Color::Ref(target) => Value::ColorRef(target),
+        });
         let u32_tuple_val = tuple::<u32>(natural(), natural()).map(|(x, y)| Value::U32Tuple(x, y));
         let u32_val = natural::<u32>().map(Value::U32);
         let f64_tuple_val =
@@ -679,7 +909,19 @@ impl Commands {
             commands: vec![(
                 "#",
                 "Add color to palette",
-                color().map(Command::PaletteAdd),
+                // Only hex colors can be used bare, other notations could be
+                // mistaken for commands.
+                Parser::new(
+                    |input| {
+                        if input.starts_with('#') {
+                            color().parse(input)
+                        } else {
+                            Err(("expected color".into(), input))
+                        }
+                    },
+                    "<color>",
+                )
+                .map(Command::PaletteAdd),
             )],
         }
     }
@@ -743,6 +985,26 @@ impl Default for Commands {
                 p.then(optional(scale().skip(whitespace())).then(path()))
                     .map(|(_, (scale, path))| Command::Export(scale, path))
             })
+            .command(
+                "export/cycle",
+                "Export the view as a gif of the palette's color cycles",
+                |p| {
+                    p.then(optional(scale().skip(whitespace())).then(path()))
+                        .map(|(_, (scale, path))| Command::ExportCycle(scale, path))
+                },
+            )
+            .command(
+                "export/variants",
+                "Export the view with the palette swapped for each given palette",
+                |p| {
+                    p.then(optional(scale().skip(whitespace())))
+                        .then(path().skip(whitespace()))
+                        .then(paths().label("<palette>.."))
+                        .map(|(((_, scale), path), targets)| {
+                            Command::ExportVariants(scale, path, targets)
+                        })
+                },
+            )
             .command("wq", "Write & quit view", |p| p.value(Command::WriteQuit))
             .command("x", "Write & quit view", |p| p.value(Command::WriteQuit))
             .command("w", "Write view", |p| {
@@ -842,12 +1104,136 @@ impl Default for Commands {
                 p.then(param::<BrushMode>())
                     .map(|(_, m)| Command::BrushToggle(m))
             })
+            .command(
+                "brush/symmetry",
+                "Set the symmetry center within frames, or reset it to the frame center",
+                |p| {
+                    p.then(optional(tuple::<f32>(
+                        rational().label("<x>"),
+                        rational().label("<y>"),
+                    )))
+                    .map(|(_, center)| Command::BrushSymmetry(center))
+                },
+            )
+            .command("brush/capture", "Capture brush from selection", |p| {
+                p.value(Command::BrushCapture)
+            })
+            .command("brush/load", "Load brush from a PNG file", |p| {
+                p.then(path()).map(|(_, path)| Command::BrushLoad(path))
+            })
+            .command("brush/save", "Save brush to a PNG file", |p| {
+                p.then(path()).map(|(_, path)| Command::BrushSave(path))
+            })
+            .command("brush/reset", "Reset brush to a square", |p| {
+                p.value(Command::Brush)
+            })
             .command("brush", "Switch to brush", |p| {
                 p.value(Command::Tool(Tool::Brush))
             })
             .command("flood", "Switch to flood fill tool", |p| {
                 p.value(Command::Tool(Tool::FloodFill))
             })
+            .command("rect", "Switch to rectangle tool", |p| {
+                p.value(Command::Tool(Tool::Shape(Figure::Rectangle)))
+            })
+            .command("rect/fill", "Switch to filled rectangle tool", |p| {
+                p.value(Command::Tool(Tool::Shape(Figure::FilledRectangle)))
+            })
+            .command("ellipse", "Switch to ellipse tool", |p| {
+                p.value(Command::Tool(Tool::Shape(Figure::Ellipse)))
+            })
+            .command("ellipse/fill", "Switch to filled ellipse tool", |p| {
+                p.value(Command::Tool(Tool::Shape(Figure::FilledEllipse)))
+            })
+            .command("gradient", "Switch to gradient tool", |p| {
+                p.value(Command::Tool(Tool::Gradient(None)))
+            })
+            .command("lasso", "Switch to lasso selection tool", |p| {
+                p.value(Command::Tool(Tool::Lasso(LassoState::default())))
+            })
+            .command("wand", "Switch to magic wand selection tool", |p| {
+                p.value(Command::Tool(Tool::Wand))
+            })
+            .command("curve", "Switch to curve tool", |p| {
+                p.value(Command::Tool(Tool::Curve(CurveState::default())))
+            })
+            .command("curve/finish", "Paint the curve being drawn", |p| {
+                p.value(Command::CurveFinish)
+            })
+            .command("curve/cancel", "Discard the curve being drawn", |p| {
+                p.value(Command::CurveCancel)
+            })
+            .command("polygon", "Switch to polygon tool", |p| {
+                p.value(Command::Tool(Tool::Polygon(PolygonState::default())))
+            })
+            .command("polygon/fill", "Switch to filled polygon tool", |p| {
+                p.value(Command::Tool(Tool::Polygon(PolygonState {
+                    filled: true,
+                    ..PolygonState::default()
+                })))
+            })
+            .command("polygon/finish", "Paint the polygon being drawn", |p| {
+                p.value(Command::PolygonFinish)
+            })
+            .command("polygon/cancel", "Discard the polygon being drawn", |p| {
+                p.value(Command::PolygonCancel)
+            })
+            .command(
+                "color/edit",
+                "Toggle the color editor, or edit the fg, bg or a palette color",
+                |p| {
+                    p.then(optional(param::<Target>()))
+                        .map(|(_, t)| Command::ColorEdit(t))
+                },
+            )
+            .command("color/h", "Change the hue of the edited color", |p| {
+                p.then(param::<Change>())
+                    .map(|(_, c)| Command::ColorChange(Channel::Hue, c))
+            })
+            .command(
+                "color/s",
+                "Change the saturation of the edited color",
+                |p| {
+                    p.then(param::<Change>())
+                        .map(|(_, c)| Command::ColorChange(Channel::Saturation, c))
+                },
+            )
+            .command("color/v", "Change the value of the edited color", |p| {
+                p.then(param::<Change>())
+                    .map(|(_, c)| Command::ColorChange(Channel::Value, c))
+            })
+            .command(
+                "color/r",
+                "Change the red channel of the edited color",
+                |p| {
+                    p.then(param::<Change>())
+                        .map(|(_, c)| Command::ColorChange(Channel::Red, c))
+                },
+            )
+            .command(
+                "color/g",
+                "Change the green channel of the edited color",
+                |p| {
+                    p.then(param::<Change>())
+                        .map(|(_, c)| Command::ColorChange(Channel::Green, c))
+                },
+            )
+            .command(
+                "color/b",
+                "Change the blue channel of the edited color",
+                |p| {
+                    p.then(param::<Change>())
+                        .map(|(_, c)| Command::ColorChange(Channel::Blue, c))
+                },
+            )
+            .command(
+                "color/a",
+                "Change the alpha channel of the edited color",
+                |p| {
+                    p.then(param::<Change>())
+                        .map(|(_, c)| Command::ColorChange(Channel::Alpha, c))
+                },
+            )
             .command("mode", "Set session mode, eg. `visual` or `normal`", |p| {
                 p.then(param::<Mode>()).map(|(_, m)| Command::Mode(m))
             })
@@ -866,15 +1252,66 @@ impl Default for Commands {
             .command("v/prev", "Activate the previous view", |p| {
                 p.value(Command::ViewPrev)
             })
+            .command(
+                "v/palette",
+                "Use a palette, the current one by default, with the active view",
+                |p| {
+                    p.then(optional(token().label("<name>")))
+                        .map(|(_, name)| Command::ViewPalette(name))
+                },
+            )
+            .command(
+                "v/palette/clear",
+                "Stop using a palette with the active view",
+                |p| p.value(Command::ViewPaletteClear),
+            )
+            .command(
+                "v/indexed",
+                "Store the active view pixels as indices into the palette",
+                |p| p.value(Command::ViewIndexed(true)),
+            )
+            .command(
+                "v/rgba",
+                "Store the active view pixels as RGBA colors",
+                |p| p.value(Command::ViewIndexed(false)),
+            )
             .command("v/center", "Center the active view", |p| {
                 p.value(Command::ViewCenter)
             })
             .command("v/clear", "Clear the active view", |p| {
-                p.value(Command::Fill(Some(Rgba8::TRANSPARENT)))
+                p.value(Command::Fill(Some(Color::Rgba(Rgba8::TRANSPARENT))))
             })
             .command("v/fill", "Fill the active view", |p| {
                 p.then(optional(color())).map(|(_, c)| Command::Fill(c))
             })
+            .command(
+                "replace",
+                "Replace a color in the active view or selection",
+                |p| {
+                    p.then(tuple::<Color>(
+                        color().label("<from>"),
+                        color().label("<to>"),
+                    ))
+                    .then(optional(
+                        whitespace()
+                            .then(natural::<u8>().label("<tolerance>"))
+                            .map(|(_, t)| t),
+                    ))
+                    .map(|((_, (from, to)), t)| Command::Replace(from, to, t.unwrap_or(0)))
+                },
+            )
+            .command("replace/all", "Replace a color in all views", |p| {
+                p.then(tuple::<Color>(
+                    color().label("<from>"),
+                    color().label("<to>"),
+                ))
+                .then(optional(
+                    whitespace()
+                        .then(natural::<u8>().label("<tolerance>"))
+                        .map(|(_, t)| t),
+                ))
+                .map(|((_, (from, to)), t)| Command::ReplaceAll(from, to, t.unwrap_or(0)))
+            })
             .command("pan", "Switch to the pan tool", |p| {
                 p.then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
                     .map(|(_, (x, y))| Command::Pan(x, y))
@@ -911,8 +1348,84 @@ impl Default for Commands {
             .command("p/clear", "Clear the color palette", |p| {
                 p.value(Command::PaletteClear)
             })
+            .command("p/use", "Switch to a named palette", |p| {
+                p.then(token().label("<name>"))
+                    .map(|(_, name)| Command::PaletteUse(name))
+            })
+            .command("p/new", "Create an empty named palette and use it", |p| {
+                p.then(token().label("<name>"))
+                    .map(|(_, name)| Command::PaletteNew(name))
+            })
+            .command(
+                "p/variant",
+                "Preview the view with the palette swapped for another palette",
+                |p| {
+                    p.then(optional(token().label("<palette>")))
+                        .map(|(_, name)| Command::PaletteVariant(name))
+                },
+            )
+            .command("p/list", "List the named palettes", |p| {
+                p.value(Command::PaletteList)
+            })
+            .command(
+                "p/insert",
+                "Insert a color into the palette, before the hovered one by default",
+                |p| {
+                    p.then(optional(
+                        natural::<usize>().label("<index>").skip(whitespace()),
+                    ))
+                    .then(color())
+                    .map(|((_, i), c)| Command::PaletteInsert(i, c))
+                },
+            )
+            .command(
+                "p/remove",
+                "Remove a palette color, the hovered one by default",
+                |p| {
+                    p.then(optional(natural::<usize>().label("<index>")))
+                        .map(|(_, i)| Command::PaletteRemove(i))
+                },
+            )
+            .command(
+                "p/move",
+                "Move a palette color, the hovered one by default, to an index",
+                |p| {
+                    p.then(palette_indices())
+                        .map(|(_, (from, to))| Command::PaletteMove(from, to))
+                },
+            )
+            .command(
+                "p/swap",
+                "Swap two palette colors, the first is the hovered one by default",
+                |p| {
+                    p.then(palette_indices())
+                        .map(|(_, (a, b))| Command::PaletteSwap(a, b))
+                },
+            )
+            .command(
+                "p/set",
+                "Set a palette color, the hovered one by default",
+                |p| {
+                    p.then(optional(
+                        natural::<usize>().label("<index>").skip(whitespace()),
+                    ))
+                    .then(color())
+                    .map(|((_, i), c)| Command::PaletteSet(i, c, false))
+                },
+            )
+            .command(
+                "p/set!",
+                "Set a palette color, and replace its pixels in all views",
+                |p| {
+                    p.then(optional(
+                        natural::<usize>().label("<index>").skip(whitespace()),
+                    ))
+                    .then(color())
+                    .map(|((_, i), c)| Command::PaletteSet(i, c, true))
+                },
+            )
             .command("p/gradient", "Add a gradient to the palette", |p| {
-                p.then(tuple::<Rgba8>(
+                p.then(tuple::<Color>(
                     color().label("<from>"),
                     color().label("<to>"),
                 ))
@@ -920,11 +1433,70 @@ impl Default for Commands {
                 .then(natural::<usize>().label("<count>"))
                 .map(|((_, (cs, ce)), n)| Command::PaletteGradient(cs, ce, n))
             })
+            .command(
+                "p/quantize",
+                "Replace the palette with colors of the selection or view",
+                |p| {
+                    p.then(natural::<usize>().label("<count>"))
+                        .map(|(_, n)| Command::PaletteQuantize(n))
+                },
+            )
+            .command("p/ramp", "Add a hue-shifted ramp to the palette", |p| {
+                p.then(color().label("<base>"))
+                    .skip(whitespace())
+                    .then(natural::<usize>().label("<count>"))
+                    .map(|((_, base), n)| Command::PaletteRamp(base, n))
+            })
+            .command("p/ramp/add", "Add a shading ramp to the palette", |p| {
+                p.then(tuple::<usize>(
+                    natural().label("<from>"),
+                    natural().label("<to>"),
+                ))
+                .map(|(_, (from, to))| Command::PaletteRampAdd(from, to))
+            })
+            .command("p/ramp/clear", "Clear the palette's shading ramps", |p| {
+                p.value(Command::PaletteRampClear)
+            })
+            .command("p/cycle/add", "Add a color cycle to the palette", |p| {
+                p.then(tuple::<usize>(
+                    natural().label("<from>"),
+                    natural().label("<to>"),
+                ))
+                .skip(whitespace())
+                .then(natural::<u64>().label("<delay>"))
+                .map(|((_, (from, to)), delay)| Command::PaletteCycleAdd(from, to, delay))
+            })
+            .command("p/cycle/clear", "Clear the palette's color cycles", |p| {
+                p.value(Command::PaletteCycleClear)
+            })
+            .command(
+                "p/remap",
+                "Map the selection or view to the palette colors",
+                |p| {
+                    p.then(optional(param::<Metric>()))
+                        .then(optional(
+                            optional(whitespace())
+                                .then(param::<Dithering>())
+                                .map(|(_, d)| d),
+                        ))
+                        .map(|((_, metric), dithering)| {
+                            Command::PaletteRemap(
+                                metric.unwrap_or(Metric::Rgb),
+                                dithering.unwrap_or(Dithering::None),
+                            )
+                        })
+                },
+            )
             .command(
                 "p/sample",
                 "Sample palette colors from the active view",
                 |p| p.value(Command::PaletteSample),
             )
+            .command(
+                "p/stats",
+                "Show how many pixels of the view or selection use each palette color",
+                |p| p.value(Command::PaletteStats),
+            )
             .command("p/sort", "Sort the palette colors", |p| {
                 p.value(Command::PaletteSort)
             })
@@ -964,6 +1536,13 @@ impl Default for Commands {
                         "pan" => Ok(Command::Tool(Tool::Pan(PanState::default()))),
                         "brush" => Ok(Command::Tool(Tool::Brush)),
                         "sampler" => Ok(Command::Tool(Tool::Sampler)),
+                        "rect" => Ok(Command::Tool(Tool::Shape(Figure::Rectangle))),
+                        "ellipse" => Ok(Command::Tool(Tool::Shape(Figure::Ellipse))),
+                        "gradient" => Ok(Command::Tool(Tool::Gradient(None))),
+                        "lasso" => Ok(Command::Tool(Tool::Lasso(LassoState::default()))),
+                        "wand" => Ok(Command::Tool(Tool::Wand)),
+                        "curve" => Ok(Command::Tool(Tool::Curve(CurveState::default()))),
+                        "polygon" => Ok(Command::Tool(Tool::Polygon(PolygonState::default()))),
                         _ => Err(format!("unknown tool {:?}", t)),
                     })
             })
@@ -999,6 +1578,20 @@ impl Default for Commands {
             .command("selection/erase", "Erase selection contents", |p| {
                 p.value(Command::SelectionErase)
             })
+            .command("selection/color", "Select all pixels of a color", |p| {
+                p.then(optional(color()))
+                    .then(optional(
+                        optional(whitespace())
+                            .then(param::<MaskOp>())
+                            .map(|(_, op)| op),
+                    ))
+                    .map(|((_, rgba), op)| Command::SelectionColor(rgba, op.unwrap_or_default()))
+            })
+            .command(
+                "selection/mask",
+                "Toggle clipping paint operations to the selection",
+                |p| p.value(Command::SelectionMask),
+            )
             .command("selection/offset", "Offset selection bounds", |p| {
                 p.then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
                     .map(|(_, (x, y))| Command::SelectionOffset(x, y))
@@ -1019,6 +1612,110 @@ impl Default for Commands {
                         _ => Err(format!("unknown axis {:?}, must be 'x' or 'y'", t)),
                     })
             })
+            .command(
+                "selection/gradient",
+                "Fill selection with a gradient",
+                |p| {
+                    p.then(word().label("x/y"))
+                        .try_map(|(_, t)| match t.as_str() {
+                            "x" => Ok(Command::SelectionGradient(Axis::Horizontal)),
+                            "y" => Ok(Command::SelectionGradient(Axis::Vertical)),
+                            _ => Err(format!("unknown axis {:?}, must be 'x' or 'y'", t)),
+                        })
+                },
+            )
+            .command(
+                "selection/rotate",
+                "Rotate selection clockwise by an angle in degrees",
+                |p| {
+                    p.then(rational::<f32>().label("<degrees>"))
+                        .map(|(_, angle)| Command::SelectionRotate(angle))
+                },
+            )
+            .command(
+                "selection/scale",
+                "Scale selection by a factor, optionally different vertically",
+                |p| {
+                    p.then(rational::<f32>().label("<factor>"))
+                        .then(optional(
+                            whitespace().then(rational::<f32>().label("<factor>")),
+                        ))
+                        .try_map(|((_, x), y)| {
+                            let y = y.map_or(x, |(_, y)| y);
+
+                            if x > 0. && y > 0. {
+                                Ok(Command::SelectionScale(x, y))
+                            } else {
+                                Err("scale factor must be > 0.0")
+                            }
+                        })
+                },
+            )
+            .command(
+                "fx/outline",
+                "Outline opaque pixels of the selection or frames",
+                |p| {
+                    p.then(optional(param::<Placement>()))
+                        .then(optional(
+                            optional(whitespace())
+                                .then(param::<Connectivity>())
+                                .map(|(_, c)| c),
+                        ))
+                        .map(|((_, placement), connectivity)| {
+                            Command::Outline(
+                                placement.unwrap_or_default(),
+                                connectivity.unwrap_or(Connectivity::Four),
+                            )
+                        })
+                },
+            )
+            .command(
+                "fx/shadow",
+                "Cast a drop shadow of the selection or frames",
+                |p| {
+                    p.then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
+                        .then(optional(whitespace().then(color()).map(|(_, c)| c)))
+                        .map(|((_, (x, y)), rgba)| Command::Shadow(x, y, rgba))
+                },
+            )
+            .command(
+                "fx/orphans",
+                "Remove orphan pixels of the selection or frames",
+                |p| p.value(Command::RemoveOrphans),
+            )
+            .command(
+                "text",
+                "Paint text at the given coordinates, or switch to the text tool",
+                |p| {
+                    p.then(quoted().label("<text>"))
+                        .then(optional(
+                            whitespace()
+                                .then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
+                                .map(|(_, pos)| pos),
+                        ))
+                        .map(|((_, text), pos)| Command::Text(text, pos))
+                },
+            )
+            .command(
+                "text/font",
+                "Load a bitmap font (png or bdf), or reset to the built-in font",
+                |p| {
+                    p.then(optional(
+                        path().then(optional(
+                            whitespace()
+                                .then(tuple::<u32>(
+                                    natural().label("<width>"),
+                                    natural().label("<height>"),
+                                ))
+                                .map(|(_, size)| size),
+                        )),
+                    ))
+                    .map(|(_, font)| match font {
+                        Some((path, size)) => Command::TextFont(Some(path), size),
+                        None => Command::TextFont(None, None),
+                    })
+                },
+            )
             .command("paint/color", "Paint color", |p| {
                 p.then(color())
                     .skip(whitespace())
@@ -1417,6 +2114,26 @@ mod test {
         p.parse(":v/fill #ff00ff").unwrap();
     }
 
+    #[test]
+    fn test_export_variants_command() {
+        let p = Commands::default().line_parser();
+
+        assert_eq!(
+            p.parse(":export/variants @2x hero.png red.gpl db16")
+                .unwrap()
+                .0,
+            Command::ExportVariants(
+                Some(2),
+                String::from("hero.png"),
+                vec![String::from("red.gpl"), String::from("db16")]
+            )
+        );
+        assert_eq!(
+            p.parse(":export hero.png").unwrap().0,
+            Command::Export(None, String::from("hero.png"))
+        );
+    }
+
     #[test]
     fn test_unknown_command() {
         let p = Commands::default().line_parser();
@@ -1462,6 +2179,18 @@ mod test {
             p.parse("#ff00ff").unwrap(),
             (Value::Rgba8(Rgba8::new(0xff, 0x0, 0xff, 0xff)), "")
         );
+        assert_eq!(
+            p.parse("rgb(255, 0, 255)").unwrap(),
+            (Value::Rgba8(Rgba8::new(0xff, 0x0, 0xff, 0xff)), "")
+        );
+        assert_eq!(
+            p.parse("p:2").unwrap(),
+            (Value::ColorRef(Target::Palette(2)), "")
+        );
+        assert_eq!(
+            p.parse("normal").unwrap(),
+            (Value::Ident(String::from("normal")), "")
+        );
     }
 
     #[test]
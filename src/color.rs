@@ -1,3 +1,4 @@
+use crate::editor::Target;
 use crate::gfx::Rgba8;
 
 pub const WHITE: Rgba8 = Rgba8::new(0xff, 0xff, 0xff, 0xff);
@@ -11,3 +12,636 @@ pub const YELLOW: Rgba8 = Rgba8::new(0xff, 0xff, 0x66, 0xff);
 pub const LIGHT_GREEN: Rgba8 = Rgba8::new(0xbb, 0xff, 0xee, 0xff);
 pub const GREEN: Rgba8 = Rgba8::new(0x38, 0xb7, 0x55, 0xff);
 pub const BLUE: Rgba8 = Rgba8::new(0x29, 0x36, 0x6f, 0xff);
+
+/// Check whether two colors are within the given tolerance of each other.
+/// The RGB distance is normalized to the `0..=255` range.
+pub fn within_tolerance(a: Rgba8, b: Rgba8, tolerance: u8) -> bool {
+    if tolerance == 0 {
+        return a == b;
+    }
+    let dr = a.r as f32 - b.r as f32;
+    let dg = a.g as f32 - b.g as f32;
+    let db = a.b as f32 - b.b as f32;
+    let da = (a.a as i32 - b.a as i32).abs();
+    let distance = ((dr * dr + dg * dg + db * db) / 3.).sqrt();
+
+    distance <= tolerance as f32 && da <= tolerance as i32
+}
+
+/// How a color is composed with the color it is painted over.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum BlendMode {
+    /// Paint the color over the existing color.
+    Normal,
+    /// Multiply the color with the existing color, which darkens it.
+    Multiply,
+    /// Keep the lighter of the two colors, per channel.
+    Lighten,
+    /// Keep the darker of the two colors, per channel.
+    Darken,
+    /// Paint the color behind the existing color, ie. only where it is
+    /// transparent.
+    Behind,
+}
+
+impl std::str::FromStr for BlendMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(Self::Normal),
+            "multiply" => Ok(Self::Multiply),
+            "lighten" => Ok(Self::Lighten),
+            "darken" => Ok(Self::Darken),
+            "behind" => Ok(Self::Behind),
+            other => Err(format!(
+                "unknown blend mode `{}`, must be one of \
+                 `normal`, `multiply`, `lighten`, `darken` or `behind`",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Normal => "normal".fmt(f),
+            Self::Multiply => "multiply".fmt(f),
+            Self::Lighten => "lighten".fmt(f),
+            Self::Darken => "darken".fmt(f),
+            Self::Behind => "behind".fmt(f),
+        }
+    }
+}
+
+/// Paint color `src` over color `dst` with the given blend mode and opacity,
+/// from `0.0` to `1.0`.
+pub fn blend(dst: Rgba8, src: Rgba8, mode: BlendMode, opacity: f32) -> Rgba8 {
+    let sa = src.a as f32 / 255. * opacity;
+    let da = dst.a as f32 / 255.;
+    let a = if mode == BlendMode::Behind {
+        da + sa * (1. - da)
+    } else {
+        sa + da * (1. - sa)
+    };
+    if a <= 0. {
+        return Rgba8::TRANSPARENT;
+    }
+    let channel = |s: u8, d: u8| -> u8 {
+        let (s, d) = (s as f32, d as f32);
+        let c = match mode {
+            BlendMode::Behind => da * d + (1. - da) * sa * s,
+            _ => {
+                let b = match mode {
+                    BlendMode::Multiply => s * d / 255.,
+                    BlendMode::Lighten => s.max(d),
+                    BlendMode::Darken => s.min(d),
+                    _ => s,
+                };
+                sa * ((1. - da) * s + da * b) + (1. - sa) * da * d
+            }
+        };
+        (c / a).round().min(255.) as u8
+    };
+    let (r, g, b) = (
+        channel(src.r, dst.r),
+        channel(src.g, dst.g),
+        channel(src.b, dst.b),
+    );
+
+    Rgba8::new(r, g, b, (a * 255.).round() as u8)
+}
+
+/// Convert a color to HSV. Hue is in degrees, from `0` to `360`, and
+/// saturation and value are from `0` to `1`.
+pub fn to_hsv(c: Rgba8) -> (f32, f32, f32) {
+    let (r, g, b) = (c.r as f32 / 255., c.g as f32 / 255., c.b as f32 / 255.);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / delta).rem_euclid(6.)
+    } else if max == g {
+        60. * ((b - r) / delta + 2.)
+    } else {
+        60. * ((r - g) / delta + 4.)
+    };
+    let s = if max == 0. { 0. } else { delta / max };
+
+    (h, s, max)
+}
+
+/// Convert a color from HSV, with the given alpha.
+pub fn from_hsv(h: f32, s: f32, v: f32, a: u8) -> Rgba8 {
+    let (s, v) = (s.clamp(0., 1.), v.clamp(0., 1.));
+    let h = h.rem_euclid(360.) / 60.;
+    let c = v * s;
+    let x = c * (1. - (h % 2. - 1.).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.),
+        1 => (x, c, 0.),
+        2 => (0., c, x),
+        3 => (0., x, c),
+        4 => (x, 0., c),
+        _ => (c, 0., x),
+    };
+    let m = v - c;
+    let channel = |n: f32| ((n + m) * 255.).round() as u8;
+
+    Rgba8::new(channel(r), channel(g), channel(b), a)
+}
+
+/// Convert a color from HSL, with the given alpha. Saturation and lightness
+/// are from `0` to `1`.
+pub fn from_hsl(h: f32, s: f32, l: f32, a: u8) -> Rgba8 {
+    let (s, l) = (s.clamp(0., 1.), l.clamp(0., 1.));
+    let v = l + s * l.min(1. - l);
+    let s = if v == 0. { 0. } else { 2. * (1. - l / v) };
+
+    from_hsv(h, s, v, a)
+}
+
+/// Convert a color to OKLCh, the polar form of the OKLab color space.
+/// Lightness is from `0` to `1`, and hue is in degrees.
+pub fn to_oklch(c: Rgba8) -> (f32, f32, f32) {
+    let linear = |n: u8| {
+        let n = n as f32 / 255.;
+        if n <= 0.04045 {
+            n / 12.92
+        } else {
+            ((n + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(c.r), linear(c.g), linear(c.b));
+
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+    let lightness = 0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s;
+    let a = 1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s;
+    let b = 0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s;
+
+    (
+        lightness,
+        (a * a + b * b).sqrt(),
+        b.atan2(a).to_degrees().rem_euclid(360.),
+    )
+}
+
+/// Convert a color from OKLCh, with the given alpha. Colors outside of the
+/// sRGB gamut are brought in by reducing their chroma.
+pub fn from_oklch(l: f32, c: f32, h: f32, a: u8) -> Rgba8 {
+    let l = l.clamp(0., 1.);
+    let to_rgb = |c: f32| {
+        let (a, b) = (c * h.to_radians().cos(), c * h.to_radians().sin());
+
+        let l_ = (l + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+        let m_ = (l - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+        let s_ = (l - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+
+        [
+            4.076_741_7 * l_ - 3.307_711_6 * m_ + 0.230_969_94 * s_,
+            -1.268_438 * l_ + 2.609_757_4 * m_ - 0.341_319_38 * s_,
+            -0.004_196_086_3 * l_ - 0.703_418_6 * m_ + 1.707_614_7 * s_,
+        ]
+    };
+    let in_gamut = |rgb: &[f32; 3]| rgb.iter().all(|n| (-1e-4..=1. + 1e-4).contains(n));
+
+    let mut rgb = to_rgb(c);
+    if !in_gamut(&rgb) {
+        let (mut lo, mut hi) = (0., c);
+        for _ in 0..16 {
+            let mid = (lo + hi) / 2.;
+            if in_gamut(&to_rgb(mid)) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        rgb = to_rgb(lo);
+    }
+    let channel = |n: f32| {
+        let n = n.clamp(0., 1.);
+        let n = if n <= 0.003_130_8 {
+            n * 12.92
+        } else {
+            1.055 * n.powf(1. / 2.4) - 0.055
+        };
+        (n * 255.).round() as u8
+    };
+
+    Rgba8::new(channel(rgb[0]), channel(rgb[1]), channel(rgb[2]), a)
+}
+
+/// CSS named colors, sorted by name.
+const NAMED: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+/// Look up a CSS named color. Names are case-insensitive.
+pub fn named(name: &str) -> Option<Rgba8> {
+    let name = name.to_ascii_lowercase();
+
+    if name == "transparent" {
+        return Some(TRANSPARENT);
+    }
+    NAMED
+        .binary_search_by(|(n, _)| n.cmp(&name.as_str()))
+        .ok()
+        .map(|i| {
+            let [_, r, g, b] = NAMED[i].1.to_be_bytes();
+            Rgba8::new(r, g, b, 0xff)
+        })
+}
+
+/// A color as written in a command: either a literal color, or a reference
+/// to the foreground, background or a palette slot, which is resolved when
+/// the command runs.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Color {
+    Rgba(Rgba8),
+    Ref(Target),
+}
+
+impl Color {
+    /// Return the literal color. Panics if the color is an unresolved reference.
+    pub fn rgba(&self) -> Rgba8 {
+        match self {
+            Self::Rgba(c) => *c,
+            Self::Ref(t) => panic!("color reference `{}` was not resolved", Self::Ref(*t)),
+        }
+    }
+}
+
+impl From<Rgba8> for Color {
+    fn from(c: Rgba8) -> Self {
+        Self::Rgba(c)
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rgba(c) => c.fmt(f),
+            Self::Ref(Target::Palette(i)) => write!(f, "p:{}", i),
+            Self::Ref(t) => t.fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    /// Parse a color. Accepts `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`,
+    /// `rgb(r, g, b[, a])`, `hsl(h, s%, l%[, a])`, `hsv(h, s%, v%[, a])`,
+    /// named colors, and the references `fg`, `bg` and `p:<index>`.
+    /// Any of these can be followed by `/<alpha>`, from `0` to `1`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let malformed = || format!("malformed color value `{}`", input);
+
+        match input {
+            "fg" => return Ok(Self::Ref(Target::Foreground)),
+            "bg" => return Ok(Self::Ref(Target::Background)),
+            _ => {}
+        }
+        if let Some(i) = input.strip_prefix("p:") {
+            return i
+                .parse()
+                .map(|i| Self::Ref(Target::Palette(i)))
+                .map_err(|_| malformed());
+        }
+
+        // The alpha suffix can't be inside a function's arguments.
+        let start = input.rfind(')').unwrap_or(0);
+        let (s, alpha) = match input[start..].find('/') {
+            Some(i) => {
+                let (s, alpha) = input.split_at(start + i);
+                let a = alpha[1..].parse::<f64>().map_err(|_| malformed())?;
+
+                (s, Some((a.clamp(0., 1.) * u8::MAX as f64) as u8))
+            }
+            None => (input, None),
+        };
+        let color = parse_hex(s)
+            .or_else(|| parse_function(s))
+            .or_else(|| named(s))
+            .ok_or_else(malformed)?;
+
+        Ok(Self::Rgba(match alpha {
+            Some(a) => color.alpha(a),
+            None => color,
+        }))
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Rgba8> {
+    let hex = s.strip_prefix('#')?;
+
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits = hex.bytes().map(|b| (b as char).to_digit(16).unwrap() as u8);
+    let channels: Vec<u8> = match hex.len() {
+        3 | 4 => digits.map(|d| d * 0x11).collect(),
+        6 | 8 => digits
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|c| c[0] * 0x10 + c[1])
+            .collect(),
+        _ => return None,
+    };
+    let a = channels.get(3).copied().unwrap_or(0xff);
+
+    Some(Rgba8::new(channels[0], channels[1], channels[2], a))
+}
+
+/// Parse a color function, eg. `rgb(255, 0, 0)` or `hsl(120, 50%, 50%)`.
+/// The alpha argument is from `0` to `1`, or a percentage.
+fn parse_function(s: &str) -> Option<Rgba8> {
+    let open = s.find('(')?;
+    let name = &s[..open];
+    let args = s[open + 1..].strip_suffix(')')?;
+    let args: Vec<&str> = args.split(',').map(str::trim).collect();
+
+    // Parse an argument as a fraction of `max`.
+    let fraction = |arg: &str, max: f32| -> Option<f32> {
+        let n = match arg.strip_suffix('%') {
+            Some(pct) => pct.parse::<f32>().ok()? / 100.,
+            None => arg.parse::<f32>().ok()? / max,
+        };
+        Some(n.clamp(0., 1.))
+    };
+    let alpha = match args.len() {
+        3 => 0xff,
+        4 => (fraction(args[3], 1.)? * 255.).round() as u8,
+        _ => return None,
+    };
+
+    match name {
+        "rgb" | "rgba" => {
+            let channel = |arg| fraction(arg, 255.).map(|n| (n * 255.).round() as u8);
+
+            Some(Rgba8::new(
+                channel(args[0])?,
+                channel(args[1])?,
+                channel(args[2])?,
+                alpha,
+            ))
+        }
+        "hsl" | "hsla" | "hsv" | "hsva" => {
+            let h = args[0].strip_suffix("deg").unwrap_or(args[0]);
+            let h = h.parse::<f32>().ok()?;
+            let s = fraction(args[1], 100.)?;
+            let l = fraction(args[2], 100.)?;
+
+            if name.starts_with("hsl") {
+                Some(from_hsl(h, s, l, alpha))
+            } else {
+                Some(from_hsv(h, s, l, alpha))
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blend() {
+        let red = Rgba8::new(0xff, 0, 0, 0xff);
+        let grey = Rgba8::new(0x80, 0x80, 0x80, 0xff);
+
+        assert_eq!(blend(grey, red, BlendMode::Normal, 1.), red);
+        assert_eq!(
+            blend(grey, red, BlendMode::Normal, 0.5),
+            Rgba8::new(0xc0, 0x40, 0x40, 0xff)
+        );
+        assert_eq!(
+            blend(grey, red, BlendMode::Multiply, 1.),
+            Rgba8::new(0x80, 0, 0, 0xff)
+        );
+        assert_eq!(
+            blend(grey, red, BlendMode::Lighten, 1.),
+            Rgba8::new(0xff, 0x80, 0x80, 0xff)
+        );
+        assert_eq!(blend(grey, red, BlendMode::Behind, 1.), grey);
+        assert_eq!(blend(TRANSPARENT, red, BlendMode::Behind, 1.), red);
+        assert_eq!(
+            blend(TRANSPARENT, red, BlendMode::Multiply, 0.5),
+            Rgba8::new(0xff, 0, 0, 0x80)
+        );
+    }
+
+    #[test]
+    fn test_color_spaces() {
+        let colors = [
+            Rgba8::new(0xff, 0, 0, 0xff),
+            Rgba8::new(0x12, 0x34, 0x56, 0xff),
+            Rgba8::new(0xc0, 0xff, 0x33, 0x80),
+            Rgba8::new(0x80, 0x80, 0x80, 0xff),
+            WHITE,
+            BLACK,
+        ];
+        for c in colors.iter() {
+            let (h, s, v) = to_hsv(*c);
+            assert_eq!(from_hsv(h, s, v, c.a), *c);
+
+            let (l, ch, h) = to_oklch(*c);
+            assert_eq!(from_oklch(l, ch, h, c.a), *c);
+        }
+        assert_eq!(to_hsv(colors[0]), (0., 1., 1.));
+        assert_eq!(from_hsl(120., 1., 0.25, 0xff), Rgba8::new(0, 0x80, 0, 0xff));
+    }
+
+    #[test]
+    fn test_color() {
+        let red = Color::Rgba(Rgba8::new(0xff, 0, 0, 0xff));
+
+        assert_eq!("#f00".parse(), Ok(red));
+        assert_eq!("#ff0000".parse(), Ok(red));
+        assert_eq!(
+            "#ff000080".parse(),
+            Ok(Color::Rgba(Rgba8::new(0xff, 0, 0, 0x80)))
+        );
+        assert_eq!("rgb(255, 0, 0)".parse(), Ok(red));
+        assert_eq!("rgb(100%,0%,0%)".parse(), Ok(red));
+        assert_eq!("hsl(0, 100%, 50%)".parse(), Ok(red));
+        assert_eq!("hsv(360, 100%, 100%)".parse(), Ok(red));
+        assert_eq!("red".parse(), Ok(red));
+        assert_eq!(
+            "rgba(255, 0, 0, 0.5)".parse(),
+            Ok(Color::Rgba(Rgba8::new(0xff, 0, 0, 0x80)))
+        );
+        assert_eq!(
+            "rgb(255, 0, 0)/0.5".parse(),
+            Ok(Color::Rgba(Rgba8::new(0xff, 0, 0, 127)))
+        );
+        assert_eq!(
+            "RebeccaPurple".parse(),
+            Ok(Color::Rgba(Rgba8::new(0x66, 0x33, 0x99, 0xff)))
+        );
+        assert_eq!("fg".parse(), Ok(Color::Ref(Target::Foreground)));
+        assert_eq!("p:3".parse(), Ok(Color::Ref(Target::Palette(3))));
+        assert_eq!(Color::Ref(Target::Palette(3)).to_string(), "p:3");
+
+        assert!("#ff00".parse::<Color>().is_ok());
+        assert!("#ff000".parse::<Color>().is_err());
+        assert!("rgb(255, 0)".parse::<Color>().is_err());
+        assert!("reddish".parse::<Color>().is_err());
+        assert!("p:x".parse::<Color>().is_err());
+    }
+}
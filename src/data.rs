@@ -3,6 +3,30 @@
 /// Initial (default) configuration for rx.
 pub const CONFIG: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/config/init.rx"));
 
+/// Include a built-in palette, by name.
+macro_rules! palette {
+    ($name:literal) => {
+        (
+            $name,
+            include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/config/palettes/",
+                $name,
+                ".palette"
+            )),
+        )
+    };
+}
+
+/// Built-in palettes, by name. Each palette is a script that replaces the
+/// colors of the palette in use.
+pub const PALETTES: &[(&str, &[u8])] = &[
+    palette!("db16"),
+    palette!("db32"),
+    palette!("edg32"),
+    palette!("sweetie16"),
+];
+
 /// Cursor sprites.
 pub const CURSORS: &[u8] =
     include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/cursors.png"));
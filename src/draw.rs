@@ -1,5 +1,6 @@
 use crate::brush::{Align, Brush, BrushMode};
 use crate::color;
+use crate::editor::{Channel, ColorEditor};
 use crate::execution::Execution;
 use crate::font::{TextAlign, TextBatch};
 use crate::platform;
@@ -82,8 +83,14 @@ pub mod cursors {
             Tool::Sampler => self::SAMPLER,
             Tool::Pan(_) => self::PAN,
             Tool::FloodFill => self::FLOOD,
-
-            Tool::Brush => match m {
+            Tool::Gradient(_)
+            | Tool::Lasso(_)
+            | Tool::Wand
+            | Tool::Text(_)
+            | Tool::Curve(_)
+            | Tool::Polygon(_) => self::CROSSHAIR,
+
+            Tool::Brush | Tool::Shape(_) => match m {
                 Mode::Visual(_) if in_selection && in_view => self::OMNI,
                 Mode::Visual(VisualState::Selecting { dragging: true }) if in_selection => {
                     self::OMNI
@@ -132,6 +139,7 @@ impl Context {
         self::draw_ui(session, &mut self.ui_batch, &mut self.text_batch);
         self::draw_overlay(session, avg_frametime, &mut self.overlay_batch, execution);
         self::draw_palette(session, &mut self.ui_batch);
+        self::draw_color_editor(session, &mut self.ui_batch, &mut self.text_batch);
         self::draw_cursor(session, &mut self.cursor_sprite, &mut self.tool_batch);
         self::draw_checker(session, &mut self.checker_batch);
     }
@@ -199,7 +207,19 @@ fn draw_ui(session: &Session, canvas: &mut shape2d::Batch, text: &mut TextBatch)
             Fill::Empty,
         ));
         // Selection fill.
-        if r.intersects(view.layer_bounds()) {
+        if let Some(mask) = session.selection_mask() {
+            for span in mask.spans() {
+                canvas.add(Shape::Rectangle(
+                    (span + Vector2::new(r.x1, r.y1))
+                        .map(|n| n as f32)
+                        .transform(t),
+                    self::UI_LAYER,
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(fill.into()),
+                ));
+            }
+        } else if r.intersects(view.layer_bounds()) {
             canvas.add(Shape::Rectangle(
                 r.intersection(view.layer_bounds())
                     .map(|n| n as f32)
@@ -212,6 +232,140 @@ fn draw_ui(session: &Session, canvas: &mut shape2d::Batch, text: &mut TextBatch)
         }
     }
 
+    // Lasso.
+    if let Tool::Lasso(lasso) = &session.tool {
+        let half = Vector2::new(view.zoom / 2., view.zoom / 2.);
+        let mut points: Vec<_> = lasso
+            .vertices
+            .iter()
+            .map(|p| {
+                *session.session_coords(view.id, ViewCoords::new(p.x as f32, p.y as f32)) + half
+            })
+            .collect();
+
+        if !points.is_empty() {
+            let c = session.cursor;
+            points.push(*session.snap(c, view.offset.x, view.offset.y, view.zoom) + half);
+        }
+        for line in points.windows(2) {
+            canvas.add(Shape::Line(
+                Line::new(line[0], line[1]),
+                self::UI_LAYER,
+                Rotation::ZERO,
+                Stroke::new(1.0, color::RED.into()),
+            ));
+        }
+    }
+
+    // Curve handles.
+    if let Tool::Curve(curve) = &session.tool {
+        let z = view.zoom;
+        let half = Vector2::new(z / 2., z / 2.);
+        let points: Vec<_> = curve
+            .points
+            .iter()
+            .map(|p| *session.session_coords(view.id, ViewCoords::new(p.x as f32, p.y as f32)))
+            .collect();
+
+        // Each end of the curve is connected to its nearest control point.
+        let arms = match points.as_slice() {
+            [a, b, c] => vec![(a, c), (b, c)],
+            [a, b, c1, c2] => vec![(a, c1), (b, c2)],
+            _ => vec![],
+        };
+        for (p, q) in arms {
+            canvas.add(Shape::Line(
+                Line::new(*p + half, *q + half),
+                self::UI_LAYER,
+                Rotation::ZERO,
+                Stroke::new(1.0, color::RED.into()),
+            ));
+        }
+        for p in points {
+            canvas.add(Shape::Rectangle(
+                Rect::new(p.x, p.y, p.x + z, p.y + z),
+                self::UI_LAYER,
+                Rotation::ZERO,
+                Stroke::new(1.0, color::RED.into()),
+                Fill::Empty,
+            ));
+        }
+    }
+
+    // Polygon start. Clicking on it closes the polygon.
+    if let Tool::Polygon(polygon) = &session.tool {
+        if let Some(p) = polygon.vertices.first() {
+            let z = view.zoom;
+            let p = session.session_coords(view.id, ViewCoords::new(p.x as f32, p.y as f32));
+
+            canvas.add(Shape::Rectangle(
+                Rect::new(p.x, p.y, p.x + z, p.y + z),
+                self::UI_LAYER,
+                Rotation::ZERO,
+                Stroke::new(1.0, color::RED.into()),
+                Fill::Empty,
+            ));
+        }
+    }
+
+    // Symmetry guides, for each frame.
+    {
+        let brush = &session.brush;
+        let offset = session.offset + view.offset;
+        let (fw, fh, z) = (view.fw as f32, view.fh as f32, view.zoom);
+        let c = brush.symmetry_center(view.fw, view.fh);
+        let stroke = Stroke::new(1.0, Rgba8::new(0x88, 0x88, 0x88, 0x88).into());
+        let mut lines = Vec::new();
+
+        for i in 0..view.animation.len() {
+            let fx = i as f32 * fw;
+
+            if brush.is_set(BrushMode::XSym) {
+                lines.push(((fx + c.x, 0.), (fx + c.x, fh)));
+            }
+            if brush.is_set(BrushMode::YSym) {
+                lines.push(((fx, c.y), (fx + fw, c.y)));
+            }
+            if brush.is_set(BrushMode::DSym) {
+                let (t1, t2) = ((-c.x).max(-c.y), (fw - c.x).min(fh - c.y));
+                lines.push(((fx + c.x + t1, c.y + t1), (fx + c.x + t2, c.y + t2)));
+            }
+            if brush.radial_mode().is_some() {
+                let r = 1.5;
+                lines.push(((fx + c.x - r, c.y), (fx + c.x + r, c.y)));
+                lines.push(((fx + c.x, c.y - r), (fx + c.x, c.y + r)));
+            }
+        }
+        for ((x1, y1), (x2, y2)) in lines {
+            canvas.add(Shape::Line(
+                Line::new(
+                    offset + Vector2::new(x1, y1) * z,
+                    offset + Vector2::new(x2, y2) * z,
+                ),
+                self::UI_LAYER,
+                Rotation::ZERO,
+                stroke,
+            ));
+        }
+    }
+
+    if let Some(mask) = session.mask {
+        let offset = session.offset + view.offset;
+        let t = Matrix4::from_translation(offset.extend(0.)) * Matrix4::from_scale(view.zoom);
+
+        // Selection mask stroke.
+        canvas.add(Shape::Rectangle(
+            mask.map(|n| n as f32).transform(t),
+            self::UI_LAYER,
+            Rotation::ZERO,
+            Stroke::new(
+                1.,
+                Rgba8::new(color::RED.r, color::RED.g, color::RED.b, 0x88).into(),
+            ),
+            Fill::Empty,
+        ));
+    }
+
     for v in session.views.iter() {
         let offset = v.offset + session.offset;
 
@@ -465,6 +619,7 @@ fn draw_palette(session: &Session, batch: &mut shape2d::Batch) {
     let p = &session.palette;
     let height = p.height;
     for (i, color) in p.colors.iter().rev().cloned().enumerate() {
+        let index = p.size() - i - 1;
         let x = if i >= height {
             (i / height) as f32 * p.cellsize
         } else {
@@ -473,8 +628,8 @@ fn draw_palette(session: &Session, batch: &mut shape2d::Batch) {
         let y = (i % height) as f32 * p.cellsize;
 
         let mut stroke = shape2d::Stroke::NONE;
-        if let (Tool::Sampler, Some(c)) = (&session.tool, p.hover) {
-            if c == color {
+        if let (Tool::Sampler, Some(hovered)) = (&session.tool, p.hover_index) {
+            if hovered == index {
                 stroke = shape2d::Stroke::new(1., Rgba::WHITE);
             }
         }
@@ -489,6 +644,129 @@ fn draw_palette(session: &Session, batch: &mut shape2d::Batch) {
     }
 }
 
+fn draw_color_editor(session: &Session, batch: &mut shape2d::Batch, text: &mut TextBatch) {
+    let editor = match &session.color_editor {
+        Some(editor) => editor,
+        None => return,
+    };
+    let cell = |r: Rect<f32>, color: Rgba8| {
+        Shape::Rectangle(
+            r,
+            self::PALETTE_LAYER,
+            Rotation::ZERO,
+            Stroke::NONE,
+            Fill::Solid(color.into()),
+        )
+    };
+    let marker = |r: Rect<f32>| {
+        Shape::Rectangle(
+            r,
+            self::PALETTE_LAYER,
+            Rotation::ZERO,
+            Stroke::new(1., Rgba::WHITE),
+            Fill::Empty,
+        )
+    };
+    let color = editor.color();
+
+    // Background.
+    let r = editor.rect();
+    batch.add(Shape::Rectangle(
+        Rect::new(r.x1 - 4., r.y1 - 4., r.x2 + 4., r.y2 + 4.),
+        self::UI_LAYER,
+        Rotation::ZERO,
+        Stroke::new(1., Rgba::new(0.5, 0.5, 0.5, 1.0)),
+        Fill::Solid(color::BLACK.into()),
+    ));
+
+    // Saturation/value square.
+    let (square, n) = (editor.square(), 16);
+    let size = square.width() / n as f32;
+    for i in 0..n {
+        for j in 0..n {
+            let (x, y) = (square.x1 + i as f32 * size, square.y1 + j as f32 * size);
+            let (s, v) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+
+            batch.add(cell(
+                Rect::new(x, y, x + size, y + size),
+                color::from_hsv(editor.hue, s, v, 0xff),
+            ));
+        }
+    }
+    let (x, y) = (
+        square.x1 + editor.saturation * square.width(),
+        square.y1 + editor.value * square.height(),
+    );
+    batch.add(marker(Rect::new(x - 2., y - 2., x + 3., y + 3.)));
+
+    // Hue strip.
+    let (strip, n) = (editor.strip(), 32);
+    let size = strip.height() / n as f32;
+    for j in 0..n {
+        let y = strip.y1 + j as f32 * size;
+        let hue = (j as f32 + 0.5) / n as f32 * 360.;
+
+        batch.add(cell(
+            Rect::new(strip.x1, y, strip.x2, y + size),
+            color::from_hsv(hue, 1., 1., 0xff),
+        ));
+    }
+    let y = strip.y1 + editor.hue / 360. * strip.height();
+    batch.add(marker(Rect::new(
+        strip.x1 - 1.,
+        y - 1.,
+        strip.x2 + 1.,
+        y + 1.,
+    )));
+
+    // Channel sliders.
+    for channel in ColorEditor::SLIDERS.iter() {
+        let slider = match editor.slider(*channel) {
+            Some(slider) => slider,
+            None => continue,
+        };
+        let n = 32;
+        let size = slider.width() / n as f32;
+
+        for i in 0..n {
+            let x = slider.x1 + i as f32 * size;
+            let value = ((i as f32 + 0.5) / n as f32 * 255.) as u8;
+            let c = match channel {
+                Channel::Red => Rgba8 { r: value, ..color },
+                Channel::Green => Rgba8 { g: value, ..color },
+                Channel::Blue => Rgba8 { b: value, ..color },
+                _ => Rgba8 { a: value, ..color },
+            };
+            batch.add(cell(Rect::new(x, slider.y1, x + size, slider.y2), c));
+        }
+        let x = slider.x1 + editor.get(*channel) / channel.max() * slider.width();
+        batch.add(marker(Rect::new(
+            x - 1.,
+            slider.y1 - 1.,
+            x + 1.,
+            slider.y2 + 1.,
+        )));
+    }
+
+    // Label, with the edited color and its value.
+    let y = square.y1 - ColorEditor::LABEL_HEIGHT + 3.;
+    batch.add(Shape::Rectangle(
+        Rect::new(r.x2 - 12., y, r.x2, y + 12.),
+        self::PALETTE_LAYER,
+        Rotation::ZERO,
+        Stroke::new(1., Rgba::WHITE),
+        Fill::Solid(color.into()),
+    ));
+    text.add(
+        &format!("{} {}", editor.target, color),
+        r.x1,
+        y,
+        self::TEXT_LAYER,
+        Rgba8::WHITE,
+        TextAlign::Left,
+    );
+}
+
 fn draw_checker(session: &Session, batch: &mut sprite2d::Batch) {
     if session.settings["checker"].is_set() {
         for v in session.views.iter() {
@@ -605,6 +883,21 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
     let c = session.cursor;
     let z = v.zoom;
 
+    // Draw gradient line, in normal or visual mode.
+    if let Tool::Gradient(Some(start)) = session.tool {
+        let half = Vector2::new(z / 2., z / 2.);
+        let start =
+            *session.session_coords(v.id, ViewCoords::new(start.x as f32, start.y as f32)) + half;
+        let end = *session.snap(c, v.offset.x, v.offset.y, z) + half;
+
+        shapes.add(Shape::Line(
+            Line::new(start, end),
+            self::UI_LAYER,
+            Rotation::ZERO,
+            Stroke::new(1.0, session.fg.into()),
+        ));
+    }
+
     match session.mode {
         Mode::Visual(VisualState::Selecting { .. }) => {
             if session.is_selected(session.view_coords(v.id, c).into()) {
@@ -623,7 +916,7 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
             }
         }
         Mode::Normal => {
-            if let Tool::Brush = session.tool {
+            if let Tool::Brush | Tool::Shape(_) = session.tool {
                 let view_coords = session.active_view_coords(c);
 
                 // Draw enabled brush
@@ -651,15 +944,34 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
                         (Stroke::NONE, Fill::Solid(session.fg.into()))
                     };
 
-                    for p in brush.expand(view_coords.into(), v.extent()) {
-                        shapes.add(brush.shape(
-                            *session.session_coords(v.id, p.into()),
-                            self::BRUSH_LAYER,
-                            stroke,
-                            fill,
-                            v.zoom,
-                            Align::BottomLeft,
-                        ));
+                    if brush.stamp.is_some() {
+                        for (p, color) in brush.stamp_pixels(view_coords.into(), v.extent()) {
+                            let p = session.session_coords(v.id, p.into());
+                            let (stroke, fill) = if brush.is_set(BrushMode::Erase) {
+                                (stroke, fill)
+                            } else {
+                                (Stroke::NONE, Fill::Solid(color.into()))
+                            };
+
+                            shapes.add(Shape::Rectangle(
+                                Rect::new(p.x, p.y, p.x + z, p.y + z),
+                                self::BRUSH_LAYER,
+                                Rotation::ZERO,
+                                stroke,
+                                fill,
+                            ));
+                        }
+                    } else {
+                        for p in brush.expand(view_coords.into(), v.extent()) {
+                            shapes.add(brush.shape(
+                                *session.session_coords(v.id, p.into()),
+                                self::BRUSH_LAYER,
+                                stroke,
+                                fill,
+                                v.zoom,
+                                Align::BottomLeft,
+                            ));
+                        }
                     }
 
                     // X-Ray brush mode.
@@ -699,6 +1011,24 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
                     ));
                 }
             }
+            // Draw text preview
+            if let Tool::Text(text) = &session.tool {
+                if v.contains(c - session.offset) {
+                    let c = session.snap(c, v.offset.x, v.offset.y, z);
+
+                    for p in session.font.points(text) {
+                        let (x, y) = (c.x + p.x as f32 * z, c.y + p.y as f32 * z);
+
+                        shapes.add(Shape::Rectangle(
+                            Rect::new(x, y, x + z, y + z),
+                            self::UI_LAYER,
+                            Rotation::ZERO,
+                            Stroke::NONE,
+                            Fill::Solid(session.fg.into()),
+                        ));
+                    }
+                }
+            }
         }
         _ => {}
     }
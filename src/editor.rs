@@ -0,0 +1,378 @@
+use crate::color;
+use crate::gfx::math::Point2;
+use crate::gfx::rect::Rect;
+use crate::gfx::Rgba8;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The color edited by the color editor.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Target {
+    /// The foreground color.
+    Foreground,
+    /// The background color.
+    Background,
+    /// A palette slot, by index.
+    Palette(usize),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fg" => Ok(Self::Foreground),
+            "bg" => Ok(Self::Background),
+            other => other.parse().map(Self::Palette).map_err(|_| {
+                format!(
+                    "unknown color `{}`, must be `fg`, `bg` or a palette index",
+                    other
+                )
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Foreground => "fg".fmt(f),
+            Self::Background => "bg".fmt(f),
+            Self::Palette(i) => i.fmt(f),
+        }
+    }
+}
+
+/// A channel of the edited color.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Channel {
+    /// Hue, from `0` to `360`.
+    Hue,
+    /// Saturation, from `0` to `100`.
+    Saturation,
+    /// Value, from `0` to `100`.
+    Value,
+    /// Red, from `0` to `255`.
+    Red,
+    /// Green, from `0` to `255`.
+    Green,
+    /// Blue, from `0` to `255`.
+    Blue,
+    /// Alpha, from `0` to `255`.
+    Alpha,
+}
+
+impl Channel {
+    /// Maximum value of the channel.
+    pub fn max(self) -> f32 {
+        match self {
+            Self::Hue => 360.,
+            Self::Saturation | Self::Value => 100.,
+            Self::Red | Self::Green | Self::Blue | Self::Alpha => 255.,
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hue => "h".fmt(f),
+            Self::Saturation => "s".fmt(f),
+            Self::Value => "v".fmt(f),
+            Self::Red => "r".fmt(f),
+            Self::Green => "g".fmt(f),
+            Self::Blue => "b".fmt(f),
+            Self::Alpha => "a".fmt(f),
+        }
+    }
+}
+
+/// A change to a channel.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Change {
+    /// Add to the channel.
+    By(f32),
+    /// Set the channel.
+    To(f32),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::By(n) => write!(f, "{:+}", n),
+            Self::To(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// A control of the color editor that can be dragged with the mouse.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Control {
+    /// The saturation/value square.
+    Square,
+    /// The hue strip.
+    Strip,
+    /// A channel slider.
+    Slider(Channel),
+}
+
+/// Color editor. The color is kept as HSV, so that the hue survives colors
+/// without saturation or value while editing.
+#[derive(Clone, Debug)]
+pub struct ColorEditor {
+    /// The edited color.
+    pub target: Target,
+    /// Hue, from `0` to `360`.
+    pub hue: f32,
+    /// Saturation, from `0` to `1`.
+    pub saturation: f32,
+    /// Value, from `0` to `1`.
+    pub value: f32,
+    pub alpha: u8,
+    /// Bottom-left corner of the editor, in session coordinates.
+    pub origin: Point2<f32>,
+    /// The control being dragged, if any.
+    pub dragging: Option<Control>,
+}
+
+impl ColorEditor {
+    /// Size of the saturation/value square, in pixels.
+    pub const SQUARE_SIZE: f32 = 128.;
+    /// Width of the hue strip.
+    pub const STRIP_WIDTH: f32 = 12.;
+    /// Height of the channel sliders.
+    pub const SLIDER_HEIGHT: f32 = 8.;
+    /// Space between controls.
+    pub const SPACING: f32 = 4.;
+    /// Height of the label, between the sliders and the square.
+    pub const LABEL_HEIGHT: f32 = 18.;
+    /// Channels with sliders, from top to bottom.
+    pub const SLIDERS: [Channel; 4] = [Channel::Red, Channel::Green, Channel::Blue, Channel::Alpha];
+
+    /// Width of the editor.
+    pub const WIDTH: f32 = Self::SQUARE_SIZE + Self::SPACING + Self::STRIP_WIDTH;
+    /// Height of the editor.
+    pub const HEIGHT: f32 = Self::SLIDERS.len() as f32 * (Self::SLIDER_HEIGHT + Self::SPACING)
+        + Self::LABEL_HEIGHT
+        + Self::SQUARE_SIZE;
+
+    /// Distance from the editor to the top-right corner of the session.
+    const MARGIN: f32 = 10.;
+
+    pub fn new(target: Target, color: Rgba8) -> Self {
+        let mut editor = Self {
+            target,
+            hue: 0.,
+            saturation: 0.,
+            value: 0.,
+            alpha: 0,
+            origin: Point2::new(0., 0.),
+            dragging: None,
+        };
+        editor.set_color(color);
+        editor
+    }
+
+    /// Place the editor in the top-right corner of a session of the given size.
+    pub fn place(&mut self, width: f32, height: f32) {
+        self.origin = Point2::new(
+            width - Self::WIDTH - Self::MARGIN,
+            height - Self::HEIGHT - Self::MARGIN,
+        );
+    }
+
+    /// Return the edited color.
+    pub fn color(&self) -> Rgba8 {
+        color::from_hsv(self.hue, self.saturation, self.value, self.alpha)
+    }
+
+    /// Set the edited color. The hue, and the saturation are kept when the
+    /// color doesn't determine them.
+    pub fn set_color(&mut self, c: Rgba8) {
+        if c == self.color() {
+            return;
+        }
+        let (h, s, v) = color::to_hsv(c);
+
+        if s > 0. && v > 0. {
+            self.hue = h;
+        }
+        if v > 0. {
+            self.saturation = s;
+        }
+        self.value = v;
+        self.alpha = c.a;
+    }
+
+    /// Return the value of a channel.
+    pub fn get(&self, channel: Channel) -> f32 {
+        let c = self.color();
+
+        match channel {
+            Channel::Hue => self.hue,
+            Channel::Saturation => self.saturation * 100.,
+            Channel::Value => self.value * 100.,
+            Channel::Red => c.r as f32,
+            Channel::Green => c.g as f32,
+            Channel::Blue => c.b as f32,
+            Channel::Alpha => c.a as f32,
+        }
+    }
+
+    /// Change the value of a channel. Values are clamped to the channel range,
+    /// except for the hue, which wraps around.
+    pub fn change(&mut self, channel: Channel, change: Change) {
+        let n = match change {
+            Change::By(n) => self.get(channel) + n,
+            Change::To(n) => n,
+        };
+        let byte = n.round().clamp(0., 255.) as u8;
+
+        match channel {
+            Channel::Hue => self.hue = n.rem_euclid(360.),
+            Channel::Saturation => self.saturation = (n / 100.).clamp(0., 1.),
+            Channel::Value => self.value = (n / 100.).clamp(0., 1.),
+            Channel::Red => self.set_color(Rgba8 {
+                r: byte,
+                ..self.color()
+            }),
+            Channel::Green => self.set_color(Rgba8 {
+                g: byte,
+                ..self.color()
+            }),
+            Channel::Blue => self.set_color(Rgba8 {
+                b: byte,
+                ..self.color()
+            }),
+            Channel::Alpha => self.alpha = byte,
+        }
+    }
+
+    /// Return the area of the saturation/value square.
+    pub fn square(&self) -> Rect<f32> {
+        let y = self.origin.y + Self::HEIGHT - Self::SQUARE_SIZE;
+
+        Rect::new(
+            self.origin.x,
+            y,
+            self.origin.x + Self::SQUARE_SIZE,
+            y + Self::SQUARE_SIZE,
+        )
+    }
+
+    /// Return the area of the hue strip.
+    pub fn strip(&self) -> Rect<f32> {
+        let square = self.square();
+        let x = square.x2 + Self::SPACING;
+
+        Rect::new(x, square.y1, x + Self::STRIP_WIDTH, square.y2)
+    }
+
+    /// Return the area of a channel slider. Only the RGBA channels have sliders.
+    pub fn slider(&self, channel: Channel) -> Option<Rect<f32>> {
+        let i = Self::SLIDERS.iter().rev().position(|c| *c == channel)?;
+        let y = self.origin.y + i as f32 * (Self::SLIDER_HEIGHT + Self::SPACING);
+
+        Some(Rect::new(
+            self.origin.x,
+            y,
+            self.origin.x + Self::WIDTH,
+            y + Self::SLIDER_HEIGHT,
+        ))
+    }
+
+    /// Return the area of the whole editor.
+    pub fn rect(&self) -> Rect<f32> {
+        Rect::new(
+            self.origin.x,
+            self.origin.y,
+            self.origin.x + Self::WIDTH,
+            self.origin.y + Self::HEIGHT,
+        )
+    }
+
+    /// Return the control at the given point, if any.
+    pub fn control_at(&self, p: Point2<f32>) -> Option<Control> {
+        let contains = |r: Rect<f32>| p.x >= r.x1 && p.x < r.x2 && p.y >= r.y1 && p.y < r.y2;
+
+        if contains(self.square()) {
+            Some(Control::Square)
+        } else if contains(self.strip()) {
+            Some(Control::Strip)
+        } else {
+            Self::SLIDERS
+                .iter()
+                .find(|c| self.slider(**c).map_or(false, contains))
+                .map(|c| Control::Slider(*c))
+        }
+    }
+
+    /// Drag a control to the given point. The point doesn't have to be within
+    /// the control.
+    pub fn drag(&mut self, control: Control, p: Point2<f32>) {
+        let fraction = |n: f32, from: f32, to: f32| ((n - from) / (to - from)).clamp(0., 1.);
+
+        match control {
+            Control::Square => {
+                let r = self.square();
+
+                self.saturation = fraction(p.x, r.x1, r.x2);
+                self.value = fraction(p.y, r.y1, r.y2);
+            }
+            Control::Strip => {
+                let r = self.strip();
+                self.hue = fraction(p.y, r.y1, r.y2) * 359.;
+            }
+            Control::Slider(channel) => {
+                if let Some(r) = self.slider(channel) {
+                    let n = fraction(p.x, r.x1, r.x2) * channel.max();
+                    self.change(channel, Change::To(n));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_editor() {
+        let red = Rgba8::new(0xff, 0, 0, 0xff);
+        let mut editor = ColorEditor::new(Target::Foreground, red);
+
+        assert_eq!(editor.color(), red);
+
+        // The hue survives greys.
+        editor.change(Channel::Hue, Change::By(120.));
+        editor.change(Channel::Saturation, Change::To(0.));
+        editor.change(Channel::Saturation, Change::To(100.));
+        assert_eq!(editor.color(), Rgba8::new(0, 0xff, 0, 0xff));
+
+        // The hue wraps around, other channels are clamped.
+        editor.change(Channel::Hue, Change::By(-150.));
+        assert_eq!(editor.get(Channel::Hue), 330.);
+        editor.change(Channel::Blue, Change::By(300.));
+        assert_eq!(editor.get(Channel::Blue), 255.);
+
+        // Dragging the square sets saturation and value.
+        editor.place(800., 600.);
+        let square = editor.square();
+        editor.drag(
+            Control::Square,
+            Point2::new(square.x1 - 10., square.y2 + 10.),
+        );
+        assert_eq!(editor.color(), Rgba8::WHITE);
+
+        let slider = editor.slider(Channel::Alpha).unwrap();
+        let p = Point2::new(slider.x1 + 1., slider.y1 + 1.);
+        assert_eq!(editor.control_at(p), Some(Control::Slider(Channel::Alpha)));
+        editor.drag(Control::Slider(Channel::Alpha), p);
+        assert_eq!(editor.color().a, 2);
+
+        assert_eq!("7".parse(), Ok(Target::Palette(7)));
+        assert_eq!("bg".parse(), Ok(Target::Background));
+    }
+}
@@ -1,9 +1,13 @@
+use crate::color;
 use crate::gfx::color::Rgba8;
 use crate::gfx::math::Point2;
 use crate::gfx::rect::Rect;
 use crate::gfx::shape2d::{Fill, Rotation, Shape, Stroke};
 use crate::gfx::ZDepth;
-use crate::view::{View, ViewCoords, ViewResource};
+use crate::view::{View, ViewCoords, ViewExtent, ViewResource};
+
+use std::fmt;
+use std::str::FromStr;
 
 struct Grid {
     pixels: Vec<Rgba8>,
@@ -27,20 +31,81 @@ impl Grid {
             None
         }
     }
+}
 
-    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Rgba8> {
-        if x < self.width && y < self.height {
-            self.pixels.get_mut(x + y * self.width)
-        } else {
-            None
+/// Flood fill options.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct FloodOptions {
+    /// Maximum distance between a pixel's color and the target color for the
+    /// pixel to be filled. Applies to both the RGB and alpha channels.
+    pub tolerance: u8,
+    /// Fill all matching pixels, instead of only the contiguous ones.
+    pub global: bool,
+    /// Consider diagonally adjacent pixels as contiguous.
+    pub diagonal: bool,
+    /// Area the fill is confined to.
+    pub scope: FloodScope,
+}
+
+/// Area a flood fill is confined to.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum FloodScope {
+    /// The whole view, across frames.
+    View,
+    /// The frame under the starting point.
+    Frame,
+}
+
+impl Default for FloodScope {
+    fn default() -> Self {
+        Self::View
+    }
+}
+
+impl FloodScope {
+    /// Return the bounds of a fill starting at the given horizontal position
+    /// of a view with the given extent.
+    pub fn bounds(self, extent: ViewExtent, x: i32) -> Rect<i32> {
+        let rect = match self {
+            Self::View => extent.rect(),
+            Self::Frame => {
+                let frame = x.max(0) as usize / extent.fw as usize;
+                extent.frame(frame.min(extent.nframes - 1))
+            }
+        };
+        rect.map(|n| n as i32)
+    }
+}
+
+impl FromStr for FloodScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "view" => Ok(Self::View),
+            "frame" => Ok(Self::Frame),
+            other => Err(format!(
+                "unknown flood scope `{}`, must be one of `view` or `frame`",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FloodScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::View => "view".fmt(f),
+            Self::Frame => "frame".fmt(f),
         }
     }
 }
 
 pub struct FloodFiller {
     grid: Grid,
+    filled: Vec<bool>,
     replacement_color: Rgba8,
-    target_color: Rgba8,
+    options: FloodOptions,
     rects: Vec<(Rect<f32>, Rgba8)>,
     stack: Vec<Point2<usize>>,
 }
@@ -48,9 +113,9 @@ pub struct FloodFiller {
 impl FloodFiller {
     pub fn new(
         view: &View<ViewResource>,
-        starting_point: ViewCoords<f32>,
         replacement_color: Rgba8,
-    ) -> Option<FloodFiller> {
+        options: FloodOptions,
+    ) -> FloodFiller {
         let (snapshot, pixels) = view.layer.current_snapshot();
         let bounds = snapshot.extent.rect();
         let grid = Grid::new(
@@ -59,20 +124,69 @@ impl FloodFiller {
             bounds.height() as usize,
         );
 
-        let starting_point = Point2::new(
-            starting_point.x as usize,
-            grid.height - starting_point.y as usize - 1,
-        );
-
-        let target_color = *grid.get(starting_point.x, starting_point.y)?;
-
-        Some(FloodFiller {
+        FloodFiller {
+            filled: vec![false; grid.width * grid.height],
             grid,
-            target_color,
             replacement_color,
+            options,
             rects: Vec::new(),
-            stack: vec![starting_point],
-        })
+            stack: Vec::new(),
+        }
+    }
+
+    /// Fill from the given starting point, without leaving the given bounds.
+    /// May be called more than once, eg. to fill multiple frames.
+    pub fn fill(&mut self, starting_point: ViewCoords<f32>, bounds: Rect<i32>) {
+        let (w, h) = (self.grid.width as i32, self.grid.height as i32);
+
+        // Convert the bounds to grid coordinates, where rows go from top to bottom.
+        let bounds = Rect::new(
+            bounds.x1.max(0),
+            (h - bounds.y2).max(0),
+            bounds.x2.min(w),
+            (h - bounds.y1).min(h),
+        )
+        .map(|n| n as usize);
+
+        let x = starting_point.x as usize;
+        let y = (h - starting_point.y as i32 - 1) as usize;
+
+        if x < bounds.x1 || x >= bounds.x2 || y < bounds.y1 || y >= bounds.y2 {
+            return;
+        }
+        let target_color = match self.grid.get(x, y) {
+            Some(c) => *c,
+            None => return,
+        };
+
+        if self.options.global {
+            self.fill_global(target_color, bounds);
+        } else {
+            self.fill_contiguous(Point2::new(x, y), target_color, bounds);
+        }
+    }
+
+    pub fn run(self) -> Option<Vec<Shape>> {
+        // Don't bother if none of the filled pixels would change color.
+        let replacement_color = self.replacement_color;
+        let changed = self
+            .filled
+            .iter()
+            .zip(self.grid.pixels.iter())
+            .any(|(filled, c)| *filled && *c != replacement_color);
+
+        if !changed {
+            return None;
+        }
+        Some(to_shapes(self.rects))
+    }
+
+    /// Return the filled pixels, in view coordinates.
+    pub fn points(&self) -> Vec<Point2<i32>> {
+        self.rects
+            .iter()
+            .flat_map(|(r, _)| (r.x1 as i32..r.x2 as i32).map(move |x| Point2::new(x, r.y1 as i32)))
+            .collect()
     }
 
     fn push_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: Rgba8) {
@@ -87,92 +201,90 @@ impl FloodFiller {
         ));
     }
 
-    fn try_set_at(&mut self, x: usize, y: usize) -> bool {
-        match self.grid.get_mut(x, y) {
+    /// Check whether the pixel at the given position should be filled.
+    fn is_fillable(&self, x: usize, y: usize, target_color: Rgba8) -> bool {
+        match self.grid.get(x, y) {
             Some(c) => {
-                if *c != self.target_color {
-                    false
-                } else {
-                    *c = self.replacement_color;
-                    true
-                }
+                !self.filled[x + y * self.grid.width]
+                    && color::within_tolerance(*c, target_color, self.options.tolerance)
             }
             None => false,
         }
     }
 
-    fn push_on_change(&mut self, x: usize, y: usize, edge: &mut bool) {
-        if let Some(c) = self.grid.get(x, y) {
-            if *c == self.target_color {
-                if *edge {
-                    // We're at an edge, we'll come back to this point in the next loop to start a
-                    // new horizontal span.
-                    self.stack.push(Point2::new(x, y));
-                    *edge = false;
+    /// Fill all pixels matching the target color, row by row.
+    fn fill_global(&mut self, target_color: Rgba8, bounds: Rect<usize>) {
+        for y in bounds.y1..bounds.y2 {
+            let mut span: Option<usize> = None;
+
+            for x in bounds.x1..bounds.x2 {
+                if self.is_fillable(x, y, target_color) {
+                    self.filled[x + y * self.grid.width] = true;
+                    span.get_or_insert(x);
+                } else if let Some(start) = span.take() {
+                    self.push_rect(start, y, x - start, 1, self.replacement_color);
                 }
-            } else {
-                *edge = true;
+            }
+            if let Some(start) = span {
+                self.push_rect(start, y, bounds.x2 - start, 1, self.replacement_color);
             }
         }
     }
 
-    fn look_above_below(&mut self, x: usize, y: usize, up: &mut bool, down: &mut bool) {
-        if y > 0 {
-            self.push_on_change(x, y - 1, up);
-        }
-
-        if y < self.grid.height - 1 {
-            self.push_on_change(x, y + 1, down);
-        }
-    }
-
-    pub fn run(mut self) -> Option<Vec<Shape>> {
-        // This algorithm fills horizontally from the starting point, looking for edges above and
-        // below. An "edge" is a place where a solid pixel changes to a fillable one. "Solid" means
-        // not equal to self.target_color. When we see one of these transitions, we push the next
-        // point onto the stack and, later, we come back and repeat the horizontal scan from that
-        // point.
-        if self.target_color == self.replacement_color {
-            return None;
-        }
+    fn fill_contiguous(&mut self, start: Point2<usize>, target_color: Rgba8, bounds: Rect<usize>) {
+        // This algorithm fills horizontally from a point, and then scans the rows above
+        // and below the filled span. Every time a fillable span is found in one of these rows,
+        // its first point is pushed onto the stack, and we later come back to repeat the
+        // horizontal fill from that point.
+        self.stack.push(start);
 
         while let Some(p) = self.stack.pop() {
+            if !self.is_fillable(p.x, p.y, target_color) {
+                continue;
+            }
             let mut min_x = p.x;
             let mut max_x = p.x;
 
-            // Keep track of whether the pixels above/below us are transitioning from solid to
-            // fillable. These will be true as long as we're "in" (above/below) a solid region, and
-            // will become false when we are past it.
-            let mut up_edge = true;
-            let mut down_edge = true;
-
-            // scan right
-            for x in p.x..=self.grid.width {
-                max_x = x;
-                if !self.try_set_at(x, p.y) {
-                    break;
-                }
-                self.look_above_below(x, p.y, &mut up_edge, &mut down_edge);
+            while min_x > bounds.x1 && self.is_fillable(min_x - 1, p.y, target_color) {
+                min_x -= 1;
+            }
+            while max_x + 1 < bounds.x2 && self.is_fillable(max_x + 1, p.y, target_color) {
+                max_x += 1;
             }
+            for x in min_x..=max_x {
+                self.filled[x + p.y * self.grid.width] = true;
+            }
+            self.push_rect(min_x, p.y, max_x - min_x + 1, 1, self.replacement_color);
 
-            up_edge = p.y > 0 && self.grid.get(p.x, p.y - 1) != Some(&self.target_color);
-            down_edge = p.y < self.grid.height - 1
-                && self.grid.get(p.x, p.y + 1) != Some(&self.target_color);
+            // With diagonal connectivity, the spans above and below may start one pixel
+            // before, or end one pixel after the current span.
+            let (min_x, max_x) = if self.options.diagonal {
+                (
+                    usize::max(min_x.saturating_sub(1), bounds.x1),
+                    usize::min(max_x + 1, bounds.x2 - 1),
+                )
+            } else {
+                (min_x, max_x)
+            };
 
-            // scan left
-            for x in (0..p.x).rev() {
-                min_x = x;
-                if !self.try_set_at(x, p.y) {
-                    min_x += 1;
-                    break;
+            for y in [p.y.wrapping_sub(1), p.y + 1].iter().copied() {
+                if y < bounds.y1 || y >= bounds.y2 {
+                    continue;
                 }
-                self.look_above_below(x, p.y, &mut up_edge, &mut down_edge);
-            }
+                let mut edge = true;
 
-            self.push_rect(min_x, p.y, max_x - min_x, 1, self.replacement_color);
+                for x in min_x..=max_x {
+                    if self.is_fillable(x, y, target_color) {
+                        if edge {
+                            self.stack.push(Point2::new(x, y));
+                            edge = false;
+                        }
+                    } else {
+                        edge = true;
+                    }
+                }
+            }
         }
-
-        Some(to_shapes(self.rects))
     }
 }
 
@@ -189,3 +301,207 @@ fn to_shapes(input: Vec<(Rect<f32>, Rgba8)>) -> Vec<Shape> {
     }
     rects
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::view::{FileStatus, ViewManager};
+
+    const W: Rgba8 = Rgba8::WHITE;
+    const K: Rgba8 = Rgba8::BLACK;
+
+    /// Fill the given frames from the given point, and return the filled pixels
+    /// as rows of booleans, top row first.
+    fn fill(
+        pixels: &[Rgba8],
+        extent: ViewExtent,
+        start: (u32, u32),
+        options: FloodOptions,
+        multi: bool,
+    ) -> Vec<Vec<bool>> {
+        let mut views = ViewManager::new();
+        let resource = ViewResource::new(pixels.to_vec(), extent);
+        let id = views.add(
+            FileStatus::NoFile,
+            extent.fw,
+            extent.fh,
+            extent.nframes,
+            resource,
+        );
+        let view = views.get(id).unwrap();
+        let mut filler = FloodFiller::new(view, color::RED, options);
+        let (x, y) = (start.0 as f32, start.1 as f32);
+
+        if multi {
+            for i in 0..extent.nframes {
+                let offset = (i as u32 * extent.fw) as f32;
+                let bounds = FloodScope::Frame.bounds(extent, (x + offset) as i32);
+
+                filler.fill(ViewCoords::new(x + offset, y), bounds);
+            }
+        } else {
+            filler.fill(
+                ViewCoords::new(x, y),
+                options.scope.bounds(extent, x as i32),
+            );
+        }
+
+        let (w, h) = (extent.width() as usize, extent.fh as usize);
+        let mut rows = vec![vec![false; w]; h];
+        for p in filler.points() {
+            rows[h - p.y as usize - 1][p.x as usize] = true;
+        }
+        rows
+    }
+
+    #[test]
+    fn test_connectivity() {
+        #[rustfmt::skip]
+        let pixels = [
+            W, K, K,
+            K, W, K,
+            K, K, W,
+        ];
+        let extent = ViewExtent::new(3, 3, 1);
+        let options = FloodOptions::default();
+
+        assert_eq!(
+            fill(&pixels, extent, (0, 2), options, false),
+            vec![
+                vec![true, false, false],
+                vec![false, false, false],
+                vec![false, false, false],
+            ],
+            "4-connected fills don't cross diagonals"
+        );
+        assert_eq!(
+            fill(
+                &pixels,
+                extent,
+                (0, 2),
+                FloodOptions {
+                    diagonal: true,
+                    ..options
+                },
+                false
+            ),
+            vec![
+                vec![true, false, false],
+                vec![false, true, false],
+                vec![false, false, true],
+            ],
+            "8-connected fills cross diagonals"
+        );
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let grey = Rgba8::new(0xf0, 0xf0, 0xf0, 0xff);
+        let faint = Rgba8::new(0xff, 0xff, 0xff, 0xf0);
+        let pixels = [W, grey, faint, K];
+        let extent = ViewExtent::new(4, 1, 1);
+        let options = FloodOptions::default();
+
+        assert_eq!(
+            fill(&pixels, extent, (0, 0), options, false),
+            vec![vec![true, false, false, false]]
+        );
+        assert_eq!(
+            fill(
+                &pixels,
+                extent,
+                (0, 0),
+                FloodOptions {
+                    tolerance: 0x0f,
+                    ..options
+                },
+                false
+            ),
+            vec![vec![true, true, true, false]]
+        );
+        assert_eq!(
+            fill(
+                &pixels,
+                extent,
+                (0, 0),
+                FloodOptions {
+                    tolerance: 0x0e,
+                    ..options
+                },
+                false
+            ),
+            vec![vec![true, false, false, false]]
+        );
+    }
+
+    #[test]
+    fn test_global() {
+        #[rustfmt::skip]
+        let pixels = [
+            W, K, W,   W, K, W,
+        ];
+        let extent = ViewExtent::new(3, 1, 2);
+        let options = FloodOptions {
+            global: true,
+            ..FloodOptions::default()
+        };
+
+        assert_eq!(
+            fill(&pixels, extent, (0, 0), options, false),
+            vec![vec![true, false, true, true, false, true]]
+        );
+        assert_eq!(
+            fill(
+                &pixels,
+                extent,
+                (0, 0),
+                FloodOptions {
+                    scope: FloodScope::Frame,
+                    ..options
+                },
+                false
+            ),
+            vec![vec![true, false, true, false, false, false]]
+        );
+    }
+
+    #[test]
+    fn test_frame_bounds() {
+        #[rustfmt::skip]
+        let pixels = [
+            W, W, W,   W, K, W,
+        ];
+        let extent = ViewExtent::new(3, 1, 2);
+        let options = FloodOptions::default();
+
+        assert_eq!(
+            fill(&pixels, extent, (0, 0), options, false),
+            vec![vec![true, true, true, true, false, false]],
+            "view fills cross frames"
+        );
+        assert_eq!(
+            fill(
+                &pixels,
+                extent,
+                (0, 0),
+                FloodOptions {
+                    scope: FloodScope::Frame,
+                    ..options
+                },
+                false
+            ),
+            vec![vec![true, true, true, false, false, false]],
+            "frame fills stop at the frame edge"
+        );
+        assert_eq!(
+            fill(&pixels, extent, (0, 0), options, true),
+            vec![vec![true, true, true, true, false, false]],
+            "multi-frame fills repeat the fill in each frame"
+        );
+        assert_eq!(
+            fill(&pixels, extent, (2, 0), options, true),
+            vec![vec![true, true, true, false, false, true]],
+            "multi-frame fills stop at each frame edge"
+        );
+    }
+}
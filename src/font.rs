@@ -1,6 +1,13 @@
+use crate::data;
+use crate::image;
+
+use crate::gfx::math::Point2;
 use crate::gfx::sprite2d;
 use crate::gfx::{Rect, Repeat, Rgba8, ZDepth};
 
+use std::collections::HashMap;
+use std::path::Path;
+
 pub enum TextAlign {
     Left,
     Right,
@@ -89,3 +96,273 @@ impl TextBatch {
         self.raw.clear()
     }
 }
+
+/// A fixed-size bitmap font, used to paint text into views.
+#[derive(Debug, Clone)]
+pub struct Font {
+    /// Glyph width.
+    pub gw: u32,
+    /// Glyph height.
+    pub gh: u32,
+    /// Glyph pixels, bottom row first.
+    glyphs: HashMap<char, Vec<bool>>,
+}
+
+impl Font {
+    /// Width and height of the built-in glyphs.
+    const BUILTIN_SIZE: (u32, u32) = (8, 14);
+    /// Maximum width and height of glyphs loaded from BDF files. Bitmap rows
+    /// are read as 64-bit integers.
+    const MAX_BDF_SIZE: i32 = 64;
+
+    /// Load a font from an image of glyphs laid out in a grid, stored top row
+    /// first. Glyphs are ordered left to right, top to bottom, starting with
+    /// the space character. Pixels that are at least half opaque are set.
+    pub fn from_grid(
+        pixels: &[Rgba8],
+        width: u32,
+        height: u32,
+        gw: u32,
+        gh: u32,
+    ) -> Result<Self, String> {
+        if gw == 0 || gh == 0 || width % gw != 0 || height % gh != 0 {
+            return Err(format!(
+                "image size {}x{} is not a multiple of glyph size {}x{}",
+                width, height, gw, gh
+            ));
+        }
+        let (cols, rows) = (width / gw, height / gh);
+        let mut glyphs = HashMap::new();
+
+        for i in 0..cols * rows {
+            let c = match std::char::from_u32(' ' as u32 + i) {
+                Some(c) => c,
+                None => continue,
+            };
+            let (gx, gy) = ((i % cols) * gw, (i / cols) * gh);
+            let mut bits = Vec::with_capacity((gw * gh) as usize);
+
+            for y in (0..gh).rev() {
+                for x in 0..gw {
+                    bits.push(pixels[((gy + y) * width + gx + x) as usize].a >= 0x80);
+                }
+            }
+            glyphs.insert(c, bits);
+        }
+        Ok(Self { gw, gh, glyphs })
+    }
+
+    /// Load a font from the source of a BDF file. Glyphs are placed within
+    /// the font bounding box.
+    pub fn from_bdf(source: &str) -> Result<Self, String> {
+        let mut lines = source.lines().map(str::trim);
+        let mut bounds: Option<[i32; 4]> = None;
+        let mut glyphs = HashMap::new();
+
+        let numbers = |args: &str| -> Result<[i32; 4], String> {
+            let ns = args
+                .split_whitespace()
+                .map(|n| n.parse::<i32>().map_err(|e| e.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match ns.as_slice() {
+                [w, h, x, y] => Ok([*w, *h, *x, *y]),
+                _ => Err(format!("invalid bounding box `{}`", args)),
+            }
+        };
+
+        while let Some(line) = lines.next() {
+            let (keyword, args) = match line.find(' ') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => (line, ""),
+            };
+            match keyword {
+                "FONTBOUNDINGBOX" => {
+                    let [w, h, x, y] = numbers(args)?;
+                    let max = Self::MAX_BDF_SIZE;
+
+                    if w <= 0 || h <= 0 || w > max || h > max {
+                        return Err(format!(
+                            "font bounding box size {}x{} must be between 1x1 and {}x{}",
+                            w, h, max, max
+                        ));
+                    }
+                    bounds = Some([w, h, x, y]);
+                }
+                "STARTCHAR" => {
+                    let [fw, fh, fx, fy] = bounds.ok_or("missing font bounding box")?;
+                    let mut encoding = None;
+                    let mut bbx = [fw, fh, fx, fy];
+                    let mut bits = vec![false; (fw * fh) as usize];
+
+                    while let Some(line) = lines.next() {
+                        if let Some(args) = line.strip_prefix("ENCODING ") {
+                            encoding = args
+                                .trim()
+                                .parse::<u32>()
+                                .ok()
+                                .and_then(std::char::from_u32);
+                        } else if let Some(args) = line.strip_prefix("BBX ") {
+                            bbx = numbers(args)?;
+                        } else if line == "BITMAP" {
+                            let [w, h, x, y] = bbx;
+
+                            for row in 0..h {
+                                let hex = lines.next().ok_or("unexpected end of bitmap")?;
+                                let bytes = u64::from_str_radix(hex, 16)
+                                    .ok()
+                                    .filter(|_| hex.len() * 4 <= Self::MAX_BDF_SIZE as usize)
+                                    .ok_or_else(|| format!("invalid bitmap row `{}`", hex))?;
+                                let len = hex.len() as i32 * 4;
+
+                                for col in 0..w.min(len) {
+                                    if bytes & (1 << (len - col - 1)) == 0 {
+                                        continue;
+                                    }
+                                    let cx = x - fx + col;
+                                    let cy = y - fy + (h - row - 1);
+
+                                    if cx >= 0 && cy >= 0 && cx < fw && cy < fh {
+                                        bits[(cy * fw + cx) as usize] = true;
+                                    }
+                                }
+                            }
+                        } else if line == "ENDCHAR" {
+                            break;
+                        }
+                    }
+                    if let Some(c) = encoding {
+                        glyphs.insert(c, bits);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let [fw, fh, _, _] = bounds.ok_or("missing font bounding box")?;
+
+        if glyphs.is_empty() {
+            return Err("font has no glyphs".to_owned());
+        }
+        Ok(Self {
+            gw: fw as u32,
+            gh: fh as u32,
+            glyphs,
+        })
+    }
+
+    /// Load a font from a file. PNG fonts are loaded as a grid of glyphs of
+    /// the given size, and BDF fonts from their own metrics.
+    pub fn load<P: AsRef<Path>>(path: P, size: Option<(u32, u32)>) -> Result<Self, String> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => {
+                let (gw, gh) = size.ok_or("glyph size is required for png fonts")?;
+                let (buffer, w, h) = image::load(path).map_err(|e| e.to_string())?;
+
+                Self::from_grid(Rgba8::align(&buffer), w, h, gw, gh)
+            }
+            Some("bdf") => {
+                let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+                Self::from_bdf(&source)
+            }
+            _ => Err("font must be a `.png` or `.bdf` file".to_owned()),
+        }
+    }
+
+    /// Return the pixels of the given text, relative to the bottom-left corner
+    /// of its first line. Characters without a glyph are left blank.
+    pub fn points(&self, text: &str) -> Vec<Point2<i32>> {
+        let (gw, gh) = (self.gw as i32, self.gh as i32);
+        let mut points = Vec::new();
+
+        for (line, s) in text.lines().enumerate() {
+            for (i, c) in s.chars().enumerate() {
+                let glyph = match self.glyphs.get(&c) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                let (ox, oy) = (i as i32 * gw, -(line as i32) * gh);
+
+                for (j, _) in glyph.iter().enumerate().filter(|(_, b)| **b) {
+                    points.push(Point2::new(ox + j as i32 % gw, oy + j as i32 / gw));
+                }
+            }
+        }
+        points
+    }
+}
+
+impl Default for Font {
+    /// The font used by the user interface.
+    fn default() -> Self {
+        let (buffer, w, h) = image::read(data::GLYPHS).expect("built-in glyphs are valid");
+        let (gw, gh) = Self::BUILTIN_SIZE;
+
+        Self::from_grid(Rgba8::align(&buffer), w, h, gw, gh).expect("built-in glyphs are valid")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bdf() {
+        let source = "STARTFONT 2.1
+FONTBOUNDINGBOX 3 3 0 -1
+STARTCHAR period
+ENCODING 46
+BBX 1 1 1 0
+BITMAP
+80
+ENDCHAR
+STARTCHAR bar
+ENCODING 124
+BBX 1 3 0 -1
+BITMAP
+80
+80
+80
+ENDCHAR
+ENDFONT";
+        let font = Font::from_bdf(source).unwrap();
+
+        assert_eq!((font.gw, font.gh), (3, 3));
+        assert_eq!(font.points("."), vec![Point2::new(1, 1)]);
+        assert_eq!(
+            font.points(" |"),
+            vec![Point2::new(3, 0), Point2::new(3, 1), Point2::new(3, 2)]
+        );
+    }
+
+    #[test]
+    fn test_bdf_bounds() {
+        for bbx in &["0 3 0 0", "3 -1 0 0", "65 8 0 0", "8 100000 0 0"] {
+            let source = format!("FONTBOUNDINGBOX {}\nSTARTCHAR a\nENDCHAR\n", bbx);
+
+            assert!(Font::from_bdf(&source).is_err(), "{} is rejected", bbx);
+        }
+        let source = "FONTBOUNDINGBOX 8 1 0 0
+STARTCHAR a
+ENCODING 97
+BBX 8 1 0 0
+BITMAP
+00000000000000000001
+ENDCHAR";
+        assert!(
+            Font::from_bdf(source).is_err(),
+            "bitmap rows are at most 64 bits"
+        );
+    }
+
+    #[test]
+    fn test_builtin() {
+        let font = Font::default();
+
+        assert_eq!((font.gw, font.gh), Font::BUILTIN_SIZE);
+        assert!(font.points(" ").is_empty());
+        assert!(!font.points("A").is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::gfx::math::{Point2, Vector2};
+use crate::gfx::Rgba8;
+use crate::mask::Mask;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Which neighbors of a pixel are adjacent to it.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Connectivity {
+    /// Only horizontal and vertical neighbors.
+    Four,
+    /// Horizontal, vertical and diagonal neighbors.
+    Eight,
+}
+
+impl Connectivity {
+    /// Offsets of the neighbors of a pixel.
+    fn neighbors(self) -> &'static [(i32, i32)] {
+        const FOUR: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+        const EIGHT: [(i32, i32); 8] = [
+            (-1, 0),
+            (1, 0),
+            (0, -1),
+            (0, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+            (1, 1),
+        ];
+
+        match self {
+            Self::Four => &FOUR,
+            Self::Eight => &EIGHT,
+        }
+    }
+}
+
+impl fmt::Display for Connectivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Four => "4".fmt(f),
+            Self::Eight => "8".fmt(f),
+        }
+    }
+}
+
+/// Where an outline is drawn, relative to the opaque pixels it surrounds.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Placement {
+    /// Around the opaque pixels, on transparent pixels.
+    Outside,
+    /// On the edge pixels of the opaque area.
+    Inside,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self::Outside
+    }
+}
+
+impl FromStr for Placement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "outside" => Ok(Self::Outside),
+            "inside" => Ok(Self::Inside),
+            other => Err(format!(
+                "unknown outline placement `{}`, must be `outside` or `inside`",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Outside => "outside".fmt(f),
+            Self::Inside => "inside".fmt(f),
+        }
+    }
+}
+
+/// An area of pixels effects are computed from. Each effect returns the
+/// pixels it paints, relative to the bottom-left corner of the area.
+pub struct Image<'a> {
+    /// Pixels, stored top row first.
+    pixels: &'a [Rgba8],
+    width: i32,
+    height: i32,
+    /// Pixels outside of the mask are ignored, and never painted.
+    mask: Option<&'a Mask>,
+}
+
+impl<'a> Image<'a> {
+    pub fn new(pixels: &'a [Rgba8], width: u32, height: u32, mask: Option<&'a Mask>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+
+        Self {
+            pixels,
+            width: width as i32,
+            height: height as i32,
+            mask,
+        }
+    }
+
+    /// Return the pixels of a 1px outline around or along the edge of the
+    /// opaque pixels.
+    pub fn outline(&self, placement: Placement, connectivity: Connectivity) -> Vec<Point2<i32>> {
+        self.points()
+            .filter(|p| match placement {
+                Placement::Outside => {
+                    !self.is_opaque(p.x, p.y) && self.has_neighbor(*p, connectivity, true)
+                }
+                Placement::Inside => {
+                    self.is_opaque(p.x, p.y) && self.has_neighbor(*p, connectivity, false)
+                }
+            })
+            .collect()
+    }
+
+    /// Return the pixels of a shadow of the opaque pixels cast at the given
+    /// offset. The shadow is only cast on transparent pixels.
+    pub fn shadow(&self, offset: Vector2<i32>) -> Vec<Point2<i32>> {
+        self.points()
+            .filter(|p| !self.is_opaque(p.x, p.y) && self.is_opaque(p.x - offset.x, p.y - offset.y))
+            .collect()
+    }
+
+    /// Return the opaque pixels that have no opaque neighbors.
+    pub fn orphans(&self) -> Vec<Point2<i32>> {
+        self.points()
+            .filter(|p| {
+                self.is_opaque(p.x, p.y) && !self.has_neighbor(*p, Connectivity::Eight, true)
+            })
+            .collect()
+    }
+
+    /// Iterate over the pixels effects can paint.
+    fn points(&self) -> impl Iterator<Item = Point2<i32>> + '_ {
+        let (w, h) = (self.width, self.height);
+
+        (0..h)
+            .flat_map(move |y| (0..w).map(move |x| Point2::new(x, y)))
+            .filter(move |p| self.mask.map_or(true, |m| m.contains(p.x, p.y)))
+    }
+
+    /// Check whether a pixel is opaque. Pixels outside of the area or of the
+    /// mask are considered transparent.
+    fn is_opaque(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return false;
+        }
+        if let Some(mask) = self.mask {
+            if !mask.contains(x, y) {
+                return false;
+            }
+        }
+        let i = (self.height - y - 1) * self.width + x;
+
+        self.pixels[i as usize].a > 0
+    }
+
+    /// Check whether a pixel has a neighbor with the given opacity.
+    fn has_neighbor(&self, p: Point2<i32>, connectivity: Connectivity, opaque: bool) -> bool {
+        connectivity
+            .neighbors()
+            .iter()
+            .any(|(dx, dy)| self.is_opaque(p.x + dx, p.y + dy) == opaque)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const X: Rgba8 = Rgba8::BLACK;
+    const O: Rgba8 = Rgba8::TRANSPARENT;
+
+    #[test]
+    fn test_outline() {
+        #[rustfmt::skip]
+        let pixels = [
+            O, O, O, O,
+            O, X, X, O,
+            O, X, X, O,
+            O, O, O, O,
+        ];
+        let image = Image::new(&pixels, 4, 4, None);
+
+        assert_eq!(
+            image.outline(Placement::Outside, Connectivity::Eight).len(),
+            12
+        );
+        assert_eq!(
+            image.outline(Placement::Outside, Connectivity::Four).len(),
+            8
+        );
+        assert_eq!(
+            image.outline(Placement::Inside, Connectivity::Four).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_shadow_and_orphans() {
+        #[rustfmt::skip]
+        let pixels = [
+            X, O, O,
+            O, O, O,
+            O, O, X,
+        ];
+        let image = Image::new(&pixels, 3, 3, None);
+
+        assert_eq!(image.shadow(Vector2::new(1, -1)), vec![Point2::new(1, 1)]);
+        assert_eq!(image.orphans(), vec![Point2::new(2, 0), Point2::new(0, 2)]);
+    }
+}
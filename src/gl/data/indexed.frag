@@ -0,0 +1,14 @@
+uniform usampler2D indices;
+uniform sampler2D  palette;
+
+in  vec2  f_uv;
+in  float f_opacity;
+
+out vec4  fragColor;
+
+void main() {
+	uint index = texture(indices, f_uv).r;
+	vec4 color = texelFetch(palette, ivec2(int(index), 0), 0);
+
+	fragColor = vec4(color.rgb, color.a * f_opacity);
+}
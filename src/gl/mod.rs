@@ -127,6 +127,14 @@ struct Screen2dInterface {
     framebuffer: Uniform<TextureBinding<Dim2, pixel::NormUnsigned>>,
 }
 
+#[derive(UniformInterface)]
+struct Indexed2dInterface {
+    indices: Uniform<TextureBinding<Dim2, pixel::Unsigned>>,
+    palette: Uniform<TextureBinding<Dim2, pixel::NormUnsigned>>,
+    ortho: Uniform<M44>,
+    transform: Uniform<M44>,
+}
+
 pub struct Renderer {
     pub win_size: LogicalSize,
 
@@ -148,11 +156,14 @@ pub struct Renderer {
     checker: Texture<Backend, Dim2, pixel::SRGBA8UI>,
     paste: Texture<Backend, Dim2, pixel::SRGBA8UI>,
     paste_outputs: Vec<Tess<Backend, Sprite2dVertex>>,
+    preview: Texture<Backend, Dim2, pixel::SRGBA8UI>,
+    preview_view: Option<ViewId>,
 
     sprite2d: Program<Backend, VertexSemantics, (), Sprite2dInterface>,
     shape2d: Program<Backend, VertexSemantics, (), Shape2dInterface>,
     cursor2d: Program<Backend, VertexSemantics, (), Cursor2dInterface>,
     screen2d: Program<Backend, VertexSemantics, (), Screen2dInterface>,
+    indexed2d: Program<Backend, VertexSemantics, (), Indexed2dInterface>,
 
     view_data: BTreeMap<ViewId, ViewData>,
 }
@@ -235,11 +246,43 @@ impl LayerData {
     }
 }
 
+/// Palette indices of an indexed view, with its palette. Indexed views are
+/// rendered by looking up the color of each index in the palette.
+struct IndexedData {
+    indices: Texture<Backend, Dim2, pixel::R8UI>,
+    palette: Texture<Backend, Dim2, pixel::SRGBA8UI>,
+}
+
+impl IndexedData {
+    fn new(w: u32, h: u32, ctx: &mut Context) -> Result<Self, RendererError> {
+        let indices =
+            Texture::new(ctx, [w, h], 0, self::SAMPLER).map_err(RendererError::Texture)?;
+        let palette =
+            Texture::new(ctx, [256, 1], 0, self::SAMPLER).map_err(RendererError::Texture)?;
+
+        Ok(Self { indices, palette })
+    }
+
+    fn upload(&mut self, indices: &[u8], colors: &[Rgba8]) -> Result<(), RendererError> {
+        // Indices past the last palette color are transparent.
+        let mut palette = vec![Rgba8::TRANSPARENT; 256];
+        palette[..colors.len()].copy_from_slice(colors);
+
+        self.indices
+            .upload_raw(GenMipmaps::No, indices)
+            .map_err(RendererError::Texture)?;
+        self.palette
+            .upload_raw(GenMipmaps::No, util::align_u8(&palette))
+            .map_err(RendererError::Texture)
+    }
+}
+
 struct ViewData {
     layer: LayerData,
     staging_fb: Framebuffer<Backend, Dim2, pixel::SRGBA8UI, pixel::Depth32F>,
     anim_tess: Option<Tess<Backend, Sprite2dVertex>>,
     layer_tess: Option<Tess<Backend, Sprite2dVertex>>,
+    indexed: Option<IndexedData>,
 }
 
 impl ViewData {
@@ -257,8 +300,39 @@ impl ViewData {
             staging_fb,
             anim_tess: None,
             layer_tess: None,
+            indexed: None,
         }
     }
+
+    /// Upload the palette indices of an indexed view. Until the indices match
+    /// the size of the view, eg. while it is resized, the view is rendered from
+    /// its pixels.
+    fn upload_indexed(
+        &mut self,
+        view: &View<ViewResource>,
+        ctx: &mut Context,
+    ) -> Result<(), RendererError> {
+        let [w, h] = self.layer.fb.size();
+
+        match view.layer.current_indices() {
+            Some((palette, indices)) if indices.len() == (w * h) as usize => {
+                if self
+                    .indexed
+                    .as_ref()
+                    .map_or(true, |ix| ix.indices.size() != [w, h])
+                {
+                    self.indexed = Some(IndexedData::new(w, h, ctx)?);
+                }
+                if let Some(ix) = &mut self.indexed {
+                    ix.upload(indices, &palette.colors)?;
+                }
+            }
+            _ => {
+                self.indexed = None;
+            }
+        }
+        Ok(())
+    }
 }
 
 struct Context {
@@ -371,6 +445,8 @@ impl<'a> renderer::Renderer<'a> for Renderer {
             .map_err(Error::Texture)?;
         let paste =
             Texture::new(&mut ctx, [paste_w, paste_h], 0, self::SAMPLER).map_err(Error::Texture)?;
+        let preview =
+            Texture::new(&mut ctx, [paste_w, paste_h], 0, self::SAMPLER).map_err(Error::Texture)?;
         let mut checker = Texture::new(&mut ctx, [checker_w, checker_h], 0, self::SAMPLER)
             .map_err(Error::Texture)?;
 
@@ -399,6 +475,10 @@ impl<'a> renderer::Renderer<'a> for Renderer {
             include_str!("data/screen.vert"),
             include_str!("data/screen.frag"),
         );
+        let indexed2d = ctx.program::<Indexed2dInterface>(
+            include_str!("data/sprite.vert"),
+            include_str!("data/indexed.frag"),
+        );
 
         let physical = win_size.to_physical(scale_factor);
         let present_fb =
@@ -450,11 +530,14 @@ impl<'a> renderer::Renderer<'a> for Renderer {
             shape2d,
             cursor2d,
             screen2d,
+            indexed2d,
             font,
             cursors,
             checker,
             paste,
             paste_outputs: Vec::new(),
+            preview,
+            preview_view: None,
             staging_batch: shape2d::Batch::new(),
             final_batch: shape2d::Batch::new(),
             view_data: BTreeMap::new(),
@@ -495,6 +578,7 @@ impl<'a> renderer::Renderer<'a> for Renderer {
             shape2d,
             cursor2d,
             screen2d,
+            indexed2d,
             scale_factor,
             present_fb,
             blending,
@@ -503,6 +587,8 @@ impl<'a> renderer::Renderer<'a> for Renderer {
             pipeline_st,
             paste,
             paste_outputs,
+            preview,
+            preview_view,
             view_data,
             ..
         } = self;
@@ -691,19 +777,47 @@ impl<'a> renderer::Renderer<'a> for Renderer {
                                 (session.offset + view.offset).extend(*draw::VIEW_LAYER),
                             ) * Matrix4::from_nonuniform_scale(view.zoom, view.zoom, 1.0);
 
-                        // Render views.
-                        shd_gate.shade(sprite2d, |mut iface, uni, mut rdr_gate| {
-                            let bound_view = pipeline
-                                .bind_texture(v.layer.fb.color_slot())
-                                .expect("binding textures never fails");
+                        let previewed = *preview_view == Some(*id);
+
+                        // Render indexed views with their palette.
+                        if let (Some(ix), false) = (&mut v.indexed, previewed) {
+                            shd_gate.shade(indexed2d, |mut iface, uni, mut rdr_gate| {
+                                let bound_indices = pipeline
+                                    .bind_texture(&mut ix.indices)
+                                    .expect("binding textures never fails");
+                                let bound_palette = pipeline
+                                    .bind_texture(&mut ix.palette)
+                                    .expect("binding textures never fails");
+
+                                iface.set(&uni.ortho, ortho);
+                                iface.set(&uni.transform, transform.into());
+                                iface.set(&uni.indices, bound_indices.binding());
+                                iface.set(&uni.palette, bound_palette.binding());
+
+                                rdr_gate.render(render_st, |mut tess_gate| {
+                                    tess_gate.render(&v.layer.tess)
+                                })
+                            })?;
+                        }
 
+                        // Render views, or their preview if they're being previewed.
+                        shd_gate.shade(sprite2d, |mut iface, uni, mut rdr_gate| {
                             iface.set(&uni.ortho, ortho);
                             iface.set(&uni.transform, transform.into());
-                            iface.set(&uni.tex, bound_view.binding());
 
-                            rdr_gate.render(render_st, |mut tess_gate| {
-                                tess_gate.render(&v.layer.tess)
-                            })?;
+                            if v.indexed.is_none() || previewed {
+                                let bound_view = if previewed {
+                                    pipeline.bind_texture(&mut *preview)
+                                } else {
+                                    pipeline.bind_texture(v.layer.fb.color_slot())
+                                }
+                                .expect("binding textures never fails");
+
+                                iface.set(&uni.tex, bound_view.binding());
+                                rdr_gate.render(render_st, |mut tess_gate| {
+                                    tess_gate.render(&v.layer.tess)
+                                })?;
+                            }
 
                             // TODO: We only need to render this on the active view.
                             let staging_texture = v.staging_fb.color_slot();
@@ -850,14 +964,16 @@ impl<'a> renderer::Renderer<'a> for Renderer {
             },
         );
 
-        // If active view is dirty, record a snapshot of it.
-        if v.is_dirty() {
-            // FIXME: This is ugly.
-            let id = v.id;
-            let state = v.state;
-            let is_resized = v.is_resized();
-            let extent = v.extent();
+        // If any view is dirty, record a snapshot of it. This is usually only
+        // the active view, but some commands edit all views at once.
+        let dirty: Vec<_> = session
+            .views
+            .iter()
+            .filter(|v| v.is_dirty())
+            .map(|v| (v.id, v.state, v.is_resized(), v.extent()))
+            .collect();
 
+        for (id, state, is_resized, extent) in dirty {
             if let Some(vr) = session.views.get_mut(id) {
                 let v_data = view_data.get_mut(&id).unwrap();
 
@@ -870,6 +986,8 @@ impl<'a> renderer::Renderer<'a> for Renderer {
                     }
                     ViewState::Okay | ViewState::Damaged(_) => {}
                 }
+                // Indexed views are rendered from the recorded indices.
+                v_data.upload_indexed(vr, &mut self.ctx)?;
             }
         }
 
@@ -938,13 +1056,18 @@ impl Renderer {
                     // FIXME: This should be done when the view is added in the ViewManager.
                     if let Some((s, pixels)) = session.views.get_snapshot_safe(id) {
                         let (w, h) = (s.width(), s.height());
+                        let mut view_data = ViewData::new(w, h, Some(pixels), &mut self.ctx);
 
-                        self.view_data
-                            .insert(id, ViewData::new(w, h, Some(pixels), &mut self.ctx));
+                        view_data.upload_indexed(session.view(id), &mut self.ctx)?;
+                        self.view_data.insert(id, view_data);
                     }
                 }
                 Effect::ViewRemoved(id) => {
                     self.view_data.remove(&id);
+
+                    if self.preview_view == Some(id) {
+                        self.preview_view = None;
+                    }
                 }
                 Effect::ViewOps(id, ops) => {
                     self.handle_view_ops(session.view(id), &ops)?;
@@ -964,6 +1087,29 @@ impl Renderer {
                 Effect::ViewPaintFinal(shapes) => {
                     shapes.into_iter().for_each(|s| self.final_batch.add(s));
                 }
+                Effect::ViewPreview(id, None) => {
+                    if self.preview_view == Some(id) {
+                        self.preview_view = None;
+                    }
+                }
+                Effect::ViewPreview(id, Some(pixels)) => {
+                    let (w, h) = match session.views.get_snapshot_safe(id) {
+                        Some((s, _)) if (s.width() * s.height()) as usize == pixels.len() => {
+                            (s.width(), s.height())
+                        }
+                        _ => continue,
+                    };
+                    let [preview_w, preview_h] = self.preview.size();
+
+                    if preview_w != w || preview_h != h {
+                        self.preview = Texture::new(&mut self.ctx, [w, h], 0, self::SAMPLER)
+                            .map_err(RendererError::Texture)?;
+                    }
+                    self.preview
+                        .upload_raw(GenMipmaps::No, util::align_u8(&pixels))
+                        .map_err(RendererError::Texture)?;
+                    self.preview_view = Some(id);
+                }
                 Effect::ViewTouched(_) => {}
             }
         }
@@ -1014,11 +1160,15 @@ impl Renderer {
                         )
                         .map_err(Error::Texture)?;
                 }
-                ViewOp::Yank(src) => {
-                    let (_, pixels) = v.layer.get_snapshot_rect(&src.map(|n| n as i32)).unwrap();
+                ViewOp::Yank(src, mask) => {
+                    let (_, mut pixels) =
+                        v.layer.get_snapshot_rect(&src.map(|n| n as i32)).unwrap();
                     let (w, h) = (src.width() as u32, src.height() as u32);
                     let [paste_w, paste_h] = self.paste.size();
 
+                    if let Some(mask) = mask {
+                        mask.apply(&mut pixels);
+                    }
                     if paste_w != w || paste_h != h {
                         self.paste =
                             Texture::new(&mut self.ctx, [w as u32, h as u32], 0, self::SAMPLER)
@@ -1030,9 +1180,13 @@ impl Renderer {
                         .upload_raw(GenMipmaps::No, body)
                         .map_err(Error::Texture)?;
                 }
-                ViewOp::Flip(src, dir) => {
+                ViewOp::Flip(src, dir, mask) => {
                     let (_, mut pixels) =
                         v.layer.get_snapshot_rect(&src.map(|n| n as i32)).unwrap();
+
+                    if let Some(mask) = mask {
+                        mask.apply(&mut pixels);
+                    }
                     let (w, h) = (src.width() as u32, src.height() as u32);
                     let [paste_w, paste_h] = self.paste.size();
 
@@ -1067,12 +1221,54 @@ impl Renderer {
                         .upload_raw(GenMipmaps::No, body)
                         .map_err(Error::Texture)?;
                 }
-                ViewOp::Paste(dst) => {
+                ViewOp::Transform(src, t, mask) => {
+                    let (_, mut pixels) = v.layer.get_snapshot_rect(src).unwrap();
+
+                    if let Some(mask) = mask {
+                        mask.apply(&mut pixels);
+                    }
+                    let (src_w, src_h) = (src.width() as u32, src.height() as u32);
+                    let (w, h) = t.size(src_w, src_h);
+                    let pixels = t.apply(&pixels, src_w, src_h);
+                    let [paste_w, paste_h] = self.paste.size();
+
+                    if paste_w != w || paste_h != h {
+                        self.paste = Texture::new(&mut self.ctx, [w, h], 0, self::SAMPLER)
+                            .map_err(Error::Texture)?;
+                    }
+                    let body = util::align_u8(&pixels);
+
+                    self.paste
+                        .upload_raw(GenMipmaps::No, body)
+                        .map_err(Error::Texture)?;
+                }
+                ViewOp::Paste(dst, clip) => {
                     let [paste_w, paste_h] = self.paste.size();
+                    let src = Rect::origin(paste_w as f32, paste_h as f32);
+                    let (src, dst) = match clip {
+                        // Only blit the part of the paste buffer that maps to the
+                        // clipped area, since the buffer may be scaled.
+                        Some(clip) => {
+                            let clipped = dst.intersection(*clip);
+                            let sx = paste_w as f32 / dst.width() as f32;
+                            let sy = paste_h as f32 / dst.height() as f32;
+
+                            (
+                                Rect::new(
+                                    (clipped.x1 - dst.x1) as f32 * sx,
+                                    (clipped.y1 - dst.y1) as f32 * sy,
+                                    (clipped.x2 - dst.x1) as f32 * sx,
+                                    (clipped.y2 - dst.y1) as f32 * sy,
+                                ),
+                                clipped,
+                            )
+                        }
+                        None => (src, *dst),
+                    };
                     let batch = sprite2d::Batch::singleton(
                         paste_w,
                         paste_h,
-                        Rect::origin(paste_w as f32, paste_h as f32),
+                        src,
                         dst.map(|n| n as f32),
                         ZDepth::default(),
                         Rgba::TRANSPARENT,
@@ -1098,22 +1294,29 @@ impl Renderer {
                         .upload_part_raw(GenMipmaps::No, [*x as u32, *y as u32], [1, 1], texels)
                         .map_err(Error::Texture)?;
                 }
+                ViewOp::SetPixels(pixels) => {
+                    self.view_data
+                        .get_mut(&v.id)
+                        .expect("views must have associated view data")
+                        .layer
+                        .upload(util::align_u8(pixels))?;
+                }
             }
         }
         Ok(())
     }
 
     fn handle_view_damaged(&mut self, view: &View<ViewResource>) -> Result<(), RendererError> {
-        let layer = &mut self
+        let view_data = self
             .view_data
             .get_mut(&view.id)
-            .expect("views must have associated view data")
-            .layer;
+            .expect("views must have associated view data");
 
         let (_, pixels) = view.layer.current_snapshot();
 
-        layer.clear()?;
-        layer.upload(util::align_u8(pixels))?;
+        view_data.layer.clear()?;
+        view_data.layer.upload(util::align_u8(pixels))?;
+        view_data.upload_indexed(view, &mut self.ctx)?;
 
         Ok(())
     }
@@ -1158,6 +1361,7 @@ impl Renderer {
 
             l.upload_part([0, vh - th], [tw, th], texels)?;
         }
+        view_data.upload_indexed(view, &mut self.ctx)?;
 
         self.view_data.insert(view.id, view_data);
 
@@ -0,0 +1,228 @@
+use crate::brush::Pattern;
+
+use crate::gfx::math::{Point2, Vector2};
+use crate::gfx::rect::Rect;
+use crate::gfx::shape2d::{Fill, Rotation, Shape, Stroke};
+use crate::gfx::{Rgba8, ZDepth};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Gradient kind.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum GradientKind {
+    /// Colors change along the gradient line.
+    Linear,
+    /// Colors change with the distance to the start of the gradient line.
+    Radial,
+}
+
+/// How to transition between the colors of a gradient. Since gradients only
+/// ever use the colors of their ramp, transitions are dithered.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Dithering {
+    /// No dithering: each pixel gets the closest color of the ramp.
+    None,
+    /// Ordered (Bayer) dithering.
+    Ordered,
+    /// Error diffusion (Floyd-Steinberg) dithering.
+    Diffusion,
+}
+
+impl FromStr for Dithering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "ordered" => Ok(Self::Ordered),
+            "diffusion" => Ok(Self::Diffusion),
+            other => Err(format!(
+                "unknown dithering `{}`, must be one of `none`, `ordered` or `diffusion`",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Dithering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => "none".fmt(f),
+            Self::Ordered => "ordered".fmt(f),
+            Self::Diffusion => "diffusion".fmt(f),
+        }
+    }
+}
+
+/// A gradient, spanning a line between two pixels.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    pub dithering: Dithering,
+    /// Colors of the gradient, from start to end.
+    pub ramp: Vec<Rgba8>,
+    /// Start of the gradient line.
+    pub start: Point2<f32>,
+    /// End of the gradient line.
+    pub end: Point2<f32>,
+}
+
+impl Gradient {
+    pub fn new(
+        kind: GradientKind,
+        dithering: Dithering,
+        ramp: Vec<Rgba8>,
+        start: Point2<i32>,
+        end: Point2<i32>,
+    ) -> Self {
+        assert!(!ramp.is_empty(), "gradient ramps cannot be empty");
+
+        // Use the pixel centers.
+        let center = |p: Point2<i32>| Point2::new(p.x as f32 + 0.5, p.y as f32 + 0.5);
+
+        Self {
+            kind,
+            dithering,
+            ramp,
+            start: center(start),
+            end: center(end),
+        }
+    }
+
+    /// Return the position of the given pixel along the gradient, between `0` and `1`.
+    fn position(&self, p: Point2<i32>) -> f32 {
+        let p = Vector2::new(p.x as f32 + 0.5, p.y as f32 + 0.5);
+        let start = Vector2::new(self.start.x, self.start.y);
+        let line = Vector2::new(self.end.x, self.end.y) - start;
+        let length = line.magnitude();
+
+        if length == 0. {
+            return 1.;
+        }
+        let t = match self.kind {
+            GradientKind::Linear => Vector2::dot(p - start, line) / (length * length),
+            GradientKind::Radial => (p - start).magnitude() / length,
+        };
+        t.clamp(0., 1.)
+    }
+
+    /// Return the color of each of the given pixels, in view coordinates.
+    pub fn colors(&self, pixels: &[Point2<i32>]) -> Vec<(Point2<i32>, Rgba8)> {
+        let last = (self.ramp.len() - 1) as f32;
+
+        match self.dithering {
+            Dithering::None => pixels
+                .iter()
+                .map(|p| {
+                    let index = (self.position(*p) * last).round();
+                    (*p, self.ramp[index as usize])
+                })
+                .collect(),
+            Dithering::Ordered => pixels
+                .iter()
+                .map(|p| {
+                    let index = self.position(*p) * last;
+                    let (floor, fract) = (index.floor(), index.fract());
+                    // Pick the next color on a fraction of the pixels that matches
+                    // how close we are to it.
+                    let level = (fract * 16.).round() as u8;
+                    let index = if Pattern::Bayer4(level).is_set(p.x, p.y) {
+                        floor + 1.
+                    } else {
+                        floor
+                    };
+                    (*p, self.ramp[index.min(last) as usize])
+                })
+                .collect(),
+            Dithering::Diffusion => self.diffuse(pixels),
+        }
+    }
+
+    /// Return the color of each of the given pixels, using error diffusion.
+    fn diffuse(&self, pixels: &[Point2<i32>]) -> Vec<(Point2<i32>, Rgba8)> {
+        let last = (self.ramp.len() - 1) as f32;
+        let mut errors: HashMap<Point2<i32>, f32> = pixels.iter().map(|p| (*p, 0.)).collect();
+        let mut sorted = pixels.to_vec();
+        let mut colors = Vec::with_capacity(pixels.len());
+
+        // Process pixels from the top row down, and from left to right, so that the
+        // error is always diffused to pixels that haven't been processed yet.
+        sorted.sort_by_key(|p| (-p.y, p.x));
+
+        for p in sorted {
+            let value = self.position(p) * last + errors[&p];
+            let index = value.round().clamp(0., last);
+            let error = value - index;
+
+            for (dx, dy, weight) in &[(1, 0, 7.), (-1, -1, 3.), (0, -1, 5.), (1, -1, 1.)] {
+                if let Some(e) = errors.get_mut(&Point2::new(p.x + dx, p.y + dy)) {
+                    *e += error * weight / 16.;
+                }
+            }
+            colors.push((p, self.ramp[index as usize]));
+        }
+        colors
+    }
+
+    /// Return the shapes to paint the gradient over the given pixels.
+    pub fn shapes(&self, pixels: &[Point2<i32>]) -> Vec<Shape> {
+        self.colors(pixels)
+            .into_iter()
+            .map(|(p, color)| {
+                let (x, y) = (p.x as f32, p.y as f32);
+
+                Shape::Rectangle(
+                    Rect::new(x, y, x + 1., y + 1.),
+                    ZDepth::default(),
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(color.into()),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_linear_gradient() {
+        let (a, b) = (Rgba8::BLACK, Rgba8::WHITE);
+        let pixels: Vec<_> = (0..8)
+            .flat_map(|y| (0..9).map(move |x| Point2::new(x, y)))
+            .collect();
+
+        for dithering in &[Dithering::None, Dithering::Ordered, Dithering::Diffusion] {
+            let gradient = Gradient::new(
+                GradientKind::Linear,
+                *dithering,
+                vec![a, b],
+                Point2::new(0, 0),
+                Point2::new(8, 0),
+            );
+            let colors = gradient.colors(&pixels);
+            let color_at = |x, y| {
+                colors
+                    .iter()
+                    .find(|(p, _)| *p == Point2::new(x, y))
+                    .map(|(_, c)| *c)
+                    .unwrap()
+            };
+
+            // The ends of the gradient are never dithered.
+            for y in 0..8 {
+                assert_eq!(color_at(0, y), a, "{}", dithering);
+                assert_eq!(color_at(8, y), b, "{}", dithering);
+            }
+            // The middle column mixes both colors.
+            if *dithering != Dithering::None {
+                let count = (0..8).filter(|y| color_at(4, *y) == b).count();
+                assert!(count > 0 && count < 8, "{}", dithering);
+            }
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::gfx::color::Rgba8;
+use crate::gfx::color::{Rgb8, Rgba8};
+
+use gif::{self, SetParameter};
 
 use std::convert::TryFrom;
 use std::ffi::OsStr;
@@ -6,6 +8,7 @@ use std::fmt;
 use std::fs::File;
 use std::io;
 use std::path::{self, PathBuf};
+use std::time;
 
 use crate::pixels;
 use crate::util;
@@ -105,10 +108,13 @@ pub fn read<R: io::Read>(reader: R) -> io::Result<(Vec<u8>, u32, u32)> {
         .read_info()
         .map_err(|_e| io::Error::new(io::ErrorKind::InvalidData, "decoding failed"))?;
 
-    if info.color_type != png::ColorType::RGBA {
+    // Indexed images are expanded to RGB, or RGBA if they have transparency.
+    if info.bit_depth != png::BitDepth::Eight
+        || !matches!(info.color_type, png::ColorType::RGBA | png::ColorType::RGB)
+    {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
-            "only 8-bit RGBA images are supported",
+            "only 8-bit RGBA, RGB and indexed images are supported",
         ));
     }
 
@@ -119,6 +125,12 @@ pub fn read<R: io::Read>(reader: R) -> io::Result<(Vec<u8>, u32, u32)> {
         .next_frame(&mut buffer)
         .map_err(|_e| io::Error::new(io::ErrorKind::InvalidData, "decoding failed"))?;
 
+    if info.color_type == png::ColorType::RGB {
+        buffer = buffer
+            .chunks(3)
+            .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 0xff])
+            .collect();
+    }
     Ok((buffer, width, height))
 }
 
@@ -160,12 +172,155 @@ pub fn write<W: io::Write>(out: W, w: u32, h: u32, scale: u32, pixels: &[Rgba8])
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
 }
 
+/// Save an indexed image as a png with a palette. Each pixel is an index into
+/// the palette, which has at most 256 colors.
+pub fn save_indexed<P: AsRef<path::Path>>(
+    path: P,
+    w: u32,
+    h: u32,
+    scale: u32,
+    pixels: &[u8],
+    palette: &[Rgba8],
+) -> io::Result<()> {
+    let f = File::create(path.as_ref())?;
+    let out = &mut io::BufWriter::new(f);
+
+    self::write_indexed(out, w, h, scale, pixels, palette)
+}
+
+pub fn write_indexed<W: io::Write>(
+    out: W,
+    w: u32,
+    h: u32,
+    scale: u32,
+    pixels: &[u8],
+    palette: &[Rgba8],
+) -> io::Result<()> {
+    assert!(palette.len() <= 256);
+
+    let mut encoder = png::Encoder::new(out, w * scale, h * scale);
+    let colors: Vec<Rgb8> = palette.iter().cloned().map(Rgb8::from).collect();
+
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(util::align_u8(&colors).to_vec());
+
+    // Only write the alpha of the palette colors if some aren't opaque.
+    if palette.iter().any(|c| c.a != 0xff) {
+        encoder.set_trns(palette.iter().map(|c| c.a).collect());
+    }
+    let mut writer = encoder.write_header()?;
+
+    let result = if scale == 1 {
+        writer.write_image_data(pixels)
+    } else {
+        writer.write_image_data(&pixels::scale(pixels, w, h, scale))
+    };
+    result.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Save frames of the given size as a looping gif, where each frame has its
+/// own delay. Pixels that aren't in the palette are transparent.
+pub fn save_gif<P: AsRef<path::Path>>(
+    path: P,
+    w: u32,
+    h: u32,
+    frames: &[(Vec<Rgba8>, time::Duration)],
+    palette: &[Rgba8],
+    scale: u32,
+) -> io::Result<usize> {
+    assert!(scale >= 1);
+
+    // Create a color palette for the gif, where the zero index is used
+    // for transparency.
+    let transparent: u8 = 0;
+    let mut palette = palette.to_vec();
+    palette.push(Rgba8::TRANSPARENT);
+    palette.sort();
+    palette.dedup();
+
+    assert!(palette[transparent as usize] == Rgba8::TRANSPARENT);
+
+    if palette.len() > 256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "gif palettes are limited to 255 colors",
+        ));
+    }
+    let (fw, fh) = ((w * scale) as usize, (h * scale) as usize);
+
+    // Discard alpha channel and convert to a `&[u8]`.
+    let colors: Vec<Rgb8> = palette.iter().cloned().map(Rgb8::from).collect();
+    let colors = util::align_u8(&colors);
+
+    let mut f = File::create(path.as_ref())?;
+    let mut encoder = gif::Encoder::new(&mut f, fw as u16, fh as u16, colors)?;
+    encoder.set(gif::Repeat::Infinite)?;
+
+    for (pixels, delay) in frames {
+        // Convert RGBA pixels into indexed pixels.
+        let mut image: Vec<u8> = pixels
+            .iter()
+            .map(|rgba| {
+                palette
+                    .binary_search(rgba)
+                    .map_or(transparent, |index| index as u8)
+            })
+            .collect();
+
+        if scale > 1 {
+            image = pixels::scale(&image, w, h, scale);
+        }
+        let mut frame =
+            gif::Frame::from_indexed_pixels(fw as u16, fh as u16, &image, Some(transparent));
+
+        // The gif encoder expects the frame delay in units of 10ms.
+        // If the delay is larger than a `u16` can hold, we ensure it doesn't overflow.
+        frame.delay = u128::min(delay.as_millis() / 10, u16::MAX as u128) as u16;
+        frame.dispose = gif::DisposalMethod::Background;
+
+        encoder.write_frame(&frame)?;
+    }
+    Ok(fw * fh * frames.len())
+}
+
 #[cfg(test)]
 mod test {
     use super::Path;
+    use crate::gfx::color::Rgba8;
     use std::convert::TryFrom;
     use std::path;
 
+    #[test]
+    fn test_indexed() {
+        let palette = [
+            Rgba8::TRANSPARENT,
+            Rgba8::new(0xff, 0, 0, 0xff),
+            Rgba8::WHITE,
+        ];
+        let mut out = Vec::new();
+
+        super::write_indexed(&mut out, 2, 1, 2, &[1, 0], &palette).unwrap();
+
+        let (pixels, w, h) = super::read(out.as_slice()).unwrap();
+        let pixels = Rgba8::align(&pixels);
+
+        assert_eq!((w, h), (4, 2));
+        assert_eq!(
+            &pixels[..4],
+            &[palette[1], palette[1], palette[0], palette[0]]
+        );
+
+        // Without transparency, indexed images are read as RGB.
+        let mut out = Vec::new();
+
+        super::write_indexed(&mut out, 1, 1, 1, &[0], &palette[1..]).unwrap();
+        assert_eq!(
+            super::read(out.as_slice()).unwrap().0,
+            vec![0xff, 0, 0, 0xff]
+        );
+    }
+
     #[test]
     fn test_image_path() {
         assert!(Path::try_from(path::Path::new("/")).is_err());
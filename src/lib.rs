@@ -26,17 +26,22 @@ mod brush;
 mod cmd;
 mod color;
 mod draw;
+mod editor;
 mod event;
 mod flood;
 mod font;
+mod fx;
 mod gl;
+mod gradient;
 mod history;
 mod image;
 mod io;
+mod mask;
 mod palette;
 mod parser;
 mod pixels;
 mod platform;
+mod quantize;
 mod renderer;
 mod sprite;
 mod timer;
@@ -0,0 +1,325 @@
+use crate::brush::Brush;
+use crate::cmd::Axis;
+use crate::pixels::Transform;
+
+use crate::gfx::math::Point2;
+use crate::gfx::rect::Rect;
+use crate::gfx::Rgba8;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How a new selection is combined with the current one.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum MaskOp {
+    /// Replace the current selection.
+    Replace,
+    /// Add to the current selection.
+    Union,
+    /// Remove from the current selection.
+    Subtract,
+    /// Keep the part of the current selection that is also in the new one.
+    Intersect,
+}
+
+impl Default for MaskOp {
+    fn default() -> Self {
+        Self::Replace
+    }
+}
+
+impl FromStr for MaskOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(Self::Replace),
+            "union" => Ok(Self::Union),
+            "subtract" => Ok(Self::Subtract),
+            "intersect" => Ok(Self::Intersect),
+            other => Err(format!(
+                "unknown selection operation `{}`, must be one of \
+                 `replace`, `union`, `subtract` or `intersect`",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MaskOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Replace => "replace".fmt(f),
+            Self::Union => "union".fmt(f),
+            Self::Subtract => "subtract".fmt(f),
+            Self::Intersect => "intersect".fmt(f),
+        }
+    }
+}
+
+/// A set of pixels within a rectangle, eg. a non-rectangular selection.
+/// Pixel coordinates are relative to the bottom-left corner of the rectangle.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Mask {
+    width: u32,
+    height: u32,
+    bits: Vec<bool>,
+}
+
+impl Mask {
+    /// Create a new, empty mask.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            bits: vec![false; (width * height) as usize],
+        }
+    }
+
+    /// Create a mask with all pixels set.
+    pub fn full(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            bits: vec![true; (width * height) as usize],
+        }
+    }
+
+    /// Create a mask from a set of pixels. Returns the origin of the mask,
+    /// and the mask cropped to the given pixels, or `None` if there are none.
+    pub fn from_points(points: &[Point2<i32>]) -> Option<(Point2<i32>, Self)> {
+        let first = points.first()?;
+        let mut bounds = Rect::new(first.x, first.y, first.x + 1, first.y + 1);
+
+        for p in points {
+            bounds.x1 = bounds.x1.min(p.x);
+            bounds.y1 = bounds.y1.min(p.y);
+            bounds.x2 = bounds.x2.max(p.x + 1);
+            bounds.y2 = bounds.y2.max(p.y + 1);
+        }
+        let mut mask = Self::new(bounds.width() as u32, bounds.height() as u32);
+
+        for p in points {
+            mask.set(p.x - bounds.x1, p.y - bounds.y1);
+        }
+        Some((Point2::new(bounds.x1, bounds.y1), mask))
+    }
+
+    /// Create a mask from a closed polygon, including its outline.
+    pub fn polygon(vertices: &[Point2<i32>]) -> Option<(Point2<i32>, Self)> {
+        let mut points = Vec::new();
+
+        Brush::polygon(vertices, &mut points);
+        Brush::fill_polygon(vertices, &mut points);
+
+        Self::from_points(&points)
+    }
+
+    /// Combine two masks positioned at the given origins. Returns the origin
+    /// of the resulting mask, and the mask cropped to its pixels, or `None` if
+    /// the result is empty.
+    pub fn combine(
+        op: MaskOp,
+        (a_origin, a): (Point2<i32>, &Mask),
+        (b_origin, b): (Point2<i32>, &Mask),
+    ) -> Option<(Point2<i32>, Self)> {
+        let x1 = a_origin.x.min(b_origin.x);
+        let y1 = a_origin.y.min(b_origin.y);
+        let x2 = (a_origin.x + a.width as i32).max(b_origin.x + b.width as i32);
+        let y2 = (a_origin.y + a.height as i32).max(b_origin.y + b.height as i32);
+        let mut points = Vec::new();
+
+        for y in y1..y2 {
+            for x in x1..x2 {
+                let in_a = a.contains(x - a_origin.x, y - a_origin.y);
+                let in_b = b.contains(x - b_origin.x, y - b_origin.y);
+                let selected = match op {
+                    MaskOp::Replace => in_b,
+                    MaskOp::Union => in_a || in_b,
+                    MaskOp::Subtract => in_a && !in_b,
+                    MaskOp::Intersect => in_a && in_b,
+                };
+                if selected {
+                    points.push(Point2::new(x, y));
+                }
+            }
+        }
+        Self::from_points(&points)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Check whether all pixels of the mask are set.
+    pub fn is_full(&self) -> bool {
+        self.bits.iter().all(|b| *b)
+    }
+
+    /// Check whether the given pixel is part of the mask.
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return false;
+        }
+        self.bits[(y * self.width as i32 + x) as usize]
+    }
+
+    /// Add the given pixel to the mask.
+    pub fn set(&mut self, x: i32, y: i32) {
+        if x >= 0 && y >= 0 && x < self.width as i32 && y < self.height as i32 {
+            self.bits[(y * self.width as i32 + x) as usize] = true;
+        }
+    }
+
+    /// Crop the mask positioned at the given origin to the given area. Returns
+    /// the origin of the cropped mask, or `None` if it is empty.
+    pub fn crop(&self, origin: Point2<i32>, area: Rect<i32>) -> Option<(Point2<i32>, Self)> {
+        let points: Vec<_> = self
+            .points()
+            .map(|p| Point2::new(p.x + origin.x, p.y + origin.y))
+            .filter(|p| area.contains(*p))
+            .collect();
+
+        Self::from_points(&points)
+    }
+
+    /// Iterate over the pixels of the mask.
+    pub fn points(&self) -> impl Iterator<Item = Point2<i32>> + '_ {
+        let w = self.width as i32;
+
+        self.bits
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b)
+            .map(move |(i, _)| Point2::new(i as i32 % w, i as i32 / w))
+    }
+
+    /// Clear the pixels of the given buffer that aren't part of the mask. The
+    /// buffer has the size of the mask, and is stored top row first.
+    pub fn apply(&self, pixels: &mut [Rgba8]) {
+        let (w, h) = (self.width as usize, self.height as usize);
+
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            if !self.contains((i % w) as i32, (h - i / w - 1) as i32) {
+                *pixel = Rgba8::TRANSPARENT;
+            }
+        }
+    }
+
+    /// Return the horizontal runs of pixels of the mask.
+    pub fn spans(&self) -> Vec<Rect<i32>> {
+        let mut spans = Vec::new();
+
+        for y in 0..self.height as i32 {
+            let mut start = None;
+
+            for x in 0..=self.width as i32 {
+                match (start, self.contains(x, y)) {
+                    (None, true) => start = Some(x),
+                    (Some(x1), false) => {
+                        spans.push(Rect::new(x1, y, x, y + 1));
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        spans
+    }
+
+    /// Flip the mask horizontally or vertically.
+    pub fn flip(&self, axis: Axis) -> Self {
+        let (w, h) = (self.width as i32, self.height as i32);
+        let mut flipped = Self::new(self.width, self.height);
+
+        for y in 0..h {
+            for x in 0..w {
+                if self.contains(x, y) {
+                    match axis {
+                        Axis::Horizontal => flipped.set(w - x - 1, y),
+                        Axis::Vertical => flipped.set(x, h - y - 1),
+                    }
+                }
+            }
+        }
+        flipped
+    }
+
+    /// Rotate or scale the mask, the same way the pixels it selects are.
+    pub fn transform(&self, t: Transform) -> Self {
+        let (w, h) = (self.width as i32, self.height as i32);
+
+        // Transforms operate on buffers stored top row first.
+        let bits: Vec<bool> = (0..h)
+            .rev()
+            .flat_map(|y| (0..w).map(move |x| self.contains(x, y)))
+            .collect();
+        let bits = t.apply(&bits, self.width, self.height);
+        let (width, height) = t.size(self.width, self.height);
+        let mut mask = Self::new(width, height);
+
+        for (i, _) in bits.iter().enumerate().filter(|(_, b)| **b) {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            mask.set(x as i32, (height - y - 1) as i32);
+        }
+        mask
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_polygon() {
+        let (origin, mask) = Mask::polygon(&[
+            Point2::new(2, 2),
+            Point2::new(6, 2),
+            Point2::new(6, 6),
+            Point2::new(2, 6),
+        ])
+        .unwrap();
+
+        assert_eq!(origin, Point2::new(2, 2));
+        assert_eq!((mask.width(), mask.height()), (5, 5));
+        assert!(mask.is_full());
+
+        let (_, triangle) =
+            Mask::polygon(&[Point2::new(0, 0), Point2::new(4, 0), Point2::new(0, 4)]).unwrap();
+
+        assert!(triangle.contains(0, 0));
+        assert!(triangle.contains(1, 1));
+        assert!(!triangle.contains(3, 3));
+    }
+
+    #[test]
+    fn test_combine() {
+        let a = Mask::full(4, 4);
+        let b = Mask::full(4, 4);
+        let (o, b_origin) = (Point2::new(0, 0), Point2::new(2, 0));
+
+        let (origin, union) = Mask::combine(MaskOp::Union, (o, &a), (b_origin, &b)).unwrap();
+        assert_eq!(origin, o);
+        assert_eq!((union.width(), union.height()), (6, 4));
+
+        let (origin, diff) = Mask::combine(MaskOp::Subtract, (o, &a), (b_origin, &b)).unwrap();
+        assert_eq!(origin, o);
+        assert_eq!((diff.width(), diff.height()), (2, 4));
+
+        let (origin, inter) = Mask::combine(MaskOp::Intersect, (o, &a), (b_origin, &b)).unwrap();
+        assert_eq!(origin, b_origin);
+        assert_eq!((inter.width(), inter.height()), (2, 4));
+
+        assert!(Mask::combine(MaskOp::Subtract, (o, &a), (o, &b)).is_none());
+        assert_eq!(
+            inter.spans(),
+            (0..4)
+                .map(|y| Rect::new(0, y, 2, y + 1))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,11 +1,587 @@
+use crate::color;
+use crate::quantize::{Metric, Remap};
 use crate::session::SessionCoords;
 
 use crate::gfx::Rgba8;
-use arrayvec::ArrayVec;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A shading ramp: an ordered range of palette indices, from `from` to `to`.
+/// Ramps can run in either direction.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Ramp {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl Ramp {
+    pub fn new(from: usize, to: usize) -> Self {
+        Self { from, to }
+    }
+
+    /// Check whether the given palette index is part of the ramp.
+    pub fn contains(&self, index: usize) -> bool {
+        index >= self.from.min(self.to) && index <= self.from.max(self.to)
+    }
+
+    /// Return the palette index `step` colors away from the given index,
+    /// staying within the ramp.
+    pub fn step(&self, index: usize, step: i32) -> usize {
+        let len = (self.from as i32 - self.to as i32).abs();
+        let dir = if self.to >= self.from { 1 } else { -1 };
+        let pos = (index as i32 - self.from as i32) * dir;
+
+        (self.from as i32 + (pos + step).clamp(0, len) * dir) as usize
+    }
+
+    /// Return the ramp after a color is inserted into the palette at the given
+    /// index. Colors inserted within the ramp become part of it.
+    fn inserted(self, index: usize) -> Self {
+        let (min, max) = (self.from.min(self.to), self.from.max(self.to));
+        let (min, max) = if index <= min {
+            (min + 1, max + 1)
+        } else if index <= max {
+            (min, max + 1)
+        } else {
+            (min, max)
+        };
+        self.with_bounds(min, max)
+    }
+
+    /// Return the ramp after the color at the given index is removed from the
+    /// palette, or `None` if it was the only color of the ramp.
+    fn removed(self, index: usize) -> Option<Self> {
+        let (min, max) = (self.from.min(self.to), self.from.max(self.to));
+
+        if index < min {
+            Some(self.with_bounds(min - 1, max - 1))
+        } else if index > max {
+            Some(self)
+        } else if min == max {
+            None
+        } else {
+            Some(self.with_bounds(min, max - 1))
+        }
+    }
+
+    /// Return the ramp after the color at index `from` is moved to index `to`.
+    /// The ramp ends follow their colors.
+    fn moved(self, from: usize, to: usize) -> Self {
+        let index = |i: usize| {
+            if i == from {
+                return to;
+            }
+            let i = if i > from { i - 1 } else { i };
+            if i >= to {
+                i + 1
+            } else {
+                i
+            }
+        };
+        Self::new(index(self.from), index(self.to))
+    }
+
+    /// Return the number of colors in the ramp.
+    pub fn len(&self) -> usize {
+        (self.from as i32 - self.to as i32).unsigned_abs() as usize + 1
+    }
+
+    /// Return the palette index at the given position in the ramp.
+    fn index(&self, pos: usize) -> usize {
+        if self.from <= self.to {
+            self.from + pos
+        } else {
+            self.from - pos
+        }
+    }
+
+    /// Return a ramp with the given bounds, in the direction of this ramp.
+    fn with_bounds(self, min: usize, max: usize) -> Self {
+        if self.from <= self.to {
+            Self::new(min, max)
+        } else {
+            Self::new(max, min)
+        }
+    }
+}
+
+/// A color cycling range. The colors of a range of palette indices rotate
+/// one step every `delay` milliseconds, moving from `from` towards `to`.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Cycle {
+    pub range: Ramp,
+    /// Delay between steps, in milliseconds.
+    pub delay: u64,
+}
+
+impl Cycle {
+    pub fn new(from: usize, to: usize, delay: u64) -> Self {
+        Self {
+            range: Ramp::new(from, to),
+            delay: delay.max(1),
+        }
+    }
+
+    /// Return the time it takes for the colors to come back to their
+    /// original position, in milliseconds.
+    pub fn period(&self) -> u64 {
+        self.range.len() as u64 * self.delay
+    }
+}
+
+/// Return palette colors with the color cycles applied, at the given time in
+/// milliseconds. Cycles that don't fit in the palette are ignored.
+pub fn cycle_colors(colors: &[Rgba8], cycles: &[Cycle], time: u64) -> Vec<Rgba8> {
+    let mut cycled = colors.to_vec();
+
+    for cycle in cycles {
+        let (range, len) = (cycle.range, cycle.range.len());
+
+        if range.from.max(range.to) >= colors.len() {
+            continue;
+        }
+        let offset = (time / cycle.delay) as usize % len;
+
+        for pos in 0..len {
+            cycled[range.index((pos + offset) % len)] = colors[range.index(pos)];
+        }
+    }
+    cycled
+}
+
+/// Return the times at which cycled colors change, starting at zero, and the
+/// time it takes for all cycles to loop, in milliseconds. Each period is
+/// given as a step delay and a number of steps. Returns `None` if there are
+/// more than `limit` changes in a loop.
+pub fn cycle_timeline(periods: &[(u64, u64)], limit: usize) -> Option<(Vec<u64>, u64)> {
+    fn gcd(a: u64, b: u64) -> u64 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    let periods: Vec<(u64, u64)> = periods
+        .iter()
+        .cloned()
+        .filter(|(delay, n)| *delay > 0 && *n > 0)
+        .collect();
+    let mut total: u64 = 1;
+
+    for (delay, n) in periods.iter() {
+        let period = delay.checked_mul(*n)?;
+        total = (total / gcd(total, period)).checked_mul(period)?;
+    }
+    if periods
+        .iter()
+        .any(|(delay, _)| total / delay > limit as u64)
+    {
+        return None;
+    }
+    let mut times: Vec<u64> = periods
+        .iter()
+        .flat_map(|(delay, _)| (0..total / delay).map(move |i| i * delay))
+        .collect();
+
+    times.sort_unstable();
+    times.dedup();
+
+    if times.len() > limit {
+        return None;
+    }
+    Some((times, total))
+}
+
+/// Color space ramps are generated in.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ColorSpace {
+    /// Hue, saturation and value.
+    Hsv,
+    /// OKLab, in its polar form: lightness, chroma and hue.
+    Oklab,
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hsv" => Ok(Self::Hsv),
+            "oklab" => Ok(Self::Oklab),
+            other => Err(format!(
+                "unknown color space `{}`, must be `hsv` or `oklab`",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hsv => "hsv".fmt(f),
+            Self::Oklab => "oklab".fmt(f),
+        }
+    }
+}
+
+/// Options for generating hue-shifted ramps.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct RampOptions {
+    pub space: ColorSpace,
+    /// How far the hue rotates at the ends of the ramp, in degrees. Shades
+    /// rotate towards blue, and highlights towards yellow.
+    pub hue_shift: f32,
+    /// How much saturation drops at the ends of the ramp, from `0` to `1`.
+    pub saturation: f32,
+    /// Value or lightness of the darkest and lightest colors, from `0` to `1`.
+    pub value: (f32, f32),
+}
+
+/// Generate a ramp of `count` colors going from dark to light, through the
+/// base color.
+pub fn hue_shifted_ramp(base: Rgba8, count: usize, options: &RampOptions) -> Vec<Rgba8> {
+    let (lo, hi) = options.value;
+    let (hue, sat, value) = match options.space {
+        ColorSpace::Hsv => color::to_hsv(base),
+        ColorSpace::Oklab => {
+            let (l, c, h) = color::to_oklch(base);
+            (h, c, l)
+        }
+    };
+    // Hues of blue and yellow in each color space.
+    let (shadow, highlight) = match options.space {
+        ColorSpace::Hsv => (240., 60.),
+        ColorSpace::Oklab => (264., 110.),
+    };
+    if count < 2 || hi <= lo {
+        return vec![base; count.min(1)];
+    }
+    // Place the base color where its value falls within the range.
+    let last = count - 1;
+    let k = (((value - lo) / (hi - lo)).clamp(0., 1.) * last as f32).round() as usize;
+
+    (0..count)
+        .map(|i| {
+            // Position relative to the base color, from `-1` to `1`.
+            let (t, target, end) = if i < k {
+                ((k - i) as f32 / k as f32, shadow, lo)
+            } else if i > k {
+                ((i - k) as f32 / (last - k) as f32, highlight, hi)
+            } else {
+                return base;
+            };
+            // Rotate the hue towards the target hue, without overshooting it.
+            let delta = (target - hue + 180f32).rem_euclid(360.) - 180.;
+            let shift = (options.hue_shift * t).min(delta.abs()) * delta.signum();
+
+            let h = hue + shift;
+            let s = sat * (1. - options.saturation * t * t);
+            let v = value + (end - value) * t;
+
+            match options.space {
+                ColorSpace::Hsv => color::from_hsv(h, s, v, base.a),
+                ColorSpace::Oklab => color::from_oklch(v, s, h, base.a),
+            }
+        })
+        .collect()
+}
+
+/// Parse the colors of a palette file. GIMP palettes (`.gpl`) are supported,
+/// as well as lists of hex colors, such as the output of `p/write` and the
+/// built-in palette scripts.
+pub fn parse_colors(input: &str) -> Result<Vec<Rgba8>, String> {
+    let mut lines = input.lines().map(str::trim).enumerate();
+    let gimp = input.lines().next().map(str::trim) == Some("GIMP Palette");
+    let mut colors = Vec::new();
+
+    if gimp {
+        lines.next();
+    }
+    for (i, line) in lines {
+        if line.is_empty()
+            || line.starts_with("--")
+            || line == "p/clear"
+            || (gimp && (line.starts_with('#') || line.contains(':')))
+        {
+            continue;
+        }
+        let color = if gimp {
+            let rgb = line
+                .split_whitespace()
+                .take(3)
+                .map(|c| c.parse::<u8>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|rgb| rgb.len() == 3);
+
+            rgb.map(|rgb| Rgba8::new(rgb[0], rgb[1], rgb[2], 0xff))
+        } else {
+            let hex = line.trim_start_matches("p/add").trim();
+            let rgb = hex
+                .get(..7)
+                .filter(|s| s.starts_with('#'))
+                .and_then(|s| Rgba8::from_str(s).ok());
+            let alpha = match hex.get(7..) {
+                Some("") => Some(0xff),
+                Some(a) if a.len() == 2 => u8::from_str_radix(a, 16).ok(),
+                _ => None,
+            };
+            rgb.and_then(|c| alpha.map(|a| Rgba8 { a, ..c }))
+        };
+        match color {
+            Some(c) => colors.push(c),
+            None => return Err(format!("invalid color {:?} on line {}", line, i + 1)),
+        }
+    }
+    Ok(colors)
+}
+
+/// Maps the colors of a palette to the colors of another palette of the same
+/// size, index by index. Used to make color variants of images.
+#[derive(Debug, Clone)]
+pub struct PaletteSwap {
+    map: BTreeMap<Rgba8, Rgba8>,
+}
+
+impl PaletteSwap {
+    /// Create a palette swap. If a color appears more than once in the source
+    /// palette, its first index is used.
+    pub fn new(from: &[Rgba8], to: &[Rgba8]) -> Result<Self, String> {
+        if from.len() != to.len() {
+            return Err(format!(
+                "palettes must have the same number of colors ({} and {})",
+                from.len(),
+                to.len()
+            ));
+        }
+        let mut map = BTreeMap::new();
+
+        for (a, b) in from.iter().zip(to.iter()) {
+            map.entry(*a).or_insert(*b);
+        }
+        Ok(Self { map })
+    }
+
+    /// Return the color the given color is swapped with. Colors that aren't
+    /// part of the source palette are unchanged.
+    pub fn apply(&self, color: Rgba8) -> Rgba8 {
+        self.map.get(&color).cloned().unwrap_or(color)
+    }
+}
+
+/// Palette of an indexed image, by name. Pixels of indexed images are stored
+/// as indices into the palette colors. Transparent pixels use the first
+/// transparent color, or the index past the last color if there is none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedPalette {
+    pub name: String,
+    pub colors: Vec<Rgba8>,
+}
+
+impl IndexedPalette {
+    pub fn new(name: String, colors: Vec<Rgba8>) -> Result<Self, String> {
+        if colors.is_empty() {
+            return Err(String::from("palette is empty"));
+        }
+        let max = if colors.iter().any(|c| c.a == 0) {
+            256
+        } else {
+            255
+        };
+        if colors.len() > max {
+            return Err(format!(
+                "indexed images are limited to {} colors with this palette, palette has {}",
+                max,
+                colors.len()
+            ));
+        }
+        Ok(Self { name, colors })
+    }
+
+    /// Return the color of the given index.
+    pub fn color(&self, index: u8) -> Rgba8 {
+        self.colors
+            .get(index as usize)
+            .cloned()
+            .unwrap_or(Rgba8::TRANSPARENT)
+    }
+
+    /// Return the colors of the given indices.
+    pub fn pixels(&self, indices: &[u8]) -> Vec<Rgba8> {
+        indices.iter().map(|i| self.color(*i)).collect()
+    }
+
+    /// Convert pixels to indices. Pixels that aren't in the palette take the
+    /// nearest palette color. If a color appears more than once in the palette,
+    /// its first index is used, unless the pixel already had one of the other
+    /// indices in `previous`. Returns the indices with the number of pixels
+    /// that weren't in the palette.
+    pub fn index(&self, pixels: &[Rgba8], previous: Option<&[u8]>) -> (Vec<u8>, usize) {
+        let mut map = BTreeMap::new();
+
+        for (i, c) in self.colors.iter().enumerate() {
+            map.entry(*c).or_insert(i as u8);
+        }
+        let transparent = self
+            .colors
+            .iter()
+            .position(|c| c.a == 0)
+            .unwrap_or(self.colors.len()) as u8;
+        let opaque: Vec<Rgba8> = self.colors.iter().cloned().filter(|c| c.a > 0).collect();
+        let remap = if opaque.is_empty() {
+            None
+        } else {
+            Some(Remap::new(&opaque, Metric::Rgb))
+        };
+        let mut snapped = 0;
+
+        let indices = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                if let Some(prev) = previous.and_then(|prev| prev.get(i)) {
+                    if self.color(*prev) == *p {
+                        return *prev;
+                    }
+                }
+                if let Some(index) = map.get(p) {
+                    return *index;
+                }
+                if p.a == 0 {
+                    return transparent;
+                }
+                snapped += 1;
+
+                match &remap {
+                    Some(remap) => map[&remap.map(*p)],
+                    None => transparent,
+                }
+            })
+            .collect();
+
+        (indices, snapped)
+    }
+
+    /// Return the colors of an image with the given indices. Images with
+    /// transparent pixels and no transparent palette color get an extra,
+    /// transparent, color.
+    pub fn image_colors(&self, indices: &[u8]) -> Vec<Rgba8> {
+        let mut colors = self.colors.clone();
+
+        if indices.iter().any(|i| *i as usize >= colors.len()) {
+            colors.push(Rgba8::TRANSPARENT);
+        }
+        colors
+    }
+}
+
+/// How many pixels of an image use each color of a palette.
+#[derive(Debug, Default, PartialEq)]
+pub struct ColorStats {
+    /// Pixel count of each palette color, by index. If a color appears more
+    /// than once in the palette, its pixels are counted at its first index.
+    pub counts: Vec<usize>,
+    /// Colors that aren't in the palette, with their pixel count, most used first.
+    pub off_palette: Vec<(Rgba8, usize)>,
+}
+
+impl ColorStats {
+    /// Count the colors of the given pixels. Transparent pixels are ignored,
+    /// unless the palette has a transparent color.
+    pub fn new(colors: &[Rgba8], pixels: &[Rgba8]) -> Self {
+        let mut indices = BTreeMap::new();
+        let mut counts = vec![0; colors.len()];
+        let mut off_palette = BTreeMap::new();
+
+        for (i, c) in colors.iter().enumerate() {
+            indices.entry(*c).or_insert(i);
+        }
+        for p in pixels {
+            match indices.get(p) {
+                Some(i) => counts[*i] += 1,
+                None if p.a == 0 => {}
+                None => *off_palette.entry(*p).or_insert(0) += 1,
+            }
+        }
+        let mut off_palette: Vec<_> = off_palette.into_iter().collect();
+        off_palette.sort_by_key(|(c, n)| (std::cmp::Reverse(*n), c.r, c.g, c.b, c.a));
+
+        Self {
+            counts,
+            off_palette,
+        }
+    }
+
+    /// Return the indices of the palette colors that aren't used.
+    pub fn unused(&self) -> Vec<usize> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n == 0)
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Pixels of the active view to highlight.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Highlight {
+    /// Nothing is highlighted.
+    None,
+    /// Pixels of the hovered palette color.
+    Hover,
+    /// Pixels that aren't in the palette.
+    OffPalette,
+}
+
+impl FromStr for Highlight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "hover" => Ok(Self::Hover),
+            "off-palette" => Ok(Self::OffPalette),
+            other => Err(format!(
+                "unknown highlight `{}`, must be one of `none`, `hover` or `off-palette`",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Highlight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => "none".fmt(f),
+            Self::Hover => "hover".fmt(f),
+            Self::OffPalette => "off-palette".fmt(f),
+        }
+    }
+}
+
+/// The colors, shading ramps and color cycles of a palette that isn't in use.
+#[derive(Clone, Debug, Default)]
+pub struct PaletteData {
+    pub colors: Vec<Rgba8>,
+    pub ramps: Vec<Ramp>,
+    pub cycles: Vec<Cycle>,
+}
 
 pub struct Palette {
-    pub colors: ArrayVec<[Rgba8; 256]>,
+    /// Name of the palette, used to switch between palettes.
+    pub name: String,
+    pub colors: Vec<Rgba8>,
+    /// Shading ramps. If there are none, the whole palette is used as a ramp.
+    pub ramps: Vec<Ramp>,
+    /// Color cycling ranges.
+    pub cycles: Vec<Cycle>,
     pub hover: Option<Rgba8>,
+    /// Index of the hovered color.
+    pub hover_index: Option<usize>,
     pub cellsize: f32,
     pub height: usize,
     pub x: f32,
@@ -13,10 +589,17 @@ pub struct Palette {
 }
 
 impl Palette {
+    /// Name of the palette in use at startup.
+    pub const DEFAULT: &'static str = "default";
+
     pub fn new(cellsize: f32, height: usize) -> Self {
         Self {
-            colors: ArrayVec::new(),
+            name: String::from(Self::DEFAULT),
+            colors: Vec::new(),
+            ramps: Vec::new(),
+            cycles: Vec::new(),
             hover: None,
+            hover_index: None,
             cellsize,
             height,
             x: 0.,
@@ -25,9 +608,90 @@ impl Palette {
     }
 
     pub fn add(&mut self, color: Rgba8) {
-        if !self.colors.contains(&color) {
-            self.colors.push(color);
+        self.colors.push(color);
+    }
+
+    /// Insert a color at the given index, shifting the colors after it.
+    /// Shading ramps are adjusted to keep referring to the same colors.
+    pub fn insert(&mut self, index: usize, color: Rgba8) {
+        self.colors.insert(index, color);
+
+        for ramp in self.ramps.iter_mut() {
+            *ramp = ramp.inserted(index);
+        }
+        for cycle in self.cycles.iter_mut() {
+            cycle.range = cycle.range.inserted(index);
         }
+        self.update_hover();
+    }
+
+    /// Remove the color at the given index. Shading ramps are adjusted to
+    /// keep referring to the same colors.
+    pub fn remove(&mut self, index: usize) -> Rgba8 {
+        let color = self.colors.remove(index);
+
+        self.ramps = self.ramps.iter().filter_map(|r| r.removed(index)).collect();
+        self.cycles = self
+            .cycles
+            .iter()
+            .filter_map(|c| {
+                c.range.removed(index).map(|range| Cycle {
+                    range,
+                    delay: c.delay,
+                })
+            })
+            .collect();
+        self.update_hover();
+
+        color
+    }
+
+    /// Move the color at index `from` to index `to`. The ends of shading ramps
+    /// and cycles follow their colors.
+    pub fn move_color(&mut self, from: usize, to: usize) {
+        let color = self.colors.remove(from);
+        self.colors.insert(to, color);
+
+        for ramp in self.ramps.iter_mut() {
+            *ramp = ramp.moved(from, to);
+        }
+        for cycle in self.cycles.iter_mut() {
+            cycle.range = cycle.range.moved(from, to);
+        }
+        self.update_hover();
+    }
+
+    /// Swap the colors at the given indices. Shading ramps are unchanged.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.colors.swap(a, b);
+        self.update_hover();
+    }
+
+    /// Set the color at the given index, and return the previous color.
+    pub fn set(&mut self, index: usize, color: Rgba8) -> Rgba8 {
+        let old = std::mem::replace(&mut self.colors[index], color);
+        self.update_hover();
+
+        old
+    }
+
+    /// Replace the palette with another named palette, and return the previous
+    /// name and data.
+    pub fn replace(&mut self, name: String, data: PaletteData) -> (String, PaletteData) {
+        let old = PaletteData {
+            colors: std::mem::replace(&mut self.colors, data.colors),
+            ramps: std::mem::replace(&mut self.ramps, data.ramps),
+            cycles: std::mem::replace(&mut self.cycles, data.cycles),
+        };
+        self.update_hover();
+
+        (std::mem::replace(&mut self.name, name), old)
+    }
+
+    /// Update the hovered color after the palette has changed.
+    fn update_hover(&mut self) {
+        self.hover_index = self.hover_index.filter(|i| *i < self.colors.len());
+        self.hover = self.hover_index.map(|i| self.colors[i]);
     }
 
     pub fn gradient(&mut self, colorstart: Rgba8, colorend: Rgba8, number: usize) {
@@ -51,6 +715,26 @@ impl Palette {
 
     pub fn clear(&mut self) {
         self.colors.clear();
+        self.ramps.clear();
+        self.cycles.clear();
+    }
+
+    /// Return the color `step` colors away from the given color in its shading
+    /// ramp, or `None` if the color isn't part of a ramp, or if it wouldn't change.
+    pub fn shade(&self, color: Rgba8, step: i32) -> Option<Rgba8> {
+        let index = self.colors.iter().position(|c| *c == color)?;
+        let ramp = if self.ramps.is_empty() {
+            Ramp::new(0, self.size() - 1)
+        } else {
+            *self.ramps.iter().find(|r| r.contains(index))?
+        };
+        let shade = self.colors.get(ramp.step(index, step))?;
+
+        if *shade == color {
+            None
+        } else {
+            Some(*shade)
+        }
     }
 
     pub fn size(&self) -> usize {
@@ -75,6 +759,7 @@ impl Palette {
 
         if x >= width || y >= height || x < 0 || y < 0 {
             self.hover = None;
+            self.hover_index = None;
             return;
         }
 
@@ -83,14 +768,278 @@ impl Palette {
 
         let index = y + x * (height / cellsize);
 
-        self.hover = if index < size {
+        self.hover_index = if index < size {
             // We index from the back because the palette is reversed
             // before it is displayed, due to the Y axis pointing up,
             // where as the palette is created starting at the top
             // and going down.
-            Some(self.colors[self.size() - index as usize - 1])
+            Some(self.size() - index as usize - 1)
         } else {
             None
         };
+        self.hover = self.hover_index.map(|i| self.colors[i]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shade() {
+        let mut palette = Palette::new(12., 64);
+        let colors = [
+            Rgba8::new(0x10, 0, 0, 0xff),
+            Rgba8::new(0x20, 0, 0, 0xff),
+            Rgba8::new(0x30, 0, 0, 0xff),
+            Rgba8::new(0, 0, 0x10, 0xff),
+            Rgba8::new(0, 0, 0x20, 0xff),
+        ];
+        for c in colors.iter() {
+            palette.add(*c);
+        }
+
+        // Without ramps, the whole palette is a ramp.
+        assert_eq!(palette.shade(colors[2], 1), Some(colors[3]));
+        assert_eq!(palette.shade(colors[4], 1), None);
+
+        palette.ramps.push(Ramp::new(0, 2));
+        palette.ramps.push(Ramp::new(4, 3));
+
+        assert_eq!(palette.shade(colors[0], 1), Some(colors[1]));
+        assert_eq!(palette.shade(colors[1], -1), Some(colors[0]));
+        assert_eq!(palette.shade(colors[2], 1), None, "end of ramp");
+        // Ramps can be reversed.
+        assert_eq!(palette.shade(colors[4], 1), Some(colors[3]));
+        assert_eq!(palette.shade(colors[3], 1), None);
+        assert_eq!(palette.shade(colors[3], -1), Some(colors[4]));
+        assert_eq!(palette.shade(Rgba8::WHITE, 1), None, "not in palette");
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut palette = Palette::new(12., 64);
+        let (a, b, c) = (
+            Rgba8::new(0x10, 0, 0, 0xff),
+            Rgba8::new(0x20, 0, 0, 0xff),
+            Rgba8::new(0x30, 0, 0, 0xff),
+        );
+        palette.add(a);
+        palette.add(b);
+        palette.add(b);
+        palette.add(c);
+        assert_eq!(palette.size(), 4, "duplicates are allowed");
+
+        palette.ramps.push(Ramp::new(3, 1));
+        palette.remove(2);
+        assert_eq!(palette.colors, vec![a, b, c]);
+        assert_eq!(palette.ramps, vec![Ramp::new(2, 1)]);
+
+        palette.insert(0, Rgba8::WHITE);
+        assert_eq!(palette.ramps, vec![Ramp::new(3, 2)]);
+        palette.insert(3, Rgba8::BLACK);
+        assert_eq!(palette.colors, vec![Rgba8::WHITE, a, b, Rgba8::BLACK, c]);
+        assert_eq!(palette.ramps, vec![Ramp::new(4, 2)]);
+
+        palette.move_color(4, 0);
+        assert_eq!(palette.colors, vec![c, Rgba8::WHITE, a, b, Rgba8::BLACK]);
+        assert_eq!(
+            palette.ramps,
+            vec![Ramp::new(0, 3)],
+            "ramp ends follow their color"
+        );
+
+        palette.move_color(0, 4);
+        assert_eq!(palette.colors, vec![Rgba8::WHITE, a, b, Rgba8::BLACK, c]);
+        assert_eq!(palette.ramps, vec![Ramp::new(4, 2)]);
+
+        palette.remove(2);
+        palette.remove(3);
+        palette.remove(2);
+        assert!(palette.ramps.is_empty());
+    }
+
+    #[test]
+    fn test_hue_shifted_ramp() {
+        let base = Rgba8::new(0x40, 0xa0, 0x40, 0xff);
+
+        for space in &[ColorSpace::Hsv, ColorSpace::Oklab] {
+            let options = RampOptions {
+                space: *space,
+                hue_shift: 30.,
+                saturation: 0.2,
+                value: (0.1, 0.95),
+            };
+            let ramp = hue_shifted_ramp(base, 5, &options);
+            let hue = |c: Rgba8| color::to_hsv(c).0;
+            let brightness = |c: &Rgba8| c.r as u32 + c.g as u32 + c.b as u32;
+
+            assert_eq!(ramp.len(), 5, "{}", space);
+            assert!(ramp.contains(&base), "{}", space);
+            assert!(
+                ramp.windows(2)
+                    .all(|w| brightness(&w[0]) < brightness(&w[1])),
+                "{}: {:?}",
+                space,
+                ramp
+            );
+            // Shades shift towards blue, highlights towards yellow.
+            assert!(hue(ramp[0]) > hue(base), "{}", space);
+            assert!(hue(ramp[4]) < hue(base), "{}", space);
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut palette = Palette::new(12., 64);
+        palette.add(Rgba8::WHITE);
+        palette.ramps.push(Ramp::new(0, 0));
+        palette.hover_index = Some(0);
+
+        let (name, data) = palette.replace(
+            String::from("night"),
+            PaletteData {
+                colors: vec![Rgba8::BLACK],
+                ..PaletteData::default()
+            },
+        );
+        assert_eq!(name, Palette::DEFAULT);
+        assert_eq!(data.colors, vec![Rgba8::WHITE]);
+        assert_eq!(data.ramps, vec![Ramp::new(0, 0)]);
+        assert_eq!(palette.name, "night");
+        assert_eq!(palette.hover, Some(Rgba8::BLACK));
+    }
+
+    #[test]
+    fn test_parse_colors() {
+        let gpl = "GIMP Palette\nName: Test\nColumns: 2\n#\n255   0   0\tRed\n  0 128 255\n";
+        assert_eq!(
+            parse_colors(gpl),
+            Ok(vec![
+                Rgba8::new(0xff, 0, 0, 0xff),
+                Rgba8::new(0, 0x80, 0xff, 0xff)
+            ])
+        );
+
+        let hex = "-- Comment\np/clear\n#ff0000\n\np/add #00000080\n";
+        assert_eq!(
+            parse_colors(hex),
+            Ok(vec![
+                Rgba8::new(0xff, 0, 0, 0xff),
+                Rgba8::new(0, 0, 0, 0x80)
+            ])
+        );
+        assert!(parse_colors("#ff00").is_err());
+        assert!(parse_colors("GIMP Palette\n255 0\n").is_err());
+    }
+
+    #[test]
+    fn test_palette_swap() {
+        let (a, b, c) = (Rgba8::WHITE, Rgba8::BLACK, Rgba8::new(0xff, 0, 0, 0xff));
+        let swap = PaletteSwap::new(&[a, b, a], &[b, c, c]).unwrap();
+
+        assert_eq!(swap.apply(a), b, "first index wins");
+        assert_eq!(swap.apply(b), c);
+        assert_eq!(swap.apply(c), c, "not in palette");
+        assert!(PaletteSwap::new(&[a, b], &[a]).is_err());
+    }
+
+    #[test]
+    fn test_indexed_palette() {
+        let (a, b, c) = (Rgba8::WHITE, Rgba8::BLACK, Rgba8::new(0xff, 0, 0, 0xff));
+        let clear = Rgba8::new(0xff, 0, 0, 0);
+        let palette = IndexedPalette::new(String::from("test"), vec![a, b, a]).unwrap();
+
+        let (indices, snapped) = palette.index(&[b, a, clear, Rgba8::TRANSPARENT, c], None);
+        assert_eq!(indices, vec![1, 0, 3, 3, 1]);
+        assert_eq!(
+            snapped, 1,
+            "pixels not in the palette take the nearest color"
+        );
+        assert_eq!(
+            palette.pixels(&indices),
+            vec![b, a, Rgba8::TRANSPARENT, Rgba8::TRANSPARENT, b]
+        );
+        assert_eq!(
+            palette.image_colors(&indices),
+            vec![a, b, a, Rgba8::TRANSPARENT]
+        );
+        assert_eq!(
+            palette.image_colors(&[0, 1]),
+            vec![a, b, a],
+            "no transparent color if unused"
+        );
+
+        let (indices, _) = palette.index(&[a, a, b], Some(&[2, 1, 2]));
+        assert_eq!(
+            indices,
+            vec![2, 0, 1],
+            "pixels keep their index if the color is unchanged"
+        );
+
+        assert!(IndexedPalette::new(String::from("empty"), vec![]).is_err());
+        assert!(IndexedPalette::new(String::from("big"), vec![a; 256]).is_err());
+        assert!(IndexedPalette::new(String::from("big"), vec![Rgba8::TRANSPARENT; 256]).is_ok());
+    }
+
+    #[test]
+    fn test_color_stats() {
+        let (a, b, c, d) = (
+            Rgba8::WHITE,
+            Rgba8::BLACK,
+            Rgba8::new(0xff, 0, 0, 0xff),
+            Rgba8::new(0, 0xff, 0, 0xff),
+        );
+        let stats = ColorStats::new(&[a, b, a, c], &[a, a, c, d, Rgba8::TRANSPARENT, b, d, b]);
+
+        assert_eq!(stats.counts, vec![2, 2, 0, 1]);
+        assert_eq!(stats.unused(), vec![2]);
+        assert_eq!(stats.off_palette, vec![(d, 2)]);
+
+        let stats = ColorStats::new(&[Rgba8::TRANSPARENT], &[Rgba8::TRANSPARENT, c, d, d]);
+        assert_eq!(stats.counts, vec![1]);
+        assert_eq!(stats.off_palette, vec![(d, 2), (c, 1)]);
+
+        assert_eq!("off-palette".parse(), Ok(Highlight::OffPalette));
+        assert!("off".parse::<Highlight>().is_err());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let (a, b, c, d) = (
+            Rgba8::new(0x10, 0, 0, 0xff),
+            Rgba8::new(0x20, 0, 0, 0xff),
+            Rgba8::new(0x30, 0, 0, 0xff),
+            Rgba8::new(0x40, 0, 0, 0xff),
+        );
+        let colors = [a, b, c, d];
+        let forward = Cycle::new(0, 2, 100);
+        let backward = Cycle::new(3, 1, 50);
+
+        assert_eq!(forward.period(), 300);
+        assert_eq!(cycle_colors(&colors, &[forward], 99), colors.to_vec());
+        assert_eq!(cycle_colors(&colors, &[forward], 100), vec![c, a, b, d]);
+        assert_eq!(cycle_colors(&colors, &[forward], 700), vec![c, a, b, d]);
+        assert_eq!(cycle_colors(&colors, &[backward], 50), vec![a, c, d, b]);
+        assert_eq!(
+            cycle_colors(&colors, &[Cycle::new(2, 4, 10)], 10),
+            colors.to_vec(),
+            "out of range"
+        );
+
+        let (times, total) = cycle_timeline(&[(100, 3), (150, 2)], 64).unwrap();
+        assert_eq!(total, 300);
+        assert_eq!(times, vec![0, 100, 150, 200]);
+        assert_eq!(cycle_timeline(&[(1, 1000)], 64), None);
+
+        let mut palette = Palette::new(12., 64);
+        for c in colors.iter() {
+            palette.add(*c);
+        }
+        palette.cycles.push(backward);
+        palette.remove(0);
+        assert_eq!(palette.cycles, vec![Cycle::new(2, 0, 50)]);
+        palette.insert(3, a);
+        assert_eq!(palette.cycles, vec![Cycle::new(2, 0, 50)]);
     }
 }
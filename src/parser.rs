@@ -3,9 +3,14 @@ use memoir::*;
 
 use directories as dirs;
 
-use crate::brush::BrushMode;
-use crate::gfx::Rgba8;
+use crate::brush::{BrushMode, Pattern};
+use crate::color::Color;
+use crate::editor::{Change, Target};
+use crate::fx::{Connectivity, Placement};
+use crate::gradient::Dithering;
+use crate::mask::MaskOp;
 use crate::platform;
+use crate::quantize::Metric;
 use crate::session::{Direction, Mode, VisualState};
 
 use std::ffi::OsString;
@@ -116,6 +121,68 @@ impl Parse for platform::InputState {
     }
 }
 
+impl Parse for MaskOp {
+    fn parser() -> Parser<Self> {
+        word().try_map(|w| w.parse::<MaskOp>()).label("<op>")
+    }
+}
+
+impl Parse for Placement {
+    fn parser() -> Parser<Self> {
+        word()
+            .try_map(|w| w.parse::<Placement>())
+            .label("outside/inside")
+    }
+}
+
+impl Parse for Connectivity {
+    fn parser() -> Parser<Self> {
+        natural::<u8>()
+            .try_map(|n| match n {
+                4 => Ok(Connectivity::Four),
+                8 => Ok(Connectivity::Eight),
+                _ => Err("connectivity must be either `4` or `8`"),
+            })
+            .label("4/8")
+    }
+}
+
+impl Parse for Metric {
+    fn parser() -> Parser<Self> {
+        word()
+            .try_map(|w| w.parse::<Metric>())
+            .label("rgb/weighted/lab")
+    }
+}
+
+impl Parse for Dithering {
+    fn parser() -> Parser<Self> {
+        word()
+            .try_map(|w| w.parse::<Dithering>())
+            .label("none/ordered/diffusion")
+    }
+}
+
+impl Parse for Target {
+    fn parser() -> Parser<Self> {
+        token()
+            .try_map(|t| t.parse::<Target>())
+            .label("fg/bg/<index>")
+    }
+}
+
+impl Parse for Change {
+    fn parser() -> Parser<Self> {
+        optional(symbol('+').value(1.).or(symbol('-').value(-1.)))
+            .then(rational::<f32>())
+            .map(|(sign, n)| match sign {
+                Some(sign) => Change::By(sign * n),
+                None => Change::To(n),
+            })
+            .label("[+/-]<value>")
+    }
+}
+
 impl Parse for Direction {
     fn parser() -> Parser<Self> {
         character()
@@ -132,35 +199,34 @@ pub fn param<T: Parse>() -> Parser<T> {
     T::parser()
 }
 
-pub fn color() -> Parser<Rgba8> {
-    peek(
-        token()
-            .try_map(move |input| {
-                if input.is_empty() {
-                    return Err("expected color".to_owned());
-                }
-                if input.len() < 7 {
-                    return Err(format!("{:?} is not a valid color value", input));
-                }
-                let (s, alpha) = input.split_at(7);
-
-                match Rgba8::from_str(s) {
-                    Ok(color) => {
-                        if let Ok((a, _)) = symbol('/')
-                            .then(rational::<f64>())
-                            .map(|(_, a)| a)
-                            .parse(alpha)
-                        {
-                            Ok(color.alpha((a * std::u8::MAX as f64) as u8))
-                        } else {
-                            Ok(color)
-                        }
+/// Parse a color. Whitespace is allowed inside parentheses, eg. `rgb(1, 2, 3)`.
+pub fn color() -> Parser<Color> {
+    peek(Parser::new(
+        |input| {
+            let mut depth = 0;
+            let end = input
+                .char_indices()
+                .find(|(_, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
                     }
-                    Err(_) => Err(format!("malformed color value `{}`", s)),
-                }
-            })
-            .label("<color>"),
-    )
+                    depth <= 0 && c.is_whitespace()
+                })
+                .map_or(input.len(), |(i, _)| i);
+            let (token, rest) = input.split_at(end);
+
+            if token.is_empty() {
+                return Err(("expected color".into(), input));
+            }
+            match Color::from_str(token) {
+                Ok(color) => Ok((color, rest)),
+                Err(err) => Err((err.into(), input)),
+            }
+        },
+        "<color>",
+    ))
 }
 
 impl Parse for BrushMode {
@@ -174,11 +240,33 @@ impl Parse for BrushMode {
                     "perfect" => Ok((BrushMode::Perfect, p)),
                     "xsym" => Ok((BrushMode::XSym, p)),
                     "ysym" => Ok((BrushMode::YSym, p)),
+                    "dsym" => Ok((BrushMode::DSym, p)),
                     "xray" => Ok((BrushMode::XRay, p)),
+                    "shade" => Ok((BrushMode::Shade, p)),
+                    "lock-alpha" => Ok((BrushMode::LockAlpha, p)),
                     "line" => optional(whitespace())
                         .then(optional(natural()))
                         .parse(p)
                         .map(|((_, snap), p)| (BrushMode::Line(snap), p)),
+                    "radial" => optional(whitespace())
+                        .then(optional(natural::<u8>().label("<n>")))
+                        .try_map(|(_, n)| {
+                            let n = n.unwrap_or(4);
+
+                            if n >= 2 {
+                                Ok(n)
+                            } else {
+                                Err("radial symmetry must have at least 2 ways")
+                            }
+                        })
+                        .parse(p)
+                        .map(|(n, p)| (BrushMode::Radial(n), p)),
+                    "dither" => optional(whitespace())
+                        .then(optional(param::<Pattern>()))
+                        .parse(p)
+                        .map(|((_, pattern), p)| {
+                            (BrushMode::Dither(pattern.unwrap_or(Pattern::Checker)), p)
+                        }),
                     mode => Err((
                         memoir::result::Error::new(format!("unknown brush mode '{}'", mode)),
                         input,
@@ -190,6 +278,44 @@ impl Parse for BrushMode {
     }
 }
 
+impl Parse for Pattern {
+    fn parser() -> Parser<Self> {
+        Parser::new(
+            |input| {
+                let (id, p) = token().parse(input)?;
+                let level = |max: u8| {
+                    whitespace().then(natural::<u8>().label("<level>")).try_map(
+                        move |(_, level)| {
+                            if level <= max {
+                                Ok(level)
+                            } else {
+                                Err(format!("dither level must be between 0 and {}", max))
+                            }
+                        },
+                    )
+                };
+                match id.as_str() {
+                    "checker" => Ok((Pattern::Checker, p)),
+                    "bayer2" => level(4).parse(p).map(|(l, p)| (Pattern::Bayer2(l), p)),
+                    "bayer4" => level(16).parse(p).map(|(l, p)| (Pattern::Bayer4(l), p)),
+                    hex if hex.len() == 16 => match u64::from_str_radix(hex, 16) {
+                        Ok(bits) => Ok((Pattern::Custom(bits), p)),
+                        Err(_) => Err((
+                            memoir::result::Error::new(format!("invalid pattern '{}'", hex)),
+                            input,
+                        )),
+                    },
+                    pattern => Err((
+                        memoir::result::Error::new(format!("unknown pattern '{}'", pattern)),
+                        input,
+                    )),
+                }
+            },
+            "<pattern>",
+        )
+    }
+}
+
 impl Parse for Mode {
     fn parser() -> Parser<Self> {
         Parser::new(
@@ -227,9 +353,24 @@ pub fn tuple<O>(x: Parser<O>, y: Parser<O>) -> Parser<(O, O)> {
     x.skip(whitespace()).then(y)
 }
 
+/// Parse one or two palette indices. When a single index is given, it is
+/// the second one, and the first one is left out.
+pub fn palette_indices() -> Parser<(Option<usize>, usize)> {
+    natural::<usize>()
+        .then(optional(
+            whitespace().then(natural::<usize>()).map(|(_, n)| n),
+        ))
+        .map(|(a, b)| match b {
+            Some(b) => (Some(a), b),
+            None => (None, a),
+        })
+        .label("[<index>] <index>")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::gfx::Rgba8;
 
     #[test]
     fn test_paths() {
@@ -248,7 +389,33 @@ mod test {
         let ((a, b), rest) = p.parse("#ffaa44/0.5 #141414").unwrap();
 
         assert_eq!(rest, "");
-        assert_eq!(a, Rgba8::new(0xff, 0xaa, 0x44, 127));
-        assert_eq!(b, Rgba8::new(0x14, 0x14, 0x14, 255));
+        assert_eq!(a, Color::Rgba(Rgba8::new(0xff, 0xaa, 0x44, 127)));
+        assert_eq!(b, Color::Rgba(Rgba8::new(0x14, 0x14, 0x14, 255)));
+
+        let ((a, b), rest) = p.parse("rgb(255, 0, 0) p:3 #fff").unwrap();
+
+        assert_eq!(rest, " #fff");
+        assert_eq!(a, Color::Rgba(Rgba8::new(0xff, 0, 0, 0xff)));
+        assert_eq!(b, Color::Ref(Target::Palette(3)));
+        assert!(color().parse("").is_err());
+    }
+
+    #[test]
+    fn test_brush_mode() {
+        let p = param::<BrushMode>;
+
+        assert_eq!(
+            p().parse("dither").unwrap().0,
+            BrushMode::Dither(Pattern::Checker)
+        );
+        assert_eq!(
+            p().parse("dither bayer4 9").unwrap().0,
+            BrushMode::Dither(Pattern::Bayer4(9))
+        );
+        assert_eq!(
+            p().parse("dither aa55aa55aa55aa55").unwrap().0,
+            BrushMode::Dither(Pattern::Custom(0xaa55aa55aa55aa55))
+        );
+        assert!(param::<Pattern>().parse("bayer2 5").is_err());
     }
 }
@@ -75,3 +75,229 @@ pub fn scale<T: Default + Clone + Copy>(
     }
     output_buf
 }
+
+/// A transformation of an image that changes its size.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Transform {
+    /// Rotate clockwise by the given angle, in degrees.
+    Rotate(f32),
+    /// Scale by the given horizontal and vertical factors.
+    Scale(f32, f32),
+}
+
+impl Transform {
+    /// Return the size of an image of the given size, once transformed.
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            Self::Rotate(angle) => match quarter_turns(angle) {
+                Some(n) if n % 2 == 0 => (width, height),
+                Some(_) => (height, width),
+                None => {
+                    let (sin, cos) = angle.to_radians().sin_cos();
+                    let (w, h) = (width as f32, height as f32);
+
+                    (
+                        (w * cos.abs() + h * sin.abs()).round().max(1.) as u32,
+                        (w * sin.abs() + h * cos.abs()).round().max(1.) as u32,
+                    )
+                }
+            },
+            Self::Scale(x, y) => (
+                (width as f32 * x).round().max(1.) as u32,
+                (height as f32 * y).round().max(1.) as u32,
+            ),
+        }
+    }
+
+    /// Transform an image, stored top row first. The output has the size
+    /// returned by [`Transform::size`].
+    pub fn apply<T: Default + Copy + PartialEq>(
+        &self,
+        image: &[T],
+        width: u32,
+        height: u32,
+    ) -> Vec<T> {
+        assert_eq!(image.len(), (width * height) as usize);
+
+        match *self {
+            Self::Rotate(angle) => match quarter_turns(angle) {
+                Some(n) => rotate(image, width, height, n),
+                None => rotsprite(image, width, height, angle),
+            },
+            Self::Scale(..) => {
+                let (w, h) = self.size(width, height);
+                resize(image, width, height, w, h)
+            }
+        }
+    }
+}
+
+/// Return the number of clockwise quarter turns of an angle in degrees, if
+/// it is a multiple of 90.
+fn quarter_turns(angle: f32) -> Option<u32> {
+    let angle = angle.rem_euclid(360.);
+    let turns = (angle / 90.).round();
+
+    if (angle - turns * 90.).abs() < 0.001 {
+        Some(turns as u32 % 4)
+    } else {
+        None
+    }
+}
+
+/// Rotate an image clockwise by the given number of quarter turns. This is
+/// exact, and never alters pixels.
+fn rotate<T: Copy>(image: &[T], width: u32, height: u32, turns: u32) -> Vec<T> {
+    let (w, h) = (width as usize, height as usize);
+    let mut output = Vec::with_capacity(image.len());
+
+    match turns % 4 {
+        0 => output.extend_from_slice(image),
+        1 => {
+            for y in 0..w {
+                for x in 0..h {
+                    output.push(image[(h - x - 1) * w + y]);
+                }
+            }
+        }
+        2 => output.extend(image.iter().rev()),
+        _ => {
+            for y in 0..w {
+                for x in 0..h {
+                    output.push(image[x * w + (w - y - 1)]);
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Resize an image to the given size using the nearest-neighbor algorithm.
+fn resize<T: Default + Copy>(image: &[T], width: u32, height: u32, w: u32, h: u32) -> Vec<T> {
+    let input = Pixels::new(image, width as usize, height as usize);
+
+    let mut output_buf = vec![T::default(); (w * h) as usize];
+    let mut output = PixelsMut::new(&mut output_buf, w as usize, h as usize);
+
+    for (x, y, pixel) in output.iter_mut() {
+        let x = (x * width as usize + width as usize / 2) / w as usize;
+        let y = (y * height as usize + height as usize / 2) / h as usize;
+
+        *pixel = *input.get(x, y).unwrap();
+    }
+    output_buf
+}
+
+/// Scale an image by a factor of two using the *Scale2x* algorithm, which
+/// smoothes diagonal edges without introducing new colors.
+fn scale2x<T: Default + Copy + PartialEq>(image: &[T], width: u32, height: u32) -> Vec<T> {
+    let (w, h) = (width as usize, height as usize);
+    let mut output = vec![T::default(); w * h * 4];
+
+    for y in 0..h {
+        for x in 0..w {
+            let p = image[y * w + x];
+            let a = if y > 0 { image[(y - 1) * w + x] } else { p };
+            let b = if x + 1 < w { image[y * w + x + 1] } else { p };
+            let c = if x > 0 { image[y * w + x - 1] } else { p };
+            let d = if y + 1 < h { image[(y + 1) * w + x] } else { p };
+
+            let (i, stride) = (y * 2 * w * 2 + x * 2, w * 2);
+
+            output[i] = if c == a && c != d && a != b { a } else { p };
+            output[i + 1] = if a == b && a != c && b != d { b } else { p };
+            output[i + stride] = if d == c && d != b && c != a { c } else { p };
+            output[i + stride + 1] = if b == d && b != a && d != c { d } else { p };
+        }
+    }
+    output
+}
+
+/// Rotate an image clockwise by an arbitrary angle, in the style of the
+/// *RotSprite* algorithm: the image is upscaled 8x with *Scale2x*, then
+/// rotated and downsampled with the nearest-neighbor algorithm. This keeps
+/// edges clean and never introduces new colors.
+fn rotsprite<T: Default + Copy + PartialEq>(
+    image: &[T],
+    width: u32,
+    height: u32,
+    angle: f32,
+) -> Vec<T> {
+    const FACTOR: u32 = 8;
+
+    let mut upscaled = image.to_vec();
+    let (mut uw, mut uh) = (width, height);
+
+    while uw < width * FACTOR {
+        upscaled = scale2x(&upscaled, uw, uh);
+        uw *= 2;
+        uh *= 2;
+    }
+    let input = Pixels::new(&upscaled, uw as usize, uh as usize);
+
+    let (w, h) = Transform::Rotate(angle).size(width, height);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let mut output_buf = vec![T::default(); (w * h) as usize];
+    let mut output = PixelsMut::new(&mut output_buf, w as usize, h as usize);
+
+    for (x, y, pixel) in output.iter_mut() {
+        // Coordinates of the pixel center, relative to the image center.
+        let dx = x as f32 + 0.5 - w as f32 / 2.;
+        let dy = y as f32 + 0.5 - h as f32 / 2.;
+
+        // Apply the inverse rotation to find the source pixel.
+        let sx = dx * cos + dy * sin + width as f32 / 2.;
+        let sy = -dx * sin + dy * cos + height as f32 / 2.;
+
+        if sx >= 0. && sy >= 0. && sx < width as f32 && sy < height as f32 {
+            let ux = (sx * FACTOR as f32) as usize;
+            let uy = (sy * FACTOR as f32) as usize;
+
+            if let Some(p) = input.get(ux.min(uw as usize - 1), uy.min(uh as usize - 1)) {
+                *pixel = *p;
+            }
+        }
+    }
+    output_buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        // 1 2 3
+        // 4 5 6
+        let image = [1, 2, 3, 4, 5, 6];
+
+        let t = Transform::Rotate(90.);
+        assert_eq!(t.size(3, 2), (2, 3));
+        assert_eq!(t.apply(&image, 3, 2), vec![4, 1, 5, 2, 6, 3]);
+
+        let t = Transform::Rotate(-90.);
+        assert_eq!(t.apply(&image, 3, 2), vec![3, 6, 2, 5, 1, 4]);
+
+        let t = Transform::Rotate(180.);
+        assert_eq!(t.apply(&image, 3, 2), vec![6, 5, 4, 3, 2, 1]);
+
+        let t = Transform::Rotate(45.);
+        let rotated = t.apply(&[1; 16], 4, 4);
+        assert_eq!(t.size(4, 4), (6, 6));
+        assert!(rotated.iter().all(|p| *p == 0 || *p == 1));
+        assert_eq!(rotated[0], 0);
+        assert_eq!(rotated[3 * 6 + 3], 1);
+    }
+
+    #[test]
+    fn test_scale() {
+        let image = [1, 2, 3, 4];
+
+        let t = Transform::Scale(2., 1.);
+        assert_eq!(t.size(2, 2), (4, 2));
+        assert_eq!(t.apply(&image, 2, 2), vec![1, 1, 2, 2, 3, 3, 4, 4]);
+
+        let t = Transform::Scale(0.5, 0.5);
+        assert_eq!(t.apply(&image, 2, 2), vec![4]);
+    }
+}
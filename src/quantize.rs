@@ -0,0 +1,344 @@
+use crate::brush::Pattern;
+use crate::gfx::Rgba8;
+use crate::gradient::Dithering;
+use crate::mask::Mask;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How the distance between two colors is measured.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Metric {
+    /// Euclidean distance in RGB.
+    Rgb,
+    /// Euclidean distance in RGB, weighted by how sensitive the eye is to
+    /// each channel ("redmean").
+    Weighted,
+    /// Euclidean distance in CIELAB (CIE76).
+    Lab,
+}
+
+impl Metric {
+    /// Return the coordinates of a color in the space distances are measured in.
+    fn coords(self, c: [f32; 3]) -> [f32; 3] {
+        match self {
+            Self::Rgb | Self::Weighted => c,
+            Self::Lab => lab(c),
+        }
+    }
+
+    /// Return the squared distance between two colors, given by their coordinates.
+    fn distance(self, a: [f32; 3], b: [f32; 3]) -> f32 {
+        let (dr, dg, db) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+
+        match self {
+            Self::Rgb | Self::Lab => dr * dr + dg * dg + db * db,
+            Self::Weighted => {
+                let r = (a[0] + b[0]) / 2.;
+
+                (2. + r / 256.) * dr * dr + 4. * dg * dg + (2. + (255. - r) / 256.) * db * db
+            }
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(Self::Rgb),
+            "weighted" => Ok(Self::Weighted),
+            "lab" => Ok(Self::Lab),
+            other => Err(format!(
+                "unknown color metric `{}`, must be one of `rgb`, `weighted` or `lab`",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rgb => "rgb".fmt(f),
+            Self::Weighted => "weighted".fmt(f),
+            Self::Lab => "lab".fmt(f),
+        }
+    }
+}
+
+/// Convert an sRGB color, with channels from `0` to `255`, to CIELAB.
+fn lab(c: [f32; 3]) -> [f32; 3] {
+    let linear = |v: f32| {
+        let v = v / 255.;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(c[0]), linear(c[1]), linear(c[2]));
+
+    // XYZ, relative to the D65 white point.
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.950_47;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.088_83;
+
+    let f = |t: f32| {
+        if t > 216. / 24389. {
+            t.cbrt()
+        } else {
+            (24389. / 27. * t + 16.) / 116.
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)]
+}
+
+fn rgb(c: Rgba8) -> [f32; 3] {
+    [c.r as f32, c.g as f32, c.b as f32]
+}
+
+/// Return the given channel of a color: `0` for red, `1` for green and `2` for blue.
+fn channel(c: Rgba8, ch: usize) -> u8 {
+    match ch {
+        0 => c.r,
+        1 => c.g,
+        _ => c.b,
+    }
+}
+
+/// Maps colors to the nearest color of a palette.
+pub struct Remap {
+    metric: Metric,
+    /// Palette colors, with their coordinates.
+    palette: Vec<(Rgba8, [f32; 3])>,
+}
+
+impl Remap {
+    pub fn new(palette: &[Rgba8], metric: Metric) -> Self {
+        assert!(
+            !palette.is_empty(),
+            "palettes used for remapping cannot be empty"
+        );
+
+        Self {
+            metric,
+            palette: palette
+                .iter()
+                .map(|c| (*c, metric.coords(rgb(*c))))
+                .collect(),
+        }
+    }
+
+    /// Return the palette color nearest to the given color, ignoring alpha.
+    pub fn map(&self, c: Rgba8) -> Rgba8 {
+        self.nearest(rgb(c))
+    }
+
+    /// Return the palette color nearest to the given RGB color.
+    fn nearest(&self, c: [f32; 3]) -> Rgba8 {
+        let c = self.metric.coords(c);
+        let mut nearest = (self.palette[0].0, f32::MAX);
+
+        for (color, coords) in &self.palette {
+            let d = self.metric.distance(c, *coords);
+            if d < nearest.1 {
+                nearest = (*color, d);
+            }
+        }
+        nearest.0
+    }
+
+    /// Map the given pixels, stored top row first, to palette colors. Transparent
+    /// pixels, and pixels outside of the mask are left untouched.
+    pub fn apply(
+        &self,
+        pixels: &[Rgba8],
+        width: u32,
+        height: u32,
+        mask: Option<&Mask>,
+        dithering: Dithering,
+    ) -> Vec<Rgba8> {
+        let (w, h) = (width as i32, height as i32);
+        let included = |x: i32, y: i32| {
+            pixels[(y * w + x) as usize].a > 0 && mask.map_or(true, |m| m.contains(x, h - y - 1))
+        };
+        let mut errors = vec![[0f32; 3]; pixels.len()];
+        let mut output = pixels.to_vec();
+
+        for y in 0..h {
+            for x in 0..w {
+                if !included(x, y) {
+                    continue;
+                }
+                let i = (y * w + x) as usize;
+                let mut c = rgb(pixels[i]);
+
+                match dithering {
+                    Dithering::None => {}
+                    Dithering::Ordered => {
+                        // Offset the color by the threshold of the pixel, so that
+                        // colors in-between palette colors alternate between them.
+                        let t = Pattern::BAYER4[(h - y - 1).rem_euclid(4) as usize]
+                            [x.rem_euclid(4) as usize] as f32;
+                        let offset = ((t + 0.5) / 16. - 0.5) * 32.;
+
+                        for v in c.iter_mut() {
+                            *v += offset;
+                        }
+                    }
+                    Dithering::Diffusion => {
+                        for (v, e) in c.iter_mut().zip(errors[i].iter()) {
+                            *v += e;
+                        }
+                    }
+                }
+                let color = self.nearest(c);
+
+                if dithering == Dithering::Diffusion {
+                    let error = [
+                        c[0] - color.r as f32,
+                        c[1] - color.g as f32,
+                        c[2] - color.b as f32,
+                    ];
+                    // Diffuse the error to pixels that haven't been processed yet.
+                    for (dx, dy, weight) in &[(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)] {
+                        let (nx, ny) = (x + dx, y + dy);
+
+                        if nx >= 0 && nx < w && ny < h && included(nx, ny) {
+                            let e = &mut errors[(ny * w + nx) as usize];
+
+                            for (e, error) in e.iter_mut().zip(error.iter()) {
+                                *e += error * weight / 16.;
+                            }
+                        }
+                    }
+                }
+                output[i] = color;
+            }
+        }
+        output
+    }
+}
+
+/// Build a palette of at most `n` colors representing the given colors, using
+/// median cut. Transparent colors are ignored.
+pub fn median_cut(colors: &[Rgba8], n: usize) -> Vec<Rgba8> {
+    let colors: Vec<Rgba8> = colors.iter().filter(|c| c.a > 0).cloned().collect();
+    if colors.is_empty() || n == 0 {
+        return Vec::new();
+    }
+    let mut boxes: Vec<Vec<Rgba8>> = vec![colors];
+
+    while boxes.len() < n {
+        // Split the box with the widest channel range.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let (ch, range) = (0..3)
+                    .map(|ch| {
+                        let values = b.iter().map(|c| channel(*c, ch));
+                        let (min, max) = (values.clone().min(), values.max());
+
+                        (ch, max.unwrap_or(0) - min.unwrap_or(0))
+                    })
+                    .max_by_key(|(_, range)| *range)
+                    .unwrap();
+
+                (i, ch, range)
+            })
+            .filter(|(_, _, range)| *range > 0)
+            .max_by_key(|(_, _, range)| *range);
+
+        let (i, ch) = match widest {
+            Some((i, ch, _)) => (i, ch),
+            None => break,
+        };
+        let mut b = boxes.swap_remove(i);
+        b.sort_by_key(|c| channel(*c, ch));
+
+        // Split at the median, making sure the lower half isn't empty, since
+        // the box has at least two distinct values.
+        let median = channel(b[b.len() / 2], ch);
+        let mid = match b.iter().position(|c| channel(*c, ch) == median) {
+            Some(0) => b.iter().rposition(|c| channel(*c, ch) == median).unwrap() + 1,
+            Some(p) => p,
+            None => unreachable!(),
+        };
+        let upper = b.split_off(mid);
+
+        boxes.push(b);
+        boxes.push(upper);
+    }
+
+    let mut palette: Vec<Rgba8> = boxes
+        .iter()
+        .map(|b| {
+            let len = b.len() as u64;
+            let sum = b.iter().fold([0u64; 3], |s, c| {
+                [s[0] + c.r as u64, s[1] + c.g as u64, s[2] + c.b as u64]
+            });
+            let avg = |v: u64| ((v + len / 2) / len) as u8;
+
+            Rgba8::new(avg(sum[0]), avg(sum[1]), avg(sum[2]), 0xff)
+        })
+        .collect();
+
+    palette.sort_by_key(|c| {
+        (
+            c.r as u32 * 299 + c.g as u32 * 587 + c.b as u32 * 114,
+            c.r,
+            c.g,
+        )
+    });
+    palette.dedup();
+    palette
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remap() {
+        let palette = [Rgba8::BLACK, Rgba8::WHITE];
+        let grey = Rgba8::new(0x80, 0x80, 0x80, 0xff);
+        let dark = Rgba8::new(0x20, 0x20, 0x20, 0xff);
+        let pixels = vec![dark, grey, Rgba8::TRANSPARENT, grey];
+
+        for metric in &[Metric::Rgb, Metric::Weighted, Metric::Lab] {
+            let remap = Remap::new(&palette, *metric);
+            let output = remap.apply(&pixels, 2, 2, None, Dithering::None);
+
+            assert_eq!(output[0], Rgba8::BLACK, "{}", metric);
+            assert_eq!(output[2], Rgba8::TRANSPARENT, "{}", metric);
+        }
+
+        // A flat area in-between two colors is dithered with both of them.
+        let pixels = vec![grey; 16];
+        for dithering in &[Dithering::Ordered, Dithering::Diffusion] {
+            let output = Remap::new(&palette, Metric::Rgb).apply(&pixels, 4, 4, None, *dithering);
+            let white = output.iter().filter(|c| **c == Rgba8::WHITE).count();
+
+            assert!(white > 4 && white < 12, "{}", dithering);
+        }
+    }
+
+    #[test]
+    fn test_median_cut() {
+        let (r, g, b) = (
+            Rgba8::new(0xff, 0, 0, 0xff),
+            Rgba8::new(0, 0xff, 0, 0xff),
+            Rgba8::new(0, 0, 0xff, 0xff),
+        );
+        let colors = vec![r, r, g, g, b, b, Rgba8::TRANSPARENT];
+
+        assert_eq!(median_cut(&colors, 1).len(), 1);
+        assert_eq!(median_cut(&colors, 3), vec![b, r, g]);
+        assert_eq!(median_cut(&colors, 8).len(), 3);
+    }
+}
@@ -2,15 +2,23 @@
 ///! Session
 use crate::autocomplete::FileCompleter;
 use crate::brush::*;
-use crate::cmd::{self, Command, CommandLine, KeyMapping, Op, Value};
-use crate::color;
+use crate::cmd::{self, Axis, Command, CommandLine, KeyMapping, Op, Value};
+use crate::color::{self, BlendMode, Color};
 use crate::data;
+use crate::editor::{self, ColorEditor};
 use crate::event::{Event, TimedEvent};
 use crate::execution::{DigestMode, DigestState, Execution};
-use crate::flood::FloodFiller;
+use crate::flood::{FloodFiller, FloodOptions, FloodScope};
+use crate::font::Font;
+use crate::fx;
+use crate::gradient::{Dithering, Gradient, GradientKind};
 use crate::hashmap;
+use crate::image;
+use crate::mask::{Mask, MaskOp};
 use crate::palette::*;
+use crate::pixels::Transform;
 use crate::platform::{self, InputState, Key, KeyboardInput, LogicalSize, ModifiersState};
+use crate::quantize::{self, Metric, Remap};
 use crate::util;
 use crate::view::path;
 use crate::view::resource::ViewResource;
@@ -24,12 +32,10 @@ use crate::gfx::rect::Rect;
 use crate::gfx::shape2d::{Fill, Rotation, Shape, Stroke};
 use crate::gfx::{Point, Rgb8, Rgba8, ZDepth};
 
-use arrayvec::ArrayVec;
-
 use directories as dirs;
 use nonempty::NonEmpty;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
 use std::fs::File;
@@ -191,6 +197,9 @@ pub enum Effect {
     ViewPaintDraft(Vec<Shape>),
     /// When the active view is painted on.
     ViewPaintFinal(Vec<Shape>),
+    /// When a view is non-permanently displayed with other pixels, until
+    /// the preview is removed with `None`.
+    ViewPreview(ViewId, Option<Vec<Rgba8>>),
     /// The blend mode used for painting has changed.
     ViewBlendingChanged(Blending),
 }
@@ -237,6 +246,21 @@ pub enum Tool {
     Sampler,
     /// Used to pan the workspace.
     Pan(PanState),
+    /// Used to draw geometric figures, eg. rectangles.
+    Shape(Figure),
+    /// Used to fill regions with gradients. Holds the start of the gradient
+    /// line while dragging.
+    Gradient(Option<Point2<i32>>),
+    /// Used to make freehand or polygon selections.
+    Lasso(LassoState),
+    /// Used to select regions of similar color.
+    Wand,
+    /// Used to paint the given text.
+    Text(String),
+    /// Used to draw bezier curves.
+    Curve(CurveState),
+    /// Used to draw polygons, one vertex at a time.
+    Polygon(PolygonState),
 }
 
 impl Default for Tool {
@@ -245,6 +269,44 @@ impl Default for Tool {
     }
 }
 
+/// State of the curve tool.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct CurveState {
+    /// Start and end of the curve, followed by up to two control points, in
+    /// view coordinates.
+    pub points: Vec<Point2<i32>>,
+    /// Index of the point being dragged, if any.
+    pub dragging: Option<usize>,
+}
+
+impl CurveState {
+    /// Return the index of the point at the given position, if any.
+    fn point_at(&self, p: Point2<i32>) -> Option<usize> {
+        self.points
+            .iter()
+            .position(|q| (q.x - p.x).abs() <= 1 && (q.y - p.y).abs() <= 1)
+    }
+}
+
+/// State of the polygon tool.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct PolygonState {
+    /// Vertices of the polygon, in view coordinates. While drawing, the last
+    /// vertex follows the cursor.
+    pub vertices: Vec<Point2<i32>>,
+    /// Whether the polygon is filled.
+    pub filled: bool,
+}
+
+/// State of the lasso tool.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct LassoState {
+    /// Vertices of the lasso, in view coordinates.
+    pub vertices: Vec<Point2<i32>>,
+    /// Whether the lasso is being dragged, ie. drawn freehand.
+    pub dragging: bool,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum PanState {
     Panning,
@@ -541,11 +603,31 @@ impl Default for Settings {
                 "ui/switcher" => Value::Bool(true),
                 "ui/view-info" => Value::Bool(true),
 
+                "flood/tolerance" => Value::U32(0),
+                "flood/global" => Value::Bool(false),
+                "flood/diagonal" => Value::Bool(false),
+                "flood/scope" => Value::Ident(String::from("view")),
+
+                "dither/bg" => Value::Bool(false),
+
+                "brush/opacity" => Value::U32(100),
+                "brush/blend" => Value::Ident(String::from("normal")),
+
+                "gradient/radial" => Value::Bool(false),
+                "gradient/dither" => Value::Ident(String::from("ordered")),
+                "gradient/palette" => Value::Bool(false),
+
+                "ramp/space" => Value::Ident(String::from("hsv")),
+                "ramp/hue-shift" => Value::U32(30),
+                "ramp/saturation" => Value::U32(30),
+                "ramp/value" => Value::U32Tuple(15, 95),
+
                 "grid" => Value::Bool(false),
                 "grid/color" => Value::Rgba8(color::BLUE),
                 "grid/spacing" => Value::U32Tuple(8, 8),
 
                 "p/height" => Value::U32(Session::PALETTE_HEIGHT),
+                "p/highlight" => Value::Ident(String::from("none")),
 
                 "debug/crosshair" => Value::Bool(false),
 
@@ -568,6 +650,25 @@ impl std::ops::Index<&str> for Settings {
 
 ///////////////////////////////////////////////////////////////////////////////
 
+/// What the active view is previewed with. The previewed pixels are only
+/// rebuilt when this changes.
+#[derive(PartialEq, Debug, Clone)]
+struct Preview {
+    /// The previewed view.
+    view: ViewId,
+    /// Revision of the view's pixels.
+    revision: u64,
+    /// Palette colors.
+    palette: Vec<Rgba8>,
+    /// Colors displayed in place of the palette colors, if any.
+    colors: Option<Vec<Rgba8>>,
+    /// Pixels to highlight: those of the given color, or those that aren't
+    /// in the palette.
+    highlight: Option<Option<Rgba8>>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
 /// The user session.
 ///
 /// Stores all relevant session state.
@@ -623,6 +724,11 @@ pub struct Session {
 
     /// Current pixel selection.
     pub selection: Option<Selection>,
+    /// Pixels of the current selection, relative to its bounds, if the selection
+    /// isn't rectangular.
+    pub selection_mask: Option<Mask>,
+    /// Area paint operations are clipped to, if any.
+    pub mask: Option<Rect<i32>>,
 
     /// The session's current settings.
     pub settings: Settings,
@@ -636,11 +742,19 @@ pub struct Session {
     pub effects: Vec<Effect>,
     /// Animation frame time accumulator.
     pub accumulator: time::Duration,
+    /// Time elapsed while color cycling.
+    pub cycle_time: time::Duration,
 
     /// The current state of the command line.
     pub cmdline: CommandLine,
     /// The color palette.
     pub palette: Palette,
+    /// Named palettes not in use.
+    pub palettes: BTreeMap<String, PaletteData>,
+    /// Palette variant previewed on the active view, by name, with its colors.
+    pub palette_variant: Option<(String, Vec<Rgba8>)>,
+    /// What the active view is currently previewed with, if anything.
+    preview: Option<Preview>,
 
     /// Average time it takes for a session update.
     pub avg_time: time::Duration,
@@ -651,6 +765,10 @@ pub struct Session {
     pub prev_tool: Option<Tool>,
     /// The brush tool settings.
     pub brush: Brush,
+    /// The font used to paint text.
+    pub font: Font,
+    /// The color editor, if open.
+    pub color_editor: Option<ColorEditor>,
 
     /// Input state of the mouse.
     mouse_state: InputState,
@@ -676,12 +794,16 @@ impl Session {
     const PALETTE_CELL_SIZE: f32 = 24.;
     /// Default palette height in cells.
     const PALETTE_HEIGHT: u32 = 16;
+    /// Maximum number of frames in a color cycling export.
+    const MAX_CYCLE_FRAMES: usize = 1024;
     /// Distance to pan when using keyboard.
     const PAN_PIXELS: i32 = 32;
     /// Minimum brush size.
     const MIN_BRUSH_SIZE: usize = 1;
     /// Maximum frame width or height.
     const MAX_FRAME_SIZE: u32 = 4096;
+    /// Maximum size of a transformed selection, as a multiple of the view size.
+    const MAX_TRANSFORM_SCALE: u32 = 4;
     /// Maximum zoom amount as a multiplier.
     const MAX_ZOOM: f32 = 128.0;
     /// Zoom levels used when zooming in/out.
@@ -734,12 +856,18 @@ impl Session {
             fg: color::WHITE,
             bg: color::BLACK,
             brush: Brush::default(),
+            font: Font::default(),
+            color_editor: None,
             settings: Settings::default(),
             settings_changed: HashSet::new(),
             views: ViewManager::new(),
             effects: Vec::new(),
             accumulator: time::Duration::from_secs(0),
+            cycle_time: time::Duration::from_secs(0),
             palette: Palette::new(Self::PALETTE_CELL_SIZE, Self::PALETTE_HEIGHT as usize),
+            palettes: BTreeMap::new(),
+            palette_variant: None,
+            preview: None,
             key_bindings: KeyBindings::default(),
             keys_pressed: HashSet::new(),
             ignore_received_characters: false,
@@ -747,6 +875,8 @@ impl Session {
             mode: Mode::Normal,
             prev_mode: Option::default(),
             selection: Option::default(),
+            selection_mask: None,
+            mask: None,
             message: Message::default(),
             avg_time: time::Duration::from_secs(0),
             frame_number: 0,
@@ -830,11 +960,15 @@ impl Session {
         self.settings_changed.clear();
         self.avg_time = avg_time;
 
-        if let Tool::Brush = self.tool {
+        if let Tool::Brush | Tool::Shape(_) | Tool::Curve(_) | Tool::Polygon(_) = self.tool {
             self.brush.update();
         }
+        self.sync_color_editor();
 
         if let Some(delay) = self.animation_delay() {
+            if !self.palette.cycles.is_empty() {
+                self.cycle_time += delta;
+            }
             self.accumulator += delta;
             if self.accumulator >= delay {
                 for v in self.views.iter_mut() {
@@ -932,16 +1066,69 @@ impl Session {
             }
         }
 
-        if let Tool::Brush = self.tool {
+        if let Tool::Brush | Tool::Shape(_) | Tool::Curve(_) | Tool::Polygon(_) = self.tool {
             let brush = &self.brush;
-            let output = brush.output(
-                Stroke::NONE,
-                Fill::Solid(brush.color.into()),
-                1.0,
-                Align::BottomLeft,
-            );
+            let shading = brush.is_set(BrushMode::Shade) && !brush.is_set(BrushMode::Erase);
+            let locked = brush.is_set(BrushMode::LockAlpha) && !brush.is_set(BrushMode::Erase);
+            let (blend, opacity) = self.brush_blending();
+            let blended =
+                (opacity < 1. || blend != BlendMode::Normal) && !brush.is_set(BrushMode::Erase);
+            let output = if shading {
+                self.shade_output()
+            } else {
+                brush.output(
+                    Stroke::NONE,
+                    Fill::Solid(brush.color.into()),
+                    1.0,
+                    Align::BottomLeft,
+                )
+            };
+            let output = if blended {
+                self.blend_output(output, blend, opacity)
+            } else {
+                output
+            };
+            let output = if locked {
+                self.lock_alpha(output)
+            } else {
+                output
+            };
             if !output.is_empty() {
                 match brush.state {
+                    // Shaded, blended and alpha-locked pixels replace the pixels they
+                    // were computed from.
+                    BrushState::DrawEnded(_) if shading || locked || blended => {
+                        self.effects.extend_from_slice(&[
+                            Effect::ViewBlendingChanged(Blending::Constant),
+                            Effect::ViewPaintFinal(output),
+                        ]);
+                    }
+                    // Figures are redrawn from scratch as the cursor moves, so even when
+                    // erasing, we only paint the final figure into the real buffer.
+                    BrushState::DrawEnded(_)
+                        if matches!(
+                            self.tool,
+                            Tool::Shape(_) | Tool::Curve(_) | Tool::Polygon(_)
+                        ) =>
+                    {
+                        let blending = if brush.is_set(BrushMode::Erase) {
+                            Blending::Constant
+                        } else {
+                            Blending::Alpha
+                        };
+                        self.effects.extend_from_slice(&[
+                            Effect::ViewBlendingChanged(blending),
+                            Effect::ViewPaintFinal(output),
+                        ]);
+                    }
+                    BrushState::DrawStarted(_) | BrushState::Drawing(_)
+                        if matches!(
+                            self.tool,
+                            Tool::Shape(_) | Tool::Curve(_) | Tool::Polygon(_)
+                        ) =>
+                    {
+                        self.effects.push(Effect::ViewPaintDraft(output));
+                    }
                     // If we're erasing, we can't use the staging framebuffer, since we
                     // need to be replacing pixels on the real buffer.
                     _ if brush.is_set(BrushMode::Erase) => {
@@ -968,6 +1155,35 @@ impl Session {
             }
         }
 
+        // Clip all paint operations to the selection mask.
+        if let Some(mask) = self.mask {
+            for effect in self.effects.iter_mut() {
+                match effect {
+                    Effect::ViewPaintDraft(shapes) | Effect::ViewPaintFinal(shapes) => {
+                        Session::clip(shapes, mask);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // The palette preview and highlight aren't clipped to the selection.
+        let preview = self.palette_preview();
+        if preview != self.preview {
+            if let Some(old) = self.preview.take() {
+                self.effects.push(Effect::ViewPreview(old.view, None));
+            }
+            if let Some(preview) = &preview {
+                let pixels = self.preview_pixels(preview);
+                self.effects
+                    .push(Effect::ViewPreview(preview.view, Some(pixels)));
+            }
+            self.preview = preview;
+        }
+
+        // Indexed views take the new colors of the palette slots they use.
+        self.sync_indexed_views();
+
         if self.views.is_empty() {
             self.quit(ExitReason::Normal);
         } else {
@@ -1012,8 +1228,29 @@ impl Session {
 
     /// Cleanup to be run at the end of the frame.
     pub fn cleanup(&mut self) {
+        let mut snapped = Vec::new();
+
         for v in self.views.iter_mut() {
-            v.okay();
+            // Once an edit to an indexed view is recorded, pixels that weren't in
+            // its palette have been replaced, and the view is restored from the result.
+            match v.take_snapped() {
+                0 => v.okay(),
+                n => {
+                    if let Some(p) = v.indexed() {
+                        snapped.push((n, p.name.clone()));
+                    }
+                    v.damaged(None);
+                }
+            }
+        }
+        for (n, name) in snapped {
+            self.message(
+                format!(
+                    "Warning: {} pixel(s) not in palette `{}` were replaced with its colors",
+                    n, name
+                ),
+                MessageType::Warning,
+            );
         }
     }
 
@@ -1045,9 +1282,11 @@ impl Session {
     }
 
     /// Get the current animation delay. Returns `None` if animations aren't playing,
-    /// or if none of the views have more than one frame.
+    /// or if none of the views have more than one frame, and the palette has no
+    /// color cycles.
     pub fn animation_delay(&self) -> Option<time::Duration> {
-        let animations = self.views.iter().any(|v| v.animation.len() > 1);
+        let animations =
+            self.views.iter().any(|v| v.animation.len() > 1) || !self.palette.cycles.is_empty();
 
         if self.settings["animation"].is_set() && animations {
             let delay = self.settings["animation/delay"].to_u64();
@@ -1111,12 +1350,12 @@ impl Session {
         let gained_palette_focus = !palette_hover && self.palette.hover.is_some();
 
         match &self.tool {
-            Tool::Brush if !self.brush.is_drawing() => {
+            Tool::Brush | Tool::Shape(_) if !self.brush.is_drawing() => {
                 if gained_palette_focus {
                     self.tool(Tool::Sampler);
                 }
             }
-            Tool::FloodFill => {
+            Tool::FloodFill | Tool::Gradient(None) | Tool::Text(_) => {
                 if gained_palette_focus {
                     self.tool(Tool::Sampler);
                 }
@@ -1157,6 +1396,31 @@ impl Session {
                 self.palette.height = new.to_u64() as usize;
                 self.center_palette();
             }
+            "brush/opacity" if new.to_u64() > 100 => {
+                self.settings.set(name, old.clone()).ok();
+                self.message(
+                    "Error: brush opacity must be between 0 and 100",
+                    MessageType::Error,
+                );
+            }
+            "brush/blend" => {
+                if let Err(err) = new.to_string().parse::<BlendMode>() {
+                    self.settings.set(name, old.clone()).ok();
+                    self.message(format!("Error: {}", err), MessageType::Error);
+                }
+            }
+            "flood/scope" => {
+                if let Err(err) = new.to_string().parse::<FloodScope>() {
+                    self.settings.set(name, old.clone()).ok();
+                    self.message(format!("Error: {}", err), MessageType::Error);
+                }
+            }
+            "p/highlight" => {
+                if let Err(err) = new.to_string().parse::<Highlight>() {
+                    self.settings.set(name, old.clone()).ok();
+                    self.message(format!("Error: {}", err), MessageType::Error);
+                }
+            }
             "scale" => {
                 // TODO: We need to recompute the cursor position here
                 // from the window coordinates. Currently, cursor position
@@ -1194,6 +1458,7 @@ impl Session {
         match new {
             Mode::Normal => {
                 self.selection = None;
+                self.selection_mask = None;
             }
             Mode::Command => {
                 // When switching to command mode via the keyboard, we simultaneously
@@ -1307,6 +1572,12 @@ impl Session {
         }
         self.views.activate(id);
         self.effects.push(Effect::ViewActivated(id));
+
+        if let Some(name) = self.view(id).palette.clone() {
+            if let Err(e) = self.use_palette(&name) {
+                self.message(format!("Error: {}", e), MessageType::Error);
+            }
+        }
     }
 
     /// Check whether a view is active.
@@ -1541,8 +1812,11 @@ impl Session {
 
         let written = match ext {
             "gif" => {
-                let palette = self.colors();
                 let view = self.view(id);
+                let palette = match view.indexed() {
+                    Some(p) => p.colors.clone(),
+                    None => self.colors(),
+                };
                 let delay = time::Duration::from_millis(self.settings["animation/delay"].to_u64());
 
                 view.save_gif(&path, delay, &palette, scale)?
@@ -1667,7 +1941,7 @@ impl Session {
         }
     }
 
-    fn colors(&self) -> ArrayVec<[Rgba8; 256]> {
+    fn colors(&self) -> Vec<Rgba8> {
         let mut palette = self.palette.colors.clone();
 
         palette.push(self.fg);
@@ -1710,155 +1984,1316 @@ impl Session {
         if let Some(s) = &self.selection {
             if !r.contains(s.min()) && !r.contains(s.max()) {
                 self.selection = None;
+                self.selection_mask = None;
             }
         }
     }
 
-    /// Yank the selection.
-    fn yank_selection(&mut self) -> Option<Rect<i32>> {
-        if let (Mode::Visual(VisualState::Selecting { .. }), Some(s)) = (self.mode, self.selection)
-        {
-            let v = self.active_view_mut();
-            let s = s.abs().bounds();
-
-            if s.intersects(v.layer_bounds()) {
-                let s = s.intersection(v.layer_bounds());
-
-                v.yank(s);
+    /// Replace a color with another in the given views. If there is a selection, only
+    /// pixels of the active view within it are replaced. Returns the number of pixels
+    /// replaced.
+    fn replace_colors(&mut self, ids: &[ViewId], from: Rgba8, to: Rgba8, tolerance: u8) -> usize {
+        let active = self.views.active_id;
+        let selection = self.selection.map(|_| self.selected_area());
+
+        ids.iter()
+            .map(|id| match &selection {
+                Some(Some(area)) if *id == active => {
+                    self.replace_color(*id, from, to, tolerance, Some(area))
+                }
+                Some(None) if *id == active => 0,
+                _ => self.replace_color(*id, from, to, tolerance, None),
+            })
+            .sum()
+    }
 
-                self.selection = Some(Selection::from(s));
-                self.switch_mode(Mode::Visual(VisualState::Pasting));
+    /// Replace a color with another in the given view, as a single edit. If an area
+    /// is given, only pixels within it and its mask are replaced. Returns the number
+    /// of pixels replaced.
+    fn replace_color(
+        &mut self,
+        id: ViewId,
+        from: Rgba8,
+        to: Rgba8,
+        tolerance: u8,
+        area: Option<&(Rect<i32>, Option<Mask>)>,
+    ) -> usize {
+        let v = self.view_mut(id);
+        let (area, mask) = match area {
+            Some((area, mask)) => (*area, mask.as_ref()),
+            None => (v.layer_bounds(), None),
+        };
+        let (snapshot, pixels) = v.layer.current_snapshot();
+        let (w, h) = (snapshot.width() as i32, snapshot.height() as i32);
+        let mut pixels = pixels.to_vec();
+        let mut replaced = 0;
+
+        for y in area.y1..area.y2 {
+            for x in area.x1..area.x2 {
+                if let Some(mask) = mask {
+                    if !mask.contains(x - area.x1, y - area.y1) {
+                        continue;
+                    }
+                }
+                // Snapshot rows are stored top to bottom.
+                let c = &mut pixels[((h - y - 1) * w + x) as usize];
 
-                return Some(s);
+                if *c != to && color::within_tolerance(*c, from, tolerance) {
+                    *c = to;
+                    replaced += 1;
+                }
             }
         }
-        None
+        if replaced > 0 {
+            v.set_pixels(pixels);
+            v.touch();
+        }
+        replaced
     }
 
-    fn undo(&mut self, id: ViewId) {
-        self.restore_view_snapshot(id, Direction::Backward);
+    /// Return the brush output in shading mode: every pixel under the brush is
+    /// replaced with the next color of its palette ramp. Since shades are always
+    /// computed from the current snapshot, pixels are shaded at most once per stroke.
+    fn shade_output(&self) -> Vec<Shape> {
+        let v = self.active_view();
+
+        self.brush
+            .pixels()
+            .into_iter()
+            .filter(|p| p.x >= 0 && p.y >= 0)
+            .filter_map(|p| {
+                let color = v.color_at(ViewCoords::new(p.x as u32, p.y as u32))?;
+                let shade = self.palette.shade(*color, self.brush.shade_step)?;
+                let (x, y) = (p.x as f32, p.y as f32);
+
+                Some(Shape::Rectangle(
+                    Rect::new(x, y, x + 1., y + 1.),
+                    ZDepth::ZERO,
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(shade.into()),
+                ))
+            })
+            .collect()
     }
 
-    fn redo(&mut self, id: ViewId) {
-        self.restore_view_snapshot(id, Direction::Forward);
+    /// Return the blend mode and opacity strokes are painted with.
+    fn brush_blending(&self) -> (BlendMode, f32) {
+        let blend = self.settings["brush/blend"]
+            .to_string()
+            .parse()
+            .unwrap_or(BlendMode::Normal);
+        let opacity = self.settings["brush/opacity"].to_u64().min(100) as f32 / 100.;
+
+        (blend, opacity)
     }
 
-    fn restore_view_snapshot(&mut self, id: ViewId, dir: Direction) {
-        self.view_mut(id).restore_snapshot(dir);
-        self.organize_views();
-        self.cursor_dirty();
+    /// Blend the given brush output with the pixels of the active view. Since
+    /// every pixel is blended once with the current snapshot, overlapping
+    /// brush stamps within a stroke don't stack.
+    fn blend_output(&self, shapes: Vec<Shape>, blend: BlendMode, opacity: f32) -> Vec<Shape> {
+        let v = self.active_view();
+        let mut pixels = BTreeMap::new();
+
+        for shape in shapes {
+            if let Shape::Rectangle(r, _, _, _, Fill::Solid(color)) = shape {
+                for y in r.y1.max(0.) as i32..r.y2 as i32 {
+                    for x in r.x1.max(0.) as i32..r.x2 as i32 {
+                        pixels.insert((x, y), Rgba8::from(color));
+                    }
+                }
+            }
+        }
+
+        pixels
+            .into_iter()
+            .filter_map(|((x, y), color)| {
+                let dst = v.color_at(ViewCoords::new(x as u32, y as u32))?;
+                let (x, y) = (x as f32, y as f32);
+
+                Some(Shape::Rectangle(
+                    Rect::new(x, y, x + 1., y + 1.),
+                    ZDepth::ZERO,
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(color::blend(*dst, color, blend, opacity).into()),
+                ))
+            })
+            .collect()
     }
 
-    ///////////////////////////////////////////////////////////////////////////
-    // Internal command handler
-    ///////////////////////////////////////////////////////////////////////////
+    /// Restrict the given brush output to the opaque pixels of the active view,
+    /// preserving their alpha.
+    fn lock_alpha(&self, shapes: Vec<Shape>) -> Vec<Shape> {
+        let v = self.active_view();
+        let mut output = Vec::new();
+
+        for shape in shapes {
+            if let Shape::Rectangle(r, z, _, stroke, Fill::Solid(color)) = shape {
+                let color = Rgba8::from(color);
+
+                for y in r.y1.max(0.) as i32..r.y2 as i32 {
+                    for x in r.x1.max(0.) as i32..r.x2 as i32 {
+                        match v.color_at(ViewCoords::new(x as u32, y as u32)) {
+                            Some(c) if c.a > 0 => {
+                                let (x, y) = (x as f32, y as f32);
+
+                                output.push(Shape::Rectangle(
+                                    Rect::new(x, y, x + 1., y + 1.),
+                                    z,
+                                    Rotation::ZERO,
+                                    stroke,
+                                    Fill::Solid(color.alpha(c.a).into()),
+                                ));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        output
+    }
 
-    fn handle_internal_cmd(&mut self, cmd: InternalCommand, exec: &mut Execution) {
-        match cmd {
-            InternalCommand::StopRecording => match exec.stop_recording() {
-                Ok(path) => {
-                    self.message(
-                        format!("Recording saved to `{}`", path.display()),
-                        MessageType::Execution,
+    /// Clip the given shapes to the given area. Only rectangles are clipped,
+    /// since they are the only shapes painted onto views.
+    fn clip(shapes: &mut Vec<Shape>, area: Rect<i32>) {
+        let area = area.map(|n| n as f32);
+
+        *shapes = shapes
+            .drain(..)
+            .filter_map(|shape| match shape {
+                Shape::Rectangle(r, z, rotation, stroke, fill) => {
+                    let r = Rect::new(
+                        r.x1.max(area.x1),
+                        r.y1.max(area.y1),
+                        r.x2.min(area.x2),
+                        r.y2.min(area.y2),
                     );
-                    info!("recording: events saved to `{}`", path.display());
-                    self.quit(ExitReason::Normal);
-                }
-                Err(e) => {
-                    error!("recording: error stopping: {}", e);
+                    if r.x1 < r.x2 && r.y1 < r.y2 {
+                        Some(Shape::Rectangle(r, z, rotation, stroke, fill))
+                    } else {
+                        None
+                    }
                 }
-            },
+                other => Some(other),
+            })
+            .collect();
+    }
+
+    /// Fill the gradient line being dragged with the gradient tool, if any.
+    fn finish_gradient(&mut self) {
+        if let Tool::Gradient(Some(start)) = self.tool {
+            let p = self.active_view_coords(self.cursor);
+
+            self.tool = Tool::Gradient(None);
+            self.gradient_fill(start, Point2::new(p.x.floor() as i32, p.y.floor() as i32));
         }
     }
 
-    ///////////////////////////////////////////////////////////////////////////
-    // Event handlers
-    ///////////////////////////////////////////////////////////////////////////
+    /// Fill the selection with a gradient, or if there is no selection, the region
+    /// under the start of the gradient line. The region is found the same way as
+    /// with the flood fill tool.
+    fn gradient_fill(&mut self, start: Point2<i32>, end: Point2<i32>) {
+        let region = if self.selection.is_some() {
+            self.selected_points()
+        } else {
+            self.flood_region(start)
+        };
 
-    pub fn handle_event(&mut self, event: Event, exec: &mut Execution) {
-        if let Execution::Recording {
-            ref mut events,
-            start,
-            ..
-        } = exec
+        self.paint_gradient(&region, start, end);
+    }
+
+    /// Return the region of the active view the flood fill tool would fill
+    /// when clicking on the given pixel.
+    fn flood_region(&self, p: Point2<i32>) -> Vec<Point2<i32>> {
+        let options = self.flood_options();
+        let v = self.active_view();
+        let bounds = options.scope.bounds(v.extent(), p.x);
+        let mut filler = FloodFiller::new(v, self.fg, options);
+
+        filler.fill(ViewCoords::new(p.x as f32, p.y as f32), bounds);
+        filler.points()
+    }
+
+    /// Return the flood fill options from the settings.
+    fn flood_options(&self) -> FloodOptions {
+        FloodOptions {
+            tolerance: self.settings["flood/tolerance"]
+                .to_u64()
+                .min(u8::MAX as u64) as u8,
+            global: self.settings["flood/global"].is_set(),
+            diagonal: self.settings["flood/diagonal"].is_set(),
+            scope: self.settings["flood/scope"]
+                .to_string()
+                .parse()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Paint a gradient over the given pixels of the active view, as a single edit.
+    fn paint_gradient(&mut self, pixels: &[Point2<i32>], start: Point2<i32>, end: Point2<i32>) {
+        if pixels.is_empty() {
+            return;
+        }
+        let ramp = match self.gradient_ramp() {
+            Ok(ramp) => ramp,
+            Err(err) => return self.message(format!("Error: {}", err), MessageType::Error),
+        };
+        let dithering = match self.settings["gradient/dither"]
+            .to_string()
+            .parse::<Dithering>()
         {
-            events.push(TimedEvent::new(
-                self.frame_number,
-                start.elapsed(),
-                event.clone(),
-            ));
+            Ok(dithering) => dithering,
+            Err(err) => return self.message(format!("Error: {}", err), MessageType::Error),
+        };
+        let kind = if self.settings["gradient/radial"].is_set() {
+            GradientKind::Radial
+        } else {
+            GradientKind::Linear
+        };
+        let gradient = Gradient::new(kind, dithering, ramp, start, end);
+
+        self.effects.extend_from_slice(&[
+            Effect::ViewBlendingChanged(Blending::Constant),
+            Effect::ViewPaintFinal(gradient.shapes(pixels)),
+        ]);
+        self.active_view_mut().touch();
+    }
+
+    /// Return the colors of the gradient ramp. This is either the foreground and
+    /// background colors, or the palette colors between them.
+    fn gradient_ramp(&self) -> Result<Vec<Rgba8>, Error> {
+        if !self.settings["gradient/palette"].is_set() {
+            return Ok(vec![self.fg, self.bg]);
         }
+        let colors = &self.palette.colors;
+        let position = |color| colors.iter().position(|c| *c == color);
 
-        match event {
-            Event::MouseInput(btn, st) => {
-                if self.settings["input/mouse"].is_set() {
-                    self.handle_mouse_input(btn, st);
-                }
-            }
-            Event::MouseWheel(delta) => {
-                if self.settings["input/mouse"].is_set() {
-                    self.handle_mouse_wheel(delta);
-                }
-            }
-            Event::CursorMoved(position) => {
-                if self.settings["input/mouse"].is_set() {
-                    let coords = self.window_to_session_coords(position);
-                    self.handle_cursor_moved(coords);
-                }
-            }
-            Event::KeyboardInput(input) => self.handle_keyboard_input(input, exec),
-            Event::ReceivedCharacter(c, mods) => self.handle_received_character(c, mods),
-            Event::Paste(p) => self.handle_paste(p),
+        match (position(self.fg), position(self.bg)) {
+            (Some(from), Some(to)) if from <= to => Ok(colors[from..=to].to_vec()),
+            (Some(from), Some(to)) => Ok(colors[to..=from].iter().rev().cloned().collect()),
+            _ => Err("foreground and background colors must be in the palette".to_owned()),
         }
     }
 
-    pub fn resize(&mut self, size: platform::LogicalSize, scale: f64) {
-        let (w, h) = (size.width / scale, size.height / scale);
+    /// Return the selection mask, if the selection isn't rectangular.
+    pub fn selection_mask(&self) -> Option<&Mask> {
+        let r = self.selection?.abs().bounds();
 
-        self.width = w as f32;
-        self.height = h as f32;
+        self.selection_mask
+            .as_ref()
+            .filter(|m| m.width() == r.width() as u32 && m.height() == r.height() as u32)
+    }
 
-        // TODO: Reset session cursor coordinates
-        self.center_palette();
-        self.center_active_view();
+    /// Return the selected pixels of the active view's layer.
+    fn selected_points(&self) -> Vec<Point2<i32>> {
+        match self.selected_area() {
+            Some((area, Some(mask))) => mask
+                .points()
+                .map(|p| Point2::new(p.x + area.x1, p.y + area.y1))
+                .collect(),
+            Some((area, None)) => (area.y1..area.y2)
+                .flat_map(|y| (area.x1..area.x2).map(move |x| Point2::new(x, y)))
+                .collect(),
+            None => Vec::new(),
+        }
     }
 
-    pub fn rescale(&mut self, old: f64, new: f64) {
-        let (w, h) = (self.width as f64 * old, self.height as f64 * old);
+    /// Return the selected area within the active view's layer, along with the
+    /// selection mask, if the selection isn't rectangular.
+    fn selected_area(&self) -> Option<(Rect<i32>, Option<Mask>)> {
+        let s = self.selection?.abs().bounds();
+        let layer = self.active_view().layer_bounds();
 
-        self.resize(platform::LogicalSize::new(w, h), new);
-        self.effects.push(Effect::SessionScaled(new));
+        if !s.intersects(layer) {
+            return None;
+        }
+        match self.selection_mask() {
+            Some(mask) => {
+                let (origin, mask) = mask.crop(Point2::new(s.x1, s.y1), layer)?;
+                let (w, h) = (mask.width() as i32, mask.height() as i32);
+
+                Some((
+                    Rect::new(origin.x, origin.y, origin.x + w, origin.y + h),
+                    Some(mask),
+                ))
+            }
+            None => Some((s.intersection(layer), None)),
+        }
     }
 
-    pub fn handle_resized(&mut self, size: platform::LogicalSize) {
-        self.resize(size, self.settings["scale"].to_f64());
-        self.effects.push(Effect::SessionResized(size));
+    /// Return the shapes to paint the given area with a color.
+    fn area_shapes(area: Rect<i32>, mask: Option<&Mask>, color: Rgba8) -> Vec<Shape> {
+        let rects = match mask {
+            Some(mask) => mask
+                .spans()
+                .into_iter()
+                .map(|r| r + Vector2::new(area.x1, area.y1))
+                .collect(),
+            None => vec![area],
+        };
+        rects
+            .into_iter()
+            .map(|r| {
+                Shape::Rectangle(
+                    r.map(|n| n as f32),
+                    ZDepth::default(),
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(color.into()),
+                )
+            })
+            .collect()
     }
 
-    fn handle_mouse_input(&mut self, button: platform::MouseButton, state: platform::InputState) {
-        if button != platform::MouseButton::Left {
+    /// Return the areas whole-image operations apply to: the selection if there
+    /// is one, or each frame of the active view otherwise.
+    fn image_areas(&self) -> Vec<(Rect<i32>, Option<Mask>)> {
+        match self.selected_area() {
+            Some(area) => vec![area],
+            None => {
+                let v = self.active_view();
+                let (fw, fh) = (v.fw as i32, v.fh as i32);
+
+                (0..v.animation.len() as i32)
+                    .map(|i| (Rect::new(i * fw, 0, (i + 1) * fw, fh), None))
+                    .collect()
+            }
+        }
+    }
+
+    /// Paint the pixels returned by an effect with the given color, as a single
+    /// edit. The effect is computed from the selection if there is one, or from
+    /// each frame of the active view otherwise.
+    fn paint_fx<F>(&mut self, color: Rgba8, effect: F)
+    where
+        F: Fn(&fx::Image) -> Vec<Point2<i32>>,
+    {
+        let mut shapes = Vec::new();
+
+        for (area, mask) in self.image_areas() {
+            let pixels = match self.active_view().layer.get_snapshot_rect(&area) {
+                Some((_, pixels)) => pixels,
+                None => continue,
+            };
+            let image = fx::Image::new(
+                &pixels,
+                area.width() as u32,
+                area.height() as u32,
+                mask.as_ref(),
+            );
+
+            shapes.extend(effect(&image).into_iter().map(|p| {
+                let (x, y) = ((area.x1 + p.x) as f32, (area.y1 + p.y) as f32);
+
+                Shape::Rectangle(
+                    Rect::new(x, y, x + 1., y + 1.),
+                    ZDepth::default(),
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(color.into()),
+                )
+            }));
+        }
+        if shapes.is_empty() {
             return;
         }
-        self.mouse_state = state;
+        self.effects.extend_from_slice(&[
+            Effect::ViewBlendingChanged(Blending::Constant),
+            Effect::ViewPaintFinal(shapes),
+        ]);
+        self.active_view_mut().touch();
+    }
 
-        // Pan tool.
-        match &mut self.tool {
-            Tool::Pan(ref mut p) => match (&p, state) {
-                (PanState::Panning, InputState::Released) => {
-                    *p = PanState::NotPanning;
-                    return;
-                }
-                (PanState::NotPanning, InputState::Pressed) => {
-                    *p = PanState::Panning;
-                    return;
-                }
-                _ => {}
-            },
-            _ => {}
+    /// Return the color edited by the color editor for the given target, if
+    /// it exists.
+    fn target_color(&self, target: editor::Target) -> Option<Rgba8> {
+        match target {
+            editor::Target::Foreground => Some(self.fg),
+            editor::Target::Background => Some(self.bg),
+            editor::Target::Palette(i) => self.palette.colors.get(i).cloned(),
         }
+    }
 
-        match state {
-            InputState::Pressed => {
+    /// Make a view indexed, with the current palette, and use the palette with
+    /// the view. Pixels that aren't in the palette are replaced by the nearest
+    /// palette color.
+    fn index_view(&mut self, id: ViewId) -> Result<(), Error> {
+        let name = self.palette.name.clone();
+        let palette = IndexedPalette::new(name.clone(), self.palette.colors.clone())?;
+        let v = self.view_mut(id);
+        let (_, before) = v.layer.current_snapshot();
+        let before = before.to_vec();
+
+        v.set_indexed(Some(palette));
+        v.palette = Some(name);
+
+        let (_, pixels) = v.layer.current_snapshot();
+        let count = pixels.iter().zip(&before).filter(|(a, b)| a != b).count();
+
+        if count > 0 {
+            let pixels = pixels.to_vec();
+
+            v.set_pixels(pixels);
+            v.touch();
+
+            self.message(
+                format!("{} pixel(s) replaced with palette colors", count),
+                MessageType::Info,
+            );
+        }
+        Ok(())
+    }
+
+    /// Indexed views follow the edits to the palette they're indexed with: when
+    /// a palette slot changes, so do the pixels using it. Views whose palette
+    /// has a different number of colors are left as they are.
+    fn sync_indexed_views(&mut self) {
+        let palette = &self.palette;
+
+        for v in self.views.iter_mut() {
+            if v.state != ViewState::Okay {
+                continue;
+            }
+            let palette = match v.indexed() {
+                Some(p)
+                    if p.name == palette.name
+                        && p.colors.len() == palette.colors.len()
+                        && p.colors != palette.colors =>
+                {
+                    IndexedPalette {
+                        name: p.name.clone(),
+                        colors: palette.colors.clone(),
+                    }
+                }
+                _ => continue,
+            };
+            v.set_indexed(Some(palette));
+            v.damaged(None);
+        }
+    }
+
+    /// Resolve the color references of a command, eg. `fg` or `p:3`, to the
+    /// colors they refer to.
+    fn resolve_colors(&self, cmd: &mut Command) -> Result<(), Error> {
+        let resolve = |target| {
+            self.target_color(target)
+                .ok_or_else(|| format!("palette index out of range (0..{})", self.palette.size()))
+        };
+
+        for c in cmd.colors_mut() {
+            if let Color::Ref(target) = *c {
+                *c = Color::Rgba(resolve(target)?);
+            }
+        }
+        match cmd {
+            Command::Set(_, v) | Command::Echo(v) => {
+                if let Value::ColorRef(target) = *v {
+                    *v = Value::Rgba8(resolve(target)?);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Set the color of the given color editor target.
+    fn set_target_color(&mut self, target: editor::Target, color: Rgba8) {
+        match target {
+            editor::Target::Foreground => self.fg = color,
+            editor::Target::Background => self.bg = color,
+            editor::Target::Palette(i) => {
+                if i < self.palette.size() {
+                    self.palette.set(i, color);
+                }
+            }
+        }
+    }
+
+    /// Write the color of the color editor to its target.
+    fn apply_color_editor(&mut self) {
+        if let Some(editor) = &self.color_editor {
+            let (target, color) = (editor.target, editor.color());
+            self.set_target_color(target, color);
+        }
+    }
+
+    /// Keep the color editor in sync with its target, which can be changed
+    /// by other means. The editor is closed if its target no longer exists.
+    fn sync_color_editor(&mut self) {
+        let target = match &self.color_editor {
+            Some(editor) => editor.target,
+            None => return,
+        };
+        match self.target_color(target) {
+            Some(color) => {
+                if let Some(editor) = &mut self.color_editor {
+                    editor.place(self.width, self.height);
+                    editor.set_color(color);
+                }
+            }
+            None => self.color_editor = None,
+        }
+    }
+
+    /// Return the names of all palettes, including built-in palettes that
+    /// haven't been used yet.
+    fn palette_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .palettes
+            .keys()
+            .map(|k| k.as_str())
+            .chain(data::PALETTES.iter().map(|(name, _)| *name))
+            .chain(Some(self.palette.name.as_str()))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Switch to the named palette. The palette in use is kept under its name,
+    /// so that switching back to it restores it. Built-in palettes are loaded
+    /// the first time they are used.
+    fn use_palette(&mut self, name: &str) -> Result<(), Error> {
+        if name == self.palette.name {
+            return Ok(());
+        }
+        let (data, script) = match self.palettes.remove(name) {
+            Some(data) => (data, None),
+            None => match data::PALETTES.iter().find(|(n, _)| *n == name) {
+                Some((_, script)) => (PaletteData::default(), Some(*script)),
+                None => return Err(format!("unknown palette `{}`", name)),
+            },
+        };
+        let (prev, data) = self.palette.replace(name.to_owned(), data);
+        self.palettes.insert(prev, data);
+
+        if let Some(script) = script {
+            self.source_reader(io::BufReader::new(script), name)
+                .map_err(|e| e.to_string())?;
+        }
+        self.center_palette();
+        self.sync_color_editor();
+
+        Ok(())
+    }
+
+    /// Return the colors of a palette, given its name or the path to a palette
+    /// file.
+    fn palette_colors(&self, name: &str) -> Result<Vec<Rgba8>, Error> {
+        if name == self.palette.name {
+            return Ok(self.palette.colors.clone());
+        }
+        if let Some(data) = self.palettes.get(name) {
+            return Ok(data.colors.clone());
+        }
+        if let Some((_, script)) = data::PALETTES.iter().find(|(n, _)| *n == name) {
+            return parse_colors(&String::from_utf8_lossy(script));
+        }
+        std::fs::read_to_string(name)
+            .map_err(|e| format!("error reading {}: {}", name, e))
+            .and_then(|s| parse_colors(&s).map_err(|e| format!("{}: {}", name, e)))
+    }
+
+    /// Return what the active view should be previewed with: the palette's
+    /// color cycles and the palette variant, if any, and the pixels highlighted
+    /// as set by `p/highlight`, either those of the hovered palette color, or
+    /// those that aren't in the palette.
+    fn palette_preview(&mut self) -> Option<Preview> {
+        if self.views.is_empty() {
+            return None;
+        }
+        let cycling = self.animation_delay().is_some() && !self.palette.cycles.is_empty();
+        let palette = self.palette.colors.clone();
+
+        let colors = if cycling || self.palette_variant.is_some() {
+            let mut colors = if cycling {
+                let time = self.cycle_time.as_millis() as u64;
+                cycle_colors(&palette, &self.palette.cycles, time)
+            } else {
+                palette.clone()
+            };
+            if let Some((_, variant)) = &self.palette_variant {
+                match PaletteSwap::new(&palette, variant) {
+                    Ok(swap) => colors.iter_mut().for_each(|c| *c = swap.apply(*c)),
+                    Err(e) => {
+                        // The palette in use was changed since the preview started.
+                        self.palette_variant = None;
+                        self.message(format!("Error: {}", e), MessageType::Error);
+
+                        return None;
+                    }
+                }
+            }
+            Some(colors)
+        } else {
+            None
+        };
+        let highlight = match self.settings["p/highlight"].to_string().parse() {
+            Ok(Highlight::Hover) => self.palette.hover.map(Some),
+            Ok(Highlight::OffPalette) => Some(None),
+            Ok(Highlight::None) | Err(_) => None,
+        };
+        if colors.is_none() && highlight.is_none() {
+            return None;
+        }
+        let v = self.active_view();
+
+        Some(Preview {
+            view: v.id,
+            revision: v.layer.revision(),
+            palette,
+            colors,
+            highlight,
+        })
+    }
+
+    /// Return the pixels the view is displayed with in place of its own, for
+    /// the given preview. The view isn't modified.
+    fn preview_pixels(&self, preview: &Preview) -> Vec<Rgba8> {
+        let (_, pixels) = self.view(preview.view).layer.current_snapshot();
+        let swap = preview
+            .colors
+            .as_ref()
+            .and_then(|colors| PaletteSwap::new(&preview.palette, colors).ok());
+        let palette: BTreeSet<Rgba8> = preview.palette.iter().cloned().collect();
+
+        // Pixels of the hovered color are highlighted with black or white,
+        // whichever stands out more.
+        let fill = match preview.highlight {
+            Some(Some(c)) if c.r as u32 + c.g as u32 + c.b as u32 > 3 * 0x80 => Rgba8::BLACK,
+            Some(Some(_)) => Rgba8::WHITE,
+            Some(None) | None => color::RED,
+        };
+
+        pixels
+            .iter()
+            .map(|c| {
+                let highlighted = match preview.highlight {
+                    Some(Some(hover)) => *c == hover,
+                    Some(None) => c.a > 0 && !palette.contains(c),
+                    None => false,
+                };
+                if highlighted {
+                    fill
+                } else if let Some(swap) = &swap {
+                    swap.apply(*c)
+                } else {
+                    *c
+                }
+            })
+            .collect()
+    }
+
+    /// Return the colors of the pixels in the selection, or of the whole active
+    /// view if there is no selection.
+    fn selected_colors(&self) -> Vec<Rgba8> {
+        let (snapshot, pixels) = self.active_view().layer.current_snapshot();
+        let (w, h) = (snapshot.width() as i32, snapshot.height() as i32);
+        // Pixels are stored top row first.
+        let at = |x: i32, y: i32| pixels[((h - y - 1) * w + x) as usize];
+
+        match self.selected_area() {
+            Some((area, Some(mask))) => mask
+                .points()
+                .map(|p| at(p.x + area.x1, p.y + area.y1))
+                .collect(),
+            Some((area, None)) => (area.y1..area.y2)
+                .flat_map(|y| (area.x1..area.x2).map(move |x| at(x, y)))
+                .collect(),
+            None if self.selection.is_some() => vec![],
+            None => pixels.to_vec(),
+        }
+    }
+
+    /// Export the active view as a looping gif of the palette's color cycles.
+    /// Each frame of the gif is the image with the cycled palette applied, for
+    /// as long as the colors don't change. The view's own animation, if any,
+    /// plays along.
+    fn export_cycle(&mut self, path: &Path, scale: u32) -> Result<(), Error> {
+        if self.palette.cycles.is_empty() {
+            return Err(String::from("the palette has no color cycles"));
+        }
+        if path.extension().and_then(|e| e.to_str()) != Some("gif") {
+            return Err(String::from("color cycles can only be exported as gif"));
+        }
+        let view = self.active_view();
+        let delay = self.settings["animation/delay"].to_u64().max(1);
+        let nframes = view.animation.len();
+
+        let mut periods: Vec<(u64, u64)> = self
+            .palette
+            .cycles
+            .iter()
+            .map(|c| (c.delay, c.range.len() as u64))
+            .collect();
+        if nframes > 1 {
+            periods.push((delay, nframes as u64));
+        }
+        let (times, total) = cycle_timeline(&periods, Self::MAX_CYCLE_FRAMES)
+            .ok_or_else(|| String::from("color cycles are too long to export"))?;
+
+        let (snapshot, pixels) = view.layer.current_snapshot();
+        let extent = snapshot.extent;
+        let colors = &self.palette.colors;
+
+        let frames: Vec<_> = times
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let next = times.get(i + 1).cloned().unwrap_or(total);
+                let swap =
+                    PaletteSwap::new(colors, &cycle_colors(colors, &self.palette.cycles, *t))
+                        .expect("cycled colors have the same length");
+                let frame =
+                    ViewResource::frame_pixels(pixels, extent, (*t / delay) as usize % nframes);
+                let frame: Vec<Rgba8> = frame.into_iter().map(|c| swap.apply(c)).collect();
+
+                (frame, time::Duration::from_millis(next - t))
+            })
+            .collect();
+
+        let written = image::save_gif(path, extent.fw, extent.fh, &frames, &self.colors(), scale)
+            .map_err(|e| format!("error writing {}: {}", path.display(), e))?;
+
+        self.message(
+            format!("\"{}\" {} pixels written", path.display(), written),
+            MessageType::Info,
+        );
+        Ok(())
+    }
+
+    /// Export the active view once for every target palette, with the colors of
+    /// the palette in use swapped for the target palette colors. Each variant
+    /// is named after the given path and the target palette.
+    fn export_variants(
+        &mut self,
+        path: &Path,
+        scale: u32,
+        targets: &[String],
+    ) -> Result<(), Error> {
+        if targets.is_empty() {
+            return Err(String::from("no target palettes given"));
+        }
+        if path.extension().and_then(|e| e.to_str()) != Some("png") {
+            return Err(String::from("variants can only be exported as png"));
+        }
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| format!("invalid file path {}", path.display()))?;
+
+        let mut variants = Vec::new();
+        for target in targets {
+            let colors = self.palette_colors(target)?;
+            let swap = PaletteSwap::new(&self.palette.colors, &colors)
+                .map_err(|e| format!("{}: {}", target, e))?;
+            let name = Path::new(target)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or(target);
+
+            variants.push((path.with_file_name(format!("{}-{}.png", stem, name)), swap));
+        }
+
+        let (snapshot, pixels) = self.active_view().layer.current_snapshot();
+        let (w, h) = (snapshot.width(), snapshot.height());
+
+        for (path, swap) in variants.iter() {
+            let pixels: Vec<Rgba8> = pixels.iter().map(|c| swap.apply(*c)).collect();
+
+            image::save_as(path, w, h, scale, &pixels)
+                .map_err(|e| format!("error writing {}: {}", path.display(), e))?;
+        }
+        self.message(
+            format!(
+                "{} variants of \"{}\" written",
+                variants.len(),
+                path.display()
+            ),
+            MessageType::Info,
+        );
+        Ok(())
+    }
+
+    /// Return the given palette index, or the index of the hovered palette color
+    /// if none is given. The index must be lower than `limit`.
+    fn palette_index(&self, index: Option<usize>, limit: usize) -> Result<usize, Error> {
+        match index.or(self.palette.hover_index) {
+            Some(index) if index < limit => Ok(index),
+            Some(_) => Err(format!("palette index out of range (0..{})", limit)),
+            None => Err(String::from("no palette index given, and no color hovered")),
+        }
+    }
+
+    /// Add a hue-shifted ramp of `count` colors around the given base color to the
+    /// palette, as a contiguous block, and use it as a shading ramp.
+    fn palette_ramp(&mut self, base: Rgba8, count: usize) -> Result<(), Error> {
+        let space = self.settings["ramp/space"].to_string().parse()?;
+        let (lo, hi): (u32, u32) = self.settings["ramp/value"].clone().into();
+
+        if lo >= hi || hi > 100 {
+            return Err(format!(
+                "invalid ramp value range {},{}, must be increasing and within 0..100",
+                lo, hi
+            ));
+        }
+        if count == 0 {
+            return Err(String::from("ramp size must be at least 1"));
+        }
+        let start = self.palette.size();
+        let options = RampOptions {
+            space,
+            hue_shift: self.settings["ramp/hue-shift"].to_u64() as f32,
+            saturation: self.settings["ramp/saturation"].to_u64().min(100) as f32 / 100.,
+            value: (lo as f32 / 100., hi as f32 / 100.),
+        };
+        self.palette
+            .colors
+            .extend(hue_shifted_ramp(base, count, &options));
+
+        if count > 1 {
+            self.palette.ramps.push(Ramp::new(start, start + count - 1));
+        }
+        self.center_palette();
+
+        Ok(())
+    }
+
+    /// Map the pixels of the selection, or of the active view, to the nearest
+    /// colors of the palette, as a single edit.
+    fn remap(&mut self, metric: Metric, dithering: Dithering) {
+        if self.palette.colors.is_empty() {
+            return self.message("Error: the palette is empty", MessageType::Error);
+        }
+        let remap = Remap::new(&self.palette.colors, metric);
+        let mut shapes = Vec::new();
+
+        for (area, mask) in self.image_areas() {
+            let pixels = match self.active_view().layer.get_snapshot_rect(&area) {
+                Some((_, pixels)) => pixels,
+                None => continue,
+            };
+            let (w, h) = (area.width(), area.height());
+            let output = remap.apply(&pixels, w as u32, h as u32, mask.as_ref(), dithering);
+
+            for (i, (old, new)) in pixels.iter().zip(output.iter()).enumerate() {
+                if old == new {
+                    continue;
+                }
+                // Pixels are stored top row first.
+                let (x, y) = (i as i32 % w, h - i as i32 / w - 1);
+                let (x, y) = ((area.x1 + x) as f32, (area.y1 + y) as f32);
+
+                shapes.push(Shape::Rectangle(
+                    Rect::new(x, y, x + 1., y + 1.),
+                    ZDepth::default(),
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid((*new).into()),
+                ));
+            }
+        }
+        if shapes.is_empty() {
+            return;
+        }
+        self.effects.extend_from_slice(&[
+            Effect::ViewBlendingChanged(Blending::Constant),
+            Effect::ViewPaintFinal(shapes),
+        ]);
+        self.active_view_mut().touch();
+    }
+
+    /// Replace the palette with at most `n` colors representing the selection,
+    /// or the active view.
+    fn quantize(&mut self, n: usize) {
+        if n == 0 {
+            return self.message("Error: color count must be at least 1", MessageType::Error);
+        }
+        let mut colors = Vec::new();
+
+        for (area, mask) in self.image_areas() {
+            let pixels = match self.active_view().layer.get_snapshot_rect(&area) {
+                Some((_, pixels)) => pixels,
+                None => continue,
+            };
+            let (w, h) = (area.width(), area.height());
+
+            colors.extend(pixels.iter().enumerate().filter_map(|(i, c)| {
+                let (x, y) = (i as i32 % w, h - i as i32 / w - 1);
+
+                if mask.as_ref().map_or(true, |m| m.contains(x, y)) {
+                    Some(*c)
+                } else {
+                    None
+                }
+            }));
+        }
+        let colors = quantize::median_cut(&colors, n);
+        if colors.is_empty() {
+            return self.message("Error: there are no colors to quantize", MessageType::Error);
+        }
+        self.palette.clear();
+        self.palette.colors.extend(colors);
+        self.center_palette();
+    }
+
+    /// Paint the curve being drawn with the curve tool, if any.
+    fn finish_curve(&mut self) {
+        if let Tool::Curve(curve) = &mut self.tool {
+            *curve = CurveState::default();
+
+            if let BrushState::DrawStarted(_) | BrushState::Drawing(_) = self.brush.state {
+                self.brush.stop_drawing();
+                self.active_view_mut().touch();
+            }
+        }
+    }
+
+    /// Discard the curve being drawn with the curve tool, if any.
+    fn cancel_curve(&mut self) {
+        if let Tool::Curve(curve) = &mut self.tool {
+            *curve = CurveState::default();
+
+            self.brush.state = BrushState::NotDrawing;
+            self.brush.stroke.clear();
+        }
+    }
+
+    /// Close the polygon being drawn with the polygon tool, if any, and paint it.
+    fn finish_polygon(&mut self) {
+        if let Tool::Polygon(polygon) = &mut self.tool {
+            let mut vertices = std::mem::take(&mut polygon.vertices);
+            let filled = polygon.filled;
+
+            if let BrushState::DrawStarted(_) | BrushState::Drawing(_) = self.brush.state {
+                // Drop the vertex following the cursor.
+                vertices.pop();
+
+                self.brush.draw_polygon(&vertices, true, filled);
+                self.brush.stop_drawing();
+                self.active_view_mut().touch();
+            }
+        }
+    }
+
+    /// Discard the polygon being drawn with the polygon tool, if any.
+    fn cancel_polygon(&mut self) {
+        if let Tool::Polygon(polygon) = &mut self.tool {
+            *polygon = PolygonState {
+                filled: polygon.filled,
+                ..PolygonState::default()
+            };
+
+            self.brush.state = BrushState::NotDrawing;
+            self.brush.stroke.clear();
+        }
+    }
+
+    /// Paint text in the foreground color, with the bottom-left corner of its
+    /// first line at the given position.
+    fn paint_text(&mut self, text: &str, origin: Point2<i32>) {
+        let fg = self.fg;
+        let shapes: Vec<_> = self
+            .font
+            .points(text)
+            .into_iter()
+            .map(|p| {
+                let (x, y) = ((origin.x + p.x) as f32, (origin.y + p.y) as f32);
+
+                Shape::Rectangle(
+                    Rect::new(x, y, x + 1., y + 1.),
+                    ZDepth::default(),
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(fg.into()),
+                )
+            })
+            .collect();
+
+        if shapes.is_empty() {
+            return;
+        }
+        self.effects.extend_from_slice(&[
+            Effect::ViewBlendingChanged(Blending::Constant),
+            Effect::ViewPaintFinal(shapes),
+        ]);
+        self.active_view_mut().touch();
+    }
+
+    /// Return how a new selection is combined with the current one, based on
+    /// the modifier keys held.
+    fn selection_op(&self) -> MaskOp {
+        if self.keys_pressed.contains(&Key::Shift) {
+            MaskOp::Union
+        } else if self.keys_pressed.contains(&Key::Control) {
+            MaskOp::Subtract
+        } else if self.keys_pressed.contains(&Key::Alt) {
+            MaskOp::Intersect
+        } else {
+            MaskOp::Replace
+        }
+    }
+
+    /// Select the given pixels, positioned at the given origin, combining them
+    /// with the current selection.
+    fn select(&mut self, region: Option<(Point2<i32>, Mask)>, op: MaskOp) {
+        let (origin, mask) = region.unwrap_or_else(|| (Point2::new(0, 0), Mask::new(0, 0)));
+        let current = match self.selection {
+            Some(s) => {
+                let r = s.abs().bounds();
+                let mask = self
+                    .selection_mask()
+                    .cloned()
+                    .unwrap_or_else(|| Mask::full(r.width() as u32, r.height() as u32));
+
+                (Point2::new(r.x1, r.y1), mask)
+            }
+            None => (Point2::new(0, 0), Mask::new(0, 0)),
+        };
+        let layer = self.active_view().layer_bounds();
+        let selected = Mask::combine(op, (current.0, &current.1), (origin, &mask))
+            .and_then(|(origin, mask)| mask.crop(origin, layer));
+
+        if let Some((origin, mask)) = selected {
+            let (w, h) = (mask.width() as i32, mask.height() as i32);
+
+            self.switch_mode(Mode::Visual(VisualState::default()));
+            self.selection = Some(Selection::new(
+                origin.x,
+                origin.y,
+                origin.x + w,
+                origin.y + h,
+            ));
+            self.selection_mask = if mask.is_full() { None } else { Some(mask) };
+        } else {
+            self.selection = None;
+            self.selection_mask = None;
+        }
+    }
+
+    /// Close the lasso, and select the pixels within it.
+    fn finish_lasso(&mut self) {
+        let vertices = if let Tool::Lasso(lasso) = &mut self.tool {
+            lasso.dragging = false;
+            std::mem::take(&mut lasso.vertices)
+        } else {
+            return;
+        };
+        let op = self.selection_op();
+
+        self.select(Mask::polygon(&vertices), op);
+    }
+
+    /// Yank the selection.
+    fn yank_selection(&mut self) -> Option<Rect<i32>> {
+        if let Mode::Visual(VisualState::Selecting { .. }) = self.mode {
+            if let Some((s, mask)) = self.selected_area() {
+                self.active_view_mut().yank(s, mask.clone());
+
+                self.selection = Some(Selection::from(s));
+                self.selection_mask = mask;
+                self.switch_mode(Mode::Visual(VisualState::Pasting));
+
+                return Some(s);
+            }
+        }
+        None
+    }
+
+    /// Rotate or scale the selection. The transformed pixels are copied into
+    /// the paste buffer and the original pixels are erased, so that the result
+    /// can be positioned before it is pasted.
+    fn transform_selection(&mut self, t: Transform) {
+        if let Mode::Visual(VisualState::Selecting { .. }) = self.mode {
+            if let Some((s, mask)) = self.selected_area() {
+                let (w, h) = t.size(s.width() as u32, s.height() as u32);
+                let extent = self.active_view().extent();
+                let (max_w, max_h) = (
+                    extent.width() * Self::MAX_TRANSFORM_SCALE,
+                    extent.height() * Self::MAX_TRANSFORM_SCALE,
+                );
+
+                if w > max_w || h > max_h {
+                    return self.message(
+                        format!(
+                            "Error: transformed selection would be {}x{}, maximum is {}x{}",
+                            w, h, max_w, max_h
+                        ),
+                        MessageType::Error,
+                    );
+                }
+                let (w, h) = (w as i32, h as i32);
+
+                // Keep the result centered on the original selection.
+                let x = s.x1 + (s.width() - w) / 2;
+                let y = s.y1 + (s.height() - h) / 2;
+                let transformed = mask
+                    .clone()
+                    .unwrap_or_else(|| Mask::full(s.width() as u32, s.height() as u32))
+                    .transform(t);
+
+                self.active_view_mut().transform(s, t, mask);
+
+                // Note that the effects generated here will be processed *before* the
+                // view operations.
+                self.command(Command::SelectionErase);
+
+                self.selection = Some(Selection::from(Rect::new(x, y, x + w, y + h)));
+                self.selection_mask = if transformed.is_full() {
+                    None
+                } else {
+                    Some(transformed)
+                };
+                self.switch_mode(Mode::Visual(VisualState::Pasting));
+            }
+        }
+    }
+
+    fn undo(&mut self, id: ViewId) {
+        self.restore_view_snapshot(id, Direction::Backward);
+    }
+
+    fn redo(&mut self, id: ViewId) {
+        self.restore_view_snapshot(id, Direction::Forward);
+    }
+
+    fn restore_view_snapshot(&mut self, id: ViewId, dir: Direction) {
+        self.view_mut(id).restore_snapshot(dir);
+        self.organize_views();
+        self.cursor_dirty();
+    }
+
+    ///////////////////////////////////////////////////////////////////////////
+    // Internal command handler
+    ///////////////////////////////////////////////////////////////////////////
+
+    fn handle_internal_cmd(&mut self, cmd: InternalCommand, exec: &mut Execution) {
+        match cmd {
+            InternalCommand::StopRecording => match exec.stop_recording() {
+                Ok(path) => {
+                    self.message(
+                        format!("Recording saved to `{}`", path.display()),
+                        MessageType::Execution,
+                    );
+                    info!("recording: events saved to `{}`", path.display());
+                    self.quit(ExitReason::Normal);
+                }
+                Err(e) => {
+                    error!("recording: error stopping: {}", e);
+                }
+            },
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////////////
+    // Event handlers
+    ///////////////////////////////////////////////////////////////////////////
+
+    pub fn handle_event(&mut self, event: Event, exec: &mut Execution) {
+        if let Execution::Recording {
+            ref mut events,
+            start,
+            ..
+        } = exec
+        {
+            events.push(TimedEvent::new(
+                self.frame_number,
+                start.elapsed(),
+                event.clone(),
+            ));
+        }
+
+        match event {
+            Event::MouseInput(btn, st) => {
+                if self.settings["input/mouse"].is_set() {
+                    self.handle_mouse_input(btn, st);
+                }
+            }
+            Event::MouseWheel(delta) => {
+                if self.settings["input/mouse"].is_set() {
+                    self.handle_mouse_wheel(delta);
+                }
+            }
+            Event::CursorMoved(position) => {
+                if self.settings["input/mouse"].is_set() {
+                    let coords = self.window_to_session_coords(position);
+                    self.handle_cursor_moved(coords);
+                }
+            }
+            Event::KeyboardInput(input) => self.handle_keyboard_input(input, exec),
+            Event::ReceivedCharacter(c, mods) => self.handle_received_character(c, mods),
+            Event::Paste(p) => self.handle_paste(p),
+        }
+    }
+
+    pub fn resize(&mut self, size: platform::LogicalSize, scale: f64) {
+        let (w, h) = (size.width / scale, size.height / scale);
+
+        self.width = w as f32;
+        self.height = h as f32;
+
+        // TODO: Reset session cursor coordinates
+        self.center_palette();
+        self.center_active_view();
+    }
+
+    pub fn rescale(&mut self, old: f64, new: f64) {
+        let (w, h) = (self.width as f64 * old, self.height as f64 * old);
+
+        self.resize(platform::LogicalSize::new(w, h), new);
+        self.effects.push(Effect::SessionScaled(new));
+    }
+
+    pub fn handle_resized(&mut self, size: platform::LogicalSize) {
+        self.resize(size, self.settings["scale"].to_f64());
+        self.effects.push(Effect::SessionResized(size));
+    }
+
+    fn handle_mouse_input(&mut self, button: platform::MouseButton, state: platform::InputState) {
+        if button != platform::MouseButton::Left {
+            return;
+        }
+        self.mouse_state = state;
+
+        // Pan tool.
+        match &mut self.tool {
+            Tool::Pan(ref mut p) => match (&p, state) {
+                (PanState::Panning, InputState::Released) => {
+                    *p = PanState::NotPanning;
+                    return;
+                }
+                (PanState::NotPanning, InputState::Pressed) => {
+                    *p = PanState::Panning;
+                    return;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        // Color editor.
+        if let Some(editor) = &mut self.color_editor {
+            match state {
+                InputState::Pressed => {
+                    if let Some(control) = editor.control_at(*self.cursor) {
+                        editor.dragging = Some(control);
+                        editor.drag(control, *self.cursor);
+                        self.apply_color_editor();
+                        return;
+                    }
+                }
+                InputState::Released if editor.dragging.is_some() => {
+                    editor.dragging = None;
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        match state {
+            InputState::Pressed => {
                 // Click on palette.
                 if let Some(color) = self.palette.hover {
                     if self.mode == Mode::Command {
@@ -1882,24 +3317,159 @@ impl Session {
 
                         let extent = v.extent();
 
+                        // Selection tools work in both normal and visual mode.
+                        if matches!(
+                            self.mode,
+                            Mode::Normal | Mode::Visual(VisualState::Selecting { .. })
+                        ) {
+                            let p = Point2::new(p.x as i32, p.y as i32);
+
+                            match &mut self.tool {
+                                Tool::Lasso(lasso) => {
+                                    // Clicking on the first vertex closes the polygon.
+                                    if lasso.vertices.len() >= 3 && lasso.vertices[0] == p {
+                                        self.finish_lasso();
+                                    } else {
+                                        lasso.vertices.push(p);
+                                        lasso.dragging = false;
+                                    }
+                                    return;
+                                }
+                                Tool::Wand => {
+                                    let op = self.selection_op();
+                                    let region = Mask::from_points(&self.flood_region(p));
+
+                                    self.select(region, op);
+                                    return;
+                                }
+                                // Gradients fill the selection, if any.
+                                Tool::Gradient(start) => {
+                                    *start = Some(p);
+                                    return;
+                                }
+                                _ => {}
+                            }
+                        }
+
                         match self.mode {
                             Mode::Normal => match self.tool {
-                                Tool::Brush => {
+                                Tool::Brush | Tool::Shape(_) => {
                                     let color = if self.brush.is_set(BrushMode::Erase) {
                                         Rgba8::TRANSPARENT
                                     } else {
                                         self.fg
                                     };
+                                    let dither_bg = self.settings["dither/bg"].is_set();
+
+                                    self.brush.dither_color =
+                                        if dither_bg { Some(self.bg) } else { None };
+                                    // Shade backwards through the ramp while <ctrl> is held.
+                                    self.brush.shade_step =
+                                        if self.keys_pressed.contains(&Key::Control) {
+                                            -1
+                                        } else {
+                                            1
+                                        };
                                     self.brush.start_drawing(p.into(), color, extent);
                                 }
                                 Tool::Sampler => {
                                     self.sample_color();
                                 }
                                 Tool::Pan(_) => {}
+                                // Handled above, for both normal and visual mode.
+                                Tool::Lasso(_) | Tool::Wand | Tool::Gradient(_) => {}
+                                Tool::Curve(ref mut curve) => {
+                                    let p = Point2::new(p.x as i32, p.y as i32);
+                                    let drawing = matches!(
+                                        self.brush.state,
+                                        BrushState::DrawStarted(_) | BrushState::Drawing(_)
+                                    );
+
+                                    if curve.points.is_empty() || !drawing {
+                                        // Start a new curve by dragging its end point.
+                                        let color = if self.brush.is_set(BrushMode::Erase) {
+                                            Rgba8::TRANSPARENT
+                                        } else {
+                                            self.fg
+                                        };
+                                        curve.points = vec![p, p];
+                                        curve.dragging = Some(1);
+
+                                        self.brush.start_drawing(
+                                            ViewCoords::new(p.x, p.y),
+                                            color,
+                                            extent,
+                                        );
+                                        self.brush.draw_curve(&curve.points);
+                                    } else if let Some(i) = curve.point_at(p) {
+                                        curve.dragging = Some(i);
+                                    } else if curve.points.len() < 4 {
+                                        curve.points.push(p);
+                                        curve.dragging = Some(curve.points.len() - 1);
+
+                                        self.brush.draw_curve(&curve.points);
+                                    }
+                                }
+                                Tool::Polygon(ref mut polygon) => {
+                                    let p = Point2::new(p.x as i32, p.y as i32);
+                                    let drawing = matches!(
+                                        self.brush.state,
+                                        BrushState::DrawStarted(_) | BrushState::Drawing(_)
+                                    );
+                                    // Clicking on the first vertex closes the polygon.
+                                    let closing =
+                                        polygon.vertices.len() > 3 && polygon.vertices[0] == p;
+
+                                    if polygon.vertices.is_empty() || !drawing {
+                                        // Start a new polygon. The second vertex follows
+                                        // the cursor until the next click.
+                                        let color = if self.brush.is_set(BrushMode::Erase) {
+                                            Rgba8::TRANSPARENT
+                                        } else {
+                                            self.fg
+                                        };
+                                        polygon.vertices = vec![p, p];
+
+                                        self.brush.start_drawing(
+                                            ViewCoords::new(p.x, p.y),
+                                            color,
+                                            extent,
+                                        );
+                                        self.brush.draw_polygon(&polygon.vertices, false, false);
+                                    } else if closing {
+                                        self.finish_polygon();
+                                    } else {
+                                        polygon.vertices.push(p);
+                                    }
+                                }
+                                Tool::Text(ref text) => {
+                                    let text = text.clone();
+                                    self.paint_text(&text, Point2::new(p.x as i32, p.y as i32));
+                                }
                                 Tool::FloodFill => {
                                     let start_time = time::Instant::now();
-                                    let filler = FloodFiller::new(self.active_view(), p, self.fg);
-                                    if let Some(shapes) = filler.and_then(|f| f.run()) {
+                                    let options = self.flood_options();
+                                    let ViewExtent { fw, nframes, .. } = extent;
+                                    let frame = p.x as usize / fw as usize;
+
+                                    let mut filler =
+                                        FloodFiller::new(self.active_view(), self.fg, options);
+
+                                    if self.brush.is_set(BrushMode::Multi) {
+                                        // Fill every frame from the current one onwards, at
+                                        // the same position within the frame. Each fill is
+                                        // confined to its frame, whatever the scope.
+                                        for i in frame..nframes {
+                                            let x = p.x + ((i - frame) as u32 * fw) as f32;
+                                            let bounds = FloodScope::Frame.bounds(extent, x as i32);
+
+                                            filler.fill(ViewCoords::new(x, p.y), bounds);
+                                        }
+                                    } else {
+                                        filler.fill(p, options.scope.bounds(extent, p.x as i32));
+                                    }
+
+                                    if let Some(shapes) = filler.run() {
                                         self.effects.push(Effect::ViewPaintFinal(shapes));
                                         self.active_view_mut().touch();
                                     }
@@ -1918,9 +3488,11 @@ impl Session {
                                         *dragging = true;
                                     } else {
                                         self.selection = Some(unit);
+                                        self.selection_mask = None;
                                     }
                                 } else {
                                     self.selection = Some(unit);
+                                    self.selection_mask = None;
                                 }
                             }
                             Mode::Visual(VisualState::Pasting) => {
@@ -1939,6 +3511,7 @@ impl Session {
                     match self.mode {
                         Mode::Visual(VisualState::Selecting { ref mut dragging }) => {
                             self.selection = None;
+                            self.selection_mask = None;
                             *dragging = false;
                         }
                         _ => {}
@@ -1948,9 +3521,17 @@ impl Session {
             InputState::Released => match self.mode {
                 Mode::Visual(VisualState::Selecting { ref mut dragging }) => {
                     *dragging = false;
+
+                    if let Tool::Lasso(LassoState { dragging: true, .. }) = self.tool {
+                        self.finish_lasso();
+                    }
+                    self.finish_gradient();
                 }
                 Mode::Normal => {
-                    if let Tool::Brush = self.tool {
+                    if let Tool::Lasso(LassoState { dragging: true, .. }) = self.tool {
+                        self.finish_lasso();
+                    }
+                    if let Tool::Brush | Tool::Shape(_) = self.tool {
                         match self.brush.state {
                             BrushState::Drawing { .. } | BrushState::DrawStarted { .. } => {
                                 self.brush.stop_drawing();
@@ -1959,6 +3540,13 @@ impl Session {
                             _ => {}
                         }
                     }
+                    if let Tool::Curve(curve) = &mut self.tool {
+                        // The curve is done once both control points are placed.
+                        if curve.dragging.take().is_some() && curve.points.len() == 4 {
+                            self.finish_curve();
+                        }
+                    }
+                    self.finish_gradient();
                 }
                 _ => {}
             },
@@ -1990,6 +3578,14 @@ impl Session {
         self.cursor = cursor;
         self.cursor_dirty();
 
+        if let Some(editor) = &mut self.color_editor {
+            if let Some(control) = editor.dragging {
+                editor.drag(control, *cursor);
+                self.apply_color_editor();
+                return;
+            }
+        }
+
         match self.tool {
             Tool::Pan(PanState::Panning) => {
                 self.pan(cursor.x - prev_cursor.x, cursor.y - prev_cursor.y);
@@ -1997,6 +3593,19 @@ impl Session {
             Tool::Sampler if self.mouse_state == InputState::Pressed => {
                 self.sample_color();
             }
+            Tool::Lasso(_) | Tool::Wand if self.mode != Mode::Visual(VisualState::Pasting) => {
+                if let Tool::Lasso(lasso) = &mut self.tool {
+                    let p = Point2::new(p.x.floor() as i32, p.y.floor() as i32);
+
+                    if self.mouse_state == InputState::Pressed
+                        && !lasso.vertices.is_empty()
+                        && lasso.vertices.last() != Some(&p)
+                    {
+                        lasso.vertices.push(p);
+                        lasso.dragging = true;
+                    }
+                }
+            }
             _ => {
                 match self.mode {
                     Mode::Normal => match self.tool {
@@ -2017,6 +3626,31 @@ impl Session {
                             }
                             _ => {}
                         },
+                        Tool::Curve(ref mut curve) if p != prev_p => {
+                            if let Some(i) = curve.dragging {
+                                curve.points[i] = Point2::new(p.x as i32, p.y as i32);
+                                self.brush.draw_curve(&curve.points);
+                            }
+                        }
+                        Tool::Polygon(ref mut polygon) if p != prev_p => {
+                            let drawing = matches!(
+                                self.brush.state,
+                                BrushState::DrawStarted(_) | BrushState::Drawing(_)
+                            );
+
+                            if let (true, Some(last)) = (drawing, polygon.vertices.last_mut()) {
+                                *last = Point2::new(p.x as i32, p.y as i32);
+                                self.brush.draw_polygon(&polygon.vertices, false, false);
+                            }
+                        }
+                        Tool::Shape(figure) if p != prev_p => match self.brush.state {
+                            BrushState::DrawStarted { .. } | BrushState::Drawing { .. } => {
+                                let constrain = self.keys_pressed.contains(&Key::Shift);
+
+                                self.brush.draw_figure(figure, p.into(), constrain);
+                            }
+                            _ => {}
+                        },
                         _ => {}
                     },
                     Mode::Visual(VisualState::Selecting { dragging: false }) => {
@@ -2371,9 +4005,13 @@ impl Session {
     ///////////////////////////////////////////////////////////////////////////
 
     /// Process a command.
-    fn command(&mut self, cmd: Command) {
+    fn command(&mut self, mut cmd: Command) {
         debug!("command: {:?}", cmd);
 
+        if let Err(err) = self.resolve_colors(&mut cmd) {
+            return self.message(format!("Error: {}", err), MessageType::Error);
+        }
+
         match cmd {
             Command::Mode(m) => {
                 self.toggle_mode(m);
@@ -2400,8 +4038,79 @@ impl Session {
             Command::BrushToggle(mode) => {
                 self.brush.toggle(mode);
             }
+            Command::BrushSymmetry(center) => {
+                // Centers are stored in half-pixel units, to allow for centers
+                // between pixels, eg. `4.5 4.5`.
+                self.brush.symmetry = center
+                    .map(|(x, y)| Point2::new((x * 2.).round() as i32, (y * 2.).round() as i32));
+            }
             Command::Brush => {
-                self.unimplemented();
+                self.brush.stamp = None;
+            }
+            Command::BrushCapture => {
+                if let Some(s) = self.selection {
+                    let v = self.active_view();
+                    let s = s.abs().bounds();
+
+                    if !s.intersects(v.layer_bounds()) {
+                        self.message("Error: selection is outside of view", MessageType::Error);
+                        return;
+                    }
+                    let s = s.intersection(v.layer_bounds());
+                    let pixels = self
+                        .views
+                        .get(v.id)
+                        .expect(&format!("view #{} must exist", v.id))
+                        .layer
+                        .get_snapshot_rect(&s)
+                        .map(|(_, pixels)| pixels);
+
+                    if let Some(pixels) = pixels {
+                        let (w, h) = (s.width() as u32, s.height() as u32);
+
+                        self.brush.stamp = Some(Stamp::new(w, h, pixels));
+                        self.message(
+                            format!("Brush captured from selection ({}x{})", w, h),
+                            MessageType::Info,
+                        );
+                        self.switch_mode(Mode::Normal);
+                        self.tool(Tool::Brush);
+                    }
+                } else {
+                    self.message("Error: nothing selected", MessageType::Error);
+                }
+            }
+            Command::BrushLoad(ref path) => match image::load(path) {
+                Ok((buffer, w, h)) => {
+                    let pixels = Rgba8::align(&buffer).to_vec();
+
+                    self.brush.stamp = Some(Stamp::new(w, h, pixels));
+                    self.message(
+                        format!("Brush loaded from {} ({}x{})", path, w, h),
+                        MessageType::Info,
+                    );
+                    self.tool(Tool::Brush);
+                }
+                Err(err) => {
+                    self.message(format!("Error: `{}`: {}", path, err), MessageType::Error);
+                }
+            },
+            Command::BrushSave(ref path) => {
+                if let Some(stamp) = &self.brush.stamp {
+                    match image::save_as(path, stamp.width, stamp.height, 1, &stamp.pixels) {
+                        Ok(()) => {
+                            self.message(format!("Brush written to {}", path), MessageType::Info)
+                        }
+                        Err(err) => {
+                            self.message(format!("Error: `{}`: {}", path, err), MessageType::Error)
+                        }
+                    }
+                } else {
+                    self.message(
+                        "Error: brush has no stamp, capture one with `:brush/capture`",
+                        MessageType::Error,
+                    );
+                }
             }
             Command::BrushSize(op) => {
                 let b = &mut self.brush;
@@ -2473,6 +4182,7 @@ impl Session {
             Command::Echo(ref v) => {
                 let result = match v {
                     Value::Str(s) => Ok(Value::Str(s.clone())),
+                    Value::Rgba8(c) => Ok(Value::Rgba8(*c)),
                     Value::Ident(s) => match s.as_str() {
                         "config/dir" => Ok(Value::Str(format!(
                             "{}",
@@ -2498,14 +4208,205 @@ impl Session {
                 }
             }
             Command::PaletteAdd(rgba) => {
-                self.palette.add(rgba);
+                self.palette.add(rgba.rgba());
                 self.center_palette();
             }
             Command::PaletteClear => {
                 self.palette.clear();
             }
+            Command::PaletteUse(name) => {
+                if let Err(e) = self.use_palette(&name) {
+                    self.message(format!("Error: {}", e), MessageType::Error);
+                }
+            }
+            Command::PaletteNew(name) => {
+                if self.palette_names().contains(&name.as_str()) {
+                    return self.message(
+                        format!("Error: palette `{}` already exists", name),
+                        MessageType::Error,
+                    );
+                }
+                self.palettes.insert(name.clone(), PaletteData::default());
+                self.command(Command::PaletteUse(name));
+            }
+            Command::PaletteVariant(None) => {
+                self.palette_variant = None;
+            }
+            Command::PaletteVariant(Some(name)) => {
+                let result = self.palette_colors(&name).and_then(|colors| {
+                    PaletteSwap::new(&self.palette.colors, &colors).map(|_| colors)
+                });
+
+                match result {
+                    Ok(colors) => {
+                        self.message(
+                            format!("Previewing palette variant `{}`", name),
+                            MessageType::Info,
+                        );
+                        self.palette_variant = Some((name, colors));
+                    }
+                    Err(e) => self.message(format!("Error: {}", e), MessageType::Error),
+                }
+            }
+            Command::PaletteList => {
+                let names = self
+                    .palette_names()
+                    .iter()
+                    .map(|n| {
+                        if *n == self.palette.name {
+                            format!("[{}]", n)
+                        } else {
+                            n.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                self.message(format!("Palettes: {}", names), MessageType::Info);
+            }
+            Command::PaletteInsert(index, color) => {
+                // Without an index or a hovered color, the color is appended.
+                let size = self.palette.size();
+                let index = index.or(self.palette.hover_index).or(Some(size));
+
+                match self.palette_index(index, size + 1) {
+                    Ok(index) => {
+                        self.palette.insert(index, color.rgba());
+                        self.center_palette();
+                    }
+                    Err(err) => self.message(format!("Error: {}", err), MessageType::Error),
+                }
+            }
+            Command::PaletteRemove(index) => match self.palette_index(index, self.palette.size()) {
+                Ok(index) => {
+                    self.palette.remove(index);
+                    self.center_palette();
+                }
+                Err(err) => self.message(format!("Error: {}", err), MessageType::Error),
+            },
+            Command::PaletteMove(from, to) => {
+                let size = self.palette.size();
+                match self.palette_index(from, size) {
+                    Ok(from) if to < size => self.palette.move_color(from, to),
+                    Ok(_) => self.message(
+                        format!("Error: palette index out of range (0..{})", size),
+                        MessageType::Error,
+                    ),
+                    Err(err) => self.message(format!("Error: {}", err), MessageType::Error),
+                }
+            }
+            Command::PaletteSwap(a, b) => {
+                let size = self.palette.size();
+                match self.palette_index(a, size) {
+                    Ok(a) if b < size => self.palette.swap(a, b),
+                    Ok(_) => self.message(
+                        format!("Error: palette index out of range (0..{})", size),
+                        MessageType::Error,
+                    ),
+                    Err(err) => self.message(format!("Error: {}", err), MessageType::Error),
+                }
+            }
+            Command::PaletteSet(index, color, recolor) => {
+                let index = match self.palette_index(index, self.palette.size()) {
+                    Ok(index) => index,
+                    Err(err) => return self.message(format!("Error: {}", err), MessageType::Error),
+                };
+                let color = color.rgba();
+                let old = self.palette.set(index, color);
+
+                // Pixels of indexed views that use the slot take its new color.
+                self.sync_indexed_views();
+
+                if recolor && old != color {
+                    // Indexed views follow their own palette.
+                    let ids: Vec<ViewId> = self
+                        .views
+                        .iter()
+                        .filter(|v| v.indexed().is_none())
+                        .map(|v| v.id)
+                        .collect();
+                    let replaced: usize = ids
+                        .into_iter()
+                        .map(|id| self.replace_color(id, old, color, 0, None))
+                        .sum();
+
+                    self.message(format!("{} pixel(s) replaced", replaced), MessageType::Info);
+                }
+            }
+            Command::ColorEdit(None) => {
+                if self.color_editor.take().is_none() {
+                    self.color_editor = Some(ColorEditor::new(editor::Target::Foreground, self.fg));
+                }
+                self.sync_color_editor();
+            }
+            Command::ColorEdit(Some(target)) => match self.target_color(target) {
+                Some(color) => {
+                    self.color_editor = Some(ColorEditor::new(target, color));
+                    self.sync_color_editor();
+                }
+                None => self.message(
+                    format!(
+                        "Error: palette index out of range (0..{})",
+                        self.palette.size()
+                    ),
+                    MessageType::Error,
+                ),
+            },
+            Command::ColorChange(channel, change) => {
+                // Without an open editor, the foreground color is changed.
+                let fg = self.fg;
+                let open = self.color_editor.is_some();
+                let mut editor = self
+                    .color_editor
+                    .take()
+                    .unwrap_or_else(|| ColorEditor::new(editor::Target::Foreground, fg));
+
+                editor.change(channel, change);
+                self.set_target_color(editor.target, editor.color());
+
+                if open {
+                    self.color_editor = Some(editor);
+                }
+            }
+            Command::PaletteRampAdd(from, to) => {
+                let size = self.palette.size();
+
+                if from >= size || to >= size {
+                    self.message(
+                        format!("Error: palette index out of range (0..{})", size),
+                        MessageType::Error,
+                    );
+                } else {
+                    self.palette.ramps.push(Ramp::new(from, to));
+                }
+            }
+            Command::PaletteRampClear => {
+                self.palette.ramps.clear();
+            }
+            Command::PaletteCycleAdd(from, to, delay) => {
+                let size = self.palette.size();
+
+                if from >= size || to >= size {
+                    self.message(
+                        format!("Error: palette index out of range (0..{})", size),
+                        MessageType::Error,
+                    );
+                } else {
+                    self.palette.cycles.push(Cycle::new(from, to, delay));
+                }
+            }
+            Command::PaletteCycleClear => {
+                self.palette.cycles.clear();
+                self.cycle_time = time::Duration::from_secs(0);
+            }
+            Command::PaletteRamp(base, count) => {
+                if let Err(err) = self.palette_ramp(base.rgba(), count) {
+                    self.message(format!("Error: {}", err), MessageType::Error);
+                }
+            }
             Command::PaletteGradient(colorstart, colorend, steps) => {
-                self.palette.gradient(colorstart, colorend, steps);
+                self.palette
+                    .gradient(colorstart.rgba(), colorend.rgba(), steps);
                 self.center_palette();
             }
             Command::PaletteSort => {
@@ -2527,7 +4428,7 @@ impl Session {
                         .current_snapshot();
 
                     for pixel in pixels.iter().cloned() {
-                        if pixel != Rgba8::TRANSPARENT {
+                        if pixel != Rgba8::TRANSPARENT && !self.palette.colors.contains(&pixel) {
                             self.palette.add(pixel);
                         }
                     }
@@ -2535,6 +4436,39 @@ impl Session {
                 self.command(Command::PaletteSort);
                 self.center_palette();
             }
+            Command::PaletteStats => {
+                let stats = ColorStats::new(&self.palette.colors, &self.selected_colors());
+                let list = |items: Vec<String>| {
+                    if items.is_empty() {
+                        String::from("none")
+                    } else {
+                        items.join(" ")
+                    }
+                };
+                let used = stats
+                    .counts
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, n)| **n > 0)
+                    .map(|(i, n)| format!("{}:{}", i, n))
+                    .collect();
+                let unused = stats.unused().iter().map(|i| i.to_string()).collect();
+                let off_palette = stats
+                    .off_palette
+                    .iter()
+                    .map(|(c, n)| format!("{}:{}", c, n))
+                    .collect();
+
+                self.message(
+                    format!(
+                        "Used: {} | Unused: {} | Off-palette: {}",
+                        list(used),
+                        list(unused),
+                        list(off_palette)
+                    ),
+                    MessageType::Info,
+                );
+            }
             Command::PaletteWrite(path) => match File::create(&path) {
                 Ok(mut f) => {
                     for color in self.palette.colors.iter() {
@@ -2592,7 +4526,20 @@ impl Session {
                 self.active_view_mut().clear(bg);
             }
             Command::Fill(Some(color)) => {
-                self.active_view_mut().clear(color);
+                self.active_view_mut().clear(color.rgba());
+            }
+            Command::Replace(from, to, tolerance) => {
+                let (from, to) = (from.rgba(), to.rgba());
+                let replaced = self.replace_colors(&[self.views.active_id], from, to, tolerance);
+
+                self.message(format!("{} pixel(s) replaced", replaced), MessageType::Info);
+            }
+            Command::ReplaceAll(from, to, tolerance) => {
+                let (from, to) = (from.rgba(), to.rgba());
+                let ids: Vec<ViewId> = self.views.ids().collect();
+                let replaced = self.replace_colors(&ids, from, to, tolerance);
+
+                self.message(format!("{} pixel(s) replaced", replaced), MessageType::Info);
             }
             Command::Pan(x, y) => {
                 self.pan(
@@ -2612,6 +4559,26 @@ impl Session {
                     self.center_active_view();
                 }
             }
+            Command::ViewPalette(name) => {
+                if let Some(name) = name {
+                    if let Err(e) = self.use_palette(&name) {
+                        return self.message(format!("Error: {}", e), MessageType::Error);
+                    }
+                }
+                let name = self.palette.name.clone();
+                self.active_view_mut().palette = Some(name);
+            }
+            Command::ViewPaletteClear => {
+                self.active_view_mut().palette = None;
+            }
+            Command::ViewIndexed(true) => {
+                if let Err(e) = self.index_view(self.views.active_id) {
+                    self.message(format!("Error: {}", e), MessageType::Error);
+                }
+            }
+            Command::ViewIndexed(false) => {
+                self.active_view_mut().set_indexed(None);
+            }
             Command::ViewPrev => {
                 let id = self.views.active_id;
 
@@ -2758,6 +4725,20 @@ impl Session {
                     self.message(format!("Error: {}", e), MessageType::Error);
                 }
             }
+            Command::ExportCycle(scale, path) => {
+                let scale = scale.unwrap_or(self.active_view().zoom as u32);
+
+                if let Err(e) = self.export_cycle(Path::new(&path), scale) {
+                    self.message(format!("Error: {}", e), MessageType::Error);
+                }
+            }
+            Command::ExportVariants(scale, path, targets) => {
+                let scale = scale.unwrap_or(self.active_view().zoom as u32);
+
+                if let Err(e) = self.export_variants(Path::new(&path), scale, &targets) {
+                    self.message(format!("Error: {}", e), MessageType::Error);
+                }
+            }
             Command::Write(None) => match self.save_view(self.views.active_id) {
                 Ok((storage, written)) => self.message(
                     format!("\"{}\" {} pixels written", storage, written),
@@ -2860,8 +4841,11 @@ impl Session {
                 if let Some(ref mut s) = self.selection {
                     s.resize(x, y);
                 }
+                self.selection_mask = None;
             }
             Command::SelectionExpand => {
+                self.selection_mask = None;
+
                 let v = self.active_view();
                 let (fw, fh) = (v.fw as i32, v.fh as i32);
                 let (vw, vh) = (v.width() as i32, v.fh as i32);
@@ -2890,6 +4874,8 @@ impl Session {
                 }
             }
             Command::SelectionOffset(mut x, mut y) => {
+                self.selection_mask = None;
+
                 if let Some(s) = &mut self.selection {
                     let r = s.abs().bounds();
                     if r.width() <= 2 && x < 0 {
@@ -2922,7 +4908,14 @@ impl Session {
             }
             Command::SelectionPaste => {
                 if let (Mode::Visual(VisualState::Pasting), Some(s)) = (self.mode, self.selection) {
-                    self.active_view_mut().paste(s.abs().bounds());
+                    let mask = self.mask;
+                    let v = self.active_view_mut();
+
+                    if let Some(mask) = mask {
+                        v.paste_clipped(s.abs().bounds(), mask);
+                    } else {
+                        v.paste(s.abs().bounds());
+                    }
                 } else {
                     // TODO: Enter paste mode?
                 }
@@ -2931,31 +4924,99 @@ impl Session {
                 self.yank_selection();
             }
             Command::SelectionFlip(dir) => {
-                if let (Mode::Visual(VisualState::Selecting { .. }), Some(s)) =
+                if let (Mode::Visual(VisualState::Selecting { .. }), Some(_)) =
                     (self.mode, self.selection)
                 {
-                    let v = self.active_view_mut();
-                    let s = s.abs().bounds();
+                    let clip = self.mask;
+                    let area = self.selected_area();
 
-                    if s.intersects(v.layer_bounds()) {
-                        let s = s.intersection(v.layer_bounds());
+                    if let Some((s, mask)) = &area {
+                        let v = self.active_view_mut();
 
                         // The flip operation works by copying the flipped image into
                         // the paste buffer, and pasting.
-                        v.flip(s, dir);
-                        v.paste(s);
+                        v.flip(*s, dir.clone(), mask.clone());
+
+                        if let Some(clip) = clip {
+                            v.paste_clipped(*s, clip);
+                        } else {
+                            v.paste(*s);
+                        }
 
-                        self.selection = Some(Selection::from(s));
+                        self.selection = Some(Selection::from(*s));
+                        self.selection_mask = mask.clone();
                         self.switch_mode(Mode::Visual(VisualState::Pasting));
                     }
                     // Note that the effects generated here will be processed *before* the
                     // view operations.
                     self.command(Command::SelectionErase);
+
+                    // The selection now holds the flipped pixels.
+                    if let Some((_, Some(mask))) = area {
+                        self.selection_mask = Some(mask.flip(dir));
+                    }
                     self.command(Command::Mode(Mode::Visual(VisualState::Selecting {
                         dragging: false,
                     })));
                 }
             }
+            Command::SelectionRotate(angle) => {
+                self.transform_selection(Transform::Rotate(angle));
+            }
+            Command::SelectionScale(x, y) => {
+                self.transform_selection(Transform::Scale(x, y));
+            }
+            Command::Outline(placement, connectivity) => {
+                let fg = self.fg;
+                self.paint_fx(fg, |image| image.outline(placement, connectivity));
+            }
+            Command::Shadow(x, y, color) => {
+                let color = color.map_or(self.fg, |c| c.rgba());
+                self.paint_fx(color, |image| image.shadow(Vector2::new(x, y)));
+            }
+            Command::RemoveOrphans => {
+                self.paint_fx(Rgba8::TRANSPARENT, |image| image.orphans());
+            }
+            Command::PaletteRemap(metric, dithering) => {
+                self.remap(metric, dithering);
+            }
+            Command::PaletteQuantize(n) => {
+                self.quantize(n);
+            }
+            Command::CurveFinish => {
+                self.finish_curve();
+            }
+            Command::CurveCancel => {
+                self.cancel_curve();
+            }
+            Command::PolygonFinish => {
+                self.finish_polygon();
+            }
+            Command::PolygonCancel => {
+                self.cancel_polygon();
+            }
+            Command::Text(text, Some((x, y))) => {
+                self.paint_text(&text, Point2::new(x, y));
+            }
+            Command::Text(text, None) => {
+                self.tool(Tool::Text(text));
+            }
+            Command::TextFont(None, _) => {
+                self.font = Font::default();
+                self.message("Font reset to built-in glyphs", MessageType::Info);
+            }
+            Command::TextFont(Some(ref path), size) => match Font::load(path, size) {
+                Ok(font) => {
+                    self.message(
+                        format!("Font loaded from {} ({}x{})", path, font.gw, font.gh),
+                        MessageType::Info,
+                    );
+                    self.font = font;
+                }
+                Err(err) => {
+                    self.message(format!("Error: `{}`: {}", path, err), MessageType::Error);
+                }
+            },
             Command::SelectionCut => {
                 // To mimick the behavior of `vi`, we yank the selection
                 // before deleting it.
@@ -2964,41 +5025,88 @@ impl Session {
                 }
             }
             Command::SelectionFill(color) => {
-                if let Some(s) = self.selection {
+                if let Some((s, mask)) = self.selected_area() {
                     self.effects
-                        .push(Effect::ViewPaintFinal(vec![Shape::Rectangle(
-                            s.abs().bounds().map(|n| n as f32),
-                            ZDepth::default(),
-                            Rotation::ZERO,
-                            Stroke::NONE,
-                            Fill::Solid(color.unwrap_or(self.fg).into()),
-                        )]));
+                        .push(Effect::ViewPaintFinal(Session::area_shapes(
+                            s,
+                            mask.as_ref(),
+                            color.map_or(self.fg, |c| c.rgba()),
+                        )));
                     self.active_view_mut().touch();
                 }
             }
-            Command::SelectionErase => {
+            Command::SelectionGradient(axis) => {
                 if let Some(s) = self.selection {
+                    let bounds = self.active_view().layer_bounds();
+                    let s = s.abs().bounds();
+                    if !s.intersects(bounds) {
+                        return;
+                    }
+                    let s = s.intersection(bounds);
+                    let (x2, y2) = (s.x2 - 1, s.y2 - 1);
+                    let center = Point2::new((s.x1 + x2) / 2, (s.y1 + y2) / 2);
+                    let (start, end) = match axis {
+                        Axis::Horizontal => {
+                            (Point2::new(s.x1, center.y), Point2::new(x2, center.y))
+                        }
+                        Axis::Vertical => (Point2::new(center.x, s.y1), Point2::new(center.x, y2)),
+                    };
+                    // Radial gradients start from the center of the selection.
+                    let start = if self.settings["gradient/radial"].is_set() {
+                        center
+                    } else {
+                        start
+                    };
+                    let pixels = self.selected_points();
+
+                    self.paint_gradient(&pixels, start, end);
+                }
+            }
+            Command::SelectionColor(color, op) => {
+                let color = color.map_or(self.fg, |c| c.rgba());
+                let tolerance = self.flood_options().tolerance;
+                let (snapshot, pixels) = self.active_view().layer.current_snapshot();
+                let (w, h) = (snapshot.width() as i32, snapshot.height() as i32);
+                let points: Vec<_> = pixels
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| color::within_tolerance(**c, color, tolerance))
+                    .map(|(i, _)| Point2::new(i as i32 % w, h - i as i32 / w - 1))
+                    .collect();
+
+                self.select(Mask::from_points(&points), op);
+            }
+            Command::SelectionMask => {
+                if self.mask.take().is_some() {
+                    self.message("Selection mask off", MessageType::Info);
+                } else if let Some(s) = self.selection {
+                    self.mask = Some(s.abs().bounds());
+                    self.message("Selection mask on", MessageType::Info);
+                } else {
+                    self.message("Error: nothing is selected", MessageType::Error);
+                }
+            }
+            Command::SelectionErase => {
+                if let Some((s, mask)) = self.selected_area() {
                     self.effects.extend_from_slice(&[
                         Effect::ViewBlendingChanged(Blending::Constant),
-                        Effect::ViewPaintFinal(vec![Shape::Rectangle(
-                            s.abs().bounds().map(|n| n as f32),
-                            ZDepth::default(),
-                            Rotation::ZERO,
-                            Stroke::NONE,
-                            Fill::Solid(Rgba8::TRANSPARENT.into()),
-                        )]),
+                        Effect::ViewPaintFinal(Session::area_shapes(
+                            s,
+                            mask.as_ref(),
+                            Rgba8::TRANSPARENT,
+                        )),
                     ]);
                     self.active_view_mut().touch();
                 }
             }
             Command::PaintColor(rgba, x, y) => {
-                self.active_view_mut().paint_color(rgba, x, y);
+                self.active_view_mut().paint_color(rgba.rgba(), x, y);
             }
             Command::PaintLine(rgba, x1, y1, x2, y2) => {
                 let mut stroke = vec![];
                 Brush::line(Point2::new(x1, y1), Point2::new(x2, y2), &mut stroke);
                 for pt in stroke {
-                    self.active_view_mut().paint_color(rgba, pt.x, pt.y);
+                    self.active_view_mut().paint_color(rgba.rgba(), pt.x, pt.y);
                 }
             }
             Command::PaintForeground(x, y) => {
@@ -3178,4 +5286,308 @@ mod test {
             Some(kb)
         );
     }
+
+    fn session(w: u32, h: u32, pixels: Vec<Rgba8>) -> Session {
+        let proj_dirs = dirs::ProjectDirs::from("io", "cloudhead", "rx").unwrap();
+        let base_dirs = dirs::BaseDirs::new().unwrap();
+        let mut session = Session::new(640, 480, ".", proj_dirs, base_dirs);
+        let id = session.add_view(
+            FileStatus::New(FileStorage::Single(PathBuf::from("test.png"))),
+            w,
+            h,
+            vec![pixels],
+        );
+
+        session.edit_view(id);
+        session
+    }
+
+    fn painted(effects: &[Effect]) -> Vec<Rect<f32>> {
+        effects
+            .iter()
+            .filter_map(|e| match e {
+                Effect::ViewPaintDraft(shapes) | Effect::ViewPaintFinal(shapes) => Some(shapes),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|s| match s {
+                Shape::Rectangle(r, _, _, _, _) => Some(*r),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lock_alpha() {
+        let red = Rgba8::new(255, 0, 0, 128);
+        let mut s = session(2, 1, vec![Rgba8::TRANSPARENT, red]);
+        let extent = s.active_view().extent();
+
+        s.brush.set(BrushMode::LockAlpha);
+        s.brush
+            .start_drawing(ViewCoords::new(0, 0), color::WHITE, extent);
+
+        let effects = s.update(
+            &mut vec![],
+            &mut Execution::Normal,
+            time::Duration::from_secs(0),
+            time::Duration::from_secs(0),
+        );
+        assert_eq!(
+            painted(&effects),
+            vec![],
+            "transparent pixels are left alone"
+        );
+
+        let shapes = vec![Shape::Rectangle(
+            Rect::new(0., 0., 2., 1.),
+            ZDepth::ZERO,
+            Rotation::ZERO,
+            Stroke::NONE,
+            Fill::Solid(color::WHITE.into()),
+        )];
+        let output = s.lock_alpha(shapes);
+
+        assert_eq!(output.len(), 1);
+        match &output[0] {
+            Shape::Rectangle(r, _, _, _, Fill::Solid(c)) => {
+                assert_eq!(*r, Rect::new(1., 0., 2., 1.));
+                assert_eq!(Rgba8::from(*c), color::WHITE.alpha(red.a));
+            }
+            other => panic!("unexpected shape {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_selection_mask() {
+        let mut s = session(4, 4, vec![Rgba8::TRANSPARENT; 16]);
+        let extent = s.active_view().extent();
+        let mask = Rect::new(0, 0, 2, 2);
+
+        s.mask = Some(mask);
+        s.brush.size = 3;
+        s.brush
+            .start_drawing(ViewCoords::new(1, 1), color::WHITE, extent);
+
+        let effects = s.update(
+            &mut vec![],
+            &mut Execution::Normal,
+            time::Duration::from_secs(0),
+            time::Duration::from_secs(0),
+        );
+        let rects = painted(&effects);
+        let area = mask.map(|n| n as f32);
+
+        assert!(!rects.is_empty());
+        for r in rects {
+            assert!(
+                r.x1 >= area.x1 && r.y1 >= area.y1 && r.x2 <= area.x2 && r.y2 <= area.y2,
+                "{:?} is within the mask",
+                r
+            );
+        }
+    }
+
+    /// Return the pixels set on the given view since the last update, if any.
+    fn pixels_set(s: &Session, id: ViewId) -> Option<Vec<Rgba8>> {
+        s.view(id).ops.iter().rev().find_map(|op| match op {
+            ViewOp::SetPixels(pixels) => Some(pixels.clone()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_replace_color() {
+        let (w, k) = (Rgba8::WHITE, Rgba8::BLACK);
+        let mut s = session(3, 1, vec![w; 3]);
+        let a = s.views.active_id;
+        let b = s.add_view(
+            FileStatus::New(FileStorage::Single(PathBuf::from("b.png"))),
+            3,
+            1,
+            vec![vec![w; 3]],
+        );
+        s.edit_view(a);
+
+        let mut mask = Mask::new(3, 1);
+        mask.set(0, 0);
+        mask.set(2, 0);
+
+        s.selection = Some(Selection::new(0, 0, 3, 1));
+        s.selection_mask = Some(mask);
+
+        assert_eq!(s.replace_colors(&[a, b], w, k, 0), 5);
+        assert_eq!(
+            pixels_set(&s, a),
+            Some(vec![k, w, k]),
+            "the selection and its mask apply to the active view"
+        );
+        assert_eq!(
+            pixels_set(&s, b),
+            Some(vec![k, k, k]),
+            "other views are replaced entirely"
+        );
+
+        s.view_mut(b).ops.clear();
+        s.view_mut(a).ops.clear();
+        s.selection = Some(Selection::new(8, 8, 9, 9));
+        s.selection_mask = None;
+
+        assert_eq!(s.replace_colors(&[a, b], w, k, 0), 3);
+        assert_eq!(
+            pixels_set(&s, a),
+            None,
+            "nothing is selected in the active view"
+        );
+        assert_eq!(pixels_set(&s, b), Some(vec![k, k, k]));
+    }
+
+    #[test]
+    fn test_palette_preview() {
+        let (w, k) = (Rgba8::WHITE, Rgba8::BLACK);
+        let red = Rgba8::new(0xff, 0, 0, 0x80);
+        let mut s = session(3, 1, vec![w, k, w]);
+        let id = s.views.active_id;
+
+        assert_eq!(s.palette_preview(), None);
+
+        s.palette.colors = vec![w, k];
+        s.palette_variant = Some((String::from("red"), vec![red, k]));
+
+        let preview = s.palette_preview().unwrap();
+        assert_eq!(preview.view, id);
+        assert_eq!(
+            s.preview_pixels(&preview),
+            vec![red, k, red],
+            "previewed pixels replace the originals"
+        );
+        assert_eq!(s.view(id).layer.current_snapshot().1, &[w, k, w][..]);
+        assert!(s.view(id).ops.is_empty(), "the view isn't modified");
+        assert_eq!(
+            s.palette_preview(),
+            Some(preview),
+            "the preview is unchanged while its pixels and colors are"
+        );
+    }
+
+    #[test]
+    fn test_palette_highlight() {
+        let (w, k) = (Rgba8::WHITE, Rgba8::BLACK);
+        let red = Rgba8::new(0xff, 0, 0, 0xff);
+        let mut s = session(3, 1, vec![w, k, red]);
+
+        s.palette.colors = vec![w, k];
+        s.settings
+            .set("p/highlight", Value::Ident(String::from("hover")))
+            .unwrap();
+        assert_eq!(s.palette_preview(), None, "no color is hovered");
+
+        s.palette.hover = Some(k);
+        let preview = s.palette_preview().unwrap();
+        assert_eq!(s.preview_pixels(&preview), vec![w, w, red]);
+
+        s.palette_variant = Some((String::from("swapped"), vec![k, w]));
+        let preview = s.palette_preview().unwrap();
+        assert_eq!(
+            s.preview_pixels(&preview),
+            vec![k, w, red],
+            "the highlight is composed with the palette variant"
+        );
+
+        s.palette_variant = None;
+        s.settings
+            .set("p/highlight", Value::Ident(String::from("off-palette")))
+            .unwrap();
+        let preview = s.palette_preview().unwrap();
+        assert_eq!(s.preview_pixels(&preview), vec![w, k, color::RED]);
+    }
+
+    #[test]
+    fn test_indexed_view() {
+        let (w, k, red) = (Rgba8::WHITE, Rgba8::BLACK, Rgba8::new(0xff, 0, 0, 0xff));
+        let mut s = session(3, 1, vec![w, k, k]);
+        let id = s.views.active_id;
+
+        s.palette.name = String::from("bw");
+        s.palette.colors = vec![w, k];
+        s.index_view(id).unwrap();
+
+        assert_eq!(s.view(id).palette, Some(String::from("bw")));
+        assert_eq!(
+            s.view(id).layer.current_indices().unwrap().1,
+            &[0, 1, 1][..]
+        );
+
+        // Edits are snapped to the view's palette, with a warning.
+        s.view_mut(id).record_view_painted(vec![w, red, w]);
+        s.cleanup();
+
+        assert_eq!(s.view(id).layer.current_snapshot().1, &[w, k, w][..]);
+        assert!(s.view(id).is_damaged());
+        assert_eq!(s.message.message_type, MessageType::Warning);
+
+        // Palette slot edits recolor the pixels using the slot.
+        s.view_mut(id).okay();
+        s.command(Command::PaletteSet(Some(1), Color::Rgba(red), false));
+
+        assert_eq!(s.view(id).layer.current_snapshot().1, &[w, red, w][..]);
+        assert_eq!(s.view(id).indexed().unwrap().colors, vec![w, red]);
+
+        // Other palettes don't change the view's palette.
+        s.view_mut(id).okay();
+        s.command(Command::PaletteClear);
+        s.command(Command::PaletteNew(String::from("new")));
+        s.command(Command::PaletteAdd(Color::Rgba(k)));
+        s.sync_indexed_views();
+
+        assert_eq!(s.view(id).indexed().unwrap().colors, vec![w, red]);
+        assert_eq!(
+            s.view(id).layer.current_indices().unwrap().1,
+            &[0, 1, 0][..]
+        );
+
+        let path = std::env::temp_dir().join("rx-test-indexed-view.png");
+        s.view(id).save_png(&path, 1).unwrap();
+        let (pixels, _, _) = crate::image::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(Rgba8::align(&pixels), &[w, red, w][..]);
+    }
+
+    #[test]
+    fn test_transform_selection_size() {
+        let mut s = session(64, 64, vec![Rgba8::WHITE; 64 * 64]);
+        let selection = Selection::new(0, 0, 64, 64);
+
+        s.selection = Some(selection);
+        s.mode = Mode::Visual(VisualState::selecting());
+        s.transform_selection(Transform::Scale(10000., 10000.));
+
+        assert_eq!(
+            s.selection,
+            Some(selection),
+            "the selection is left as it is"
+        );
+        assert_eq!(s.message.message_type, MessageType::Error);
+
+        s.transform_selection(Transform::Scale(2., 2.));
+
+        assert_eq!(s.selection, Some(Selection::new(-32, -32, 96, 96)));
+    }
+
+    #[test]
+    fn test_gradient_fill_selection() {
+        let mut s = session(4, 1, vec![Rgba8::WHITE; 4]);
+
+        s.gradient_fill(Point2::new(0, 0), Point2::new(3, 0));
+        assert_eq!(painted(&s.effects).len(), 4, "the flood region is filled");
+
+        s.effects.clear();
+        s.selection = Some(Selection::new(1, 0, 3, 1));
+        s.gradient_fill(Point2::new(0, 0), Point2::new(3, 0));
+
+        let painted = painted(&s.effects);
+        assert_eq!(painted.len(), 2, "the selection is filled");
+        assert!(painted.iter().all(|r| r.x1 >= 1. && r.x2 <= 3.));
+    }
 }
@@ -5,6 +5,8 @@ pub use path::{Format, Path};
 pub use resource::{Edit, EditId, Snapshot, ViewResource};
 
 use crate::cmd::Axis;
+use crate::mask::Mask;
+use crate::pixels::Transform;
 use crate::session::{Direction, Session, SessionCoords};
 
 use crate::gfx::math::*;
@@ -97,16 +99,21 @@ pub enum ViewOp {
     Blit(Rect<f32>, Rect<f32>),
     /// Clear to a color.
     Clear(Rgba8),
-    /// Yank the given area into the paste buffer.
-    Yank(Rect<i32>),
-    /// Flips a given area horizontally or vertically.
-    Flip(Rect<i32>, Axis),
-    /// Blit the paste buffer into the given area.
-    Paste(Rect<i32>),
+    /// Yank the given area into the paste buffer, optionally masked.
+    Yank(Rect<i32>, Option<Mask>),
+    /// Flips a given area horizontally or vertically, optionally masked.
+    Flip(Rect<i32>, Axis, Option<Mask>),
+    /// Rotate or scale a given area into the paste buffer, optionally masked.
+    Transform(Rect<i32>, Transform, Option<Mask>),
+    /// Blit the paste buffer into the given area, optionally clipped to
+    /// another area.
+    Paste(Rect<i32>, Option<Rect<i32>>),
     /// Resize the view.
     Resize(u32, u32),
     /// Paint a single pixel.
     SetPixel(Rgba8, i32, i32),
+    /// Replace all pixels of the view.
+    SetPixels(Vec<Rgba8>),
 }
 
 /// A view on a sprite or image.
@@ -134,6 +141,8 @@ pub struct View<R> {
     pub state: ViewState,
     /// Animation state of the sprite displayed by this view.
     pub animation: Animation<Rect<f32>>,
+    /// Name of the palette activated with this view, if any.
+    pub palette: Option<String>,
     /// View resource.
     pub resource: R,
 
@@ -205,6 +214,7 @@ impl<R> View<R> {
             flip_y: false,
             file_status: fs,
             animation: Animation::new(frames),
+            palette: None,
             state: ViewState::Okay,
             saved_snapshot,
             resource,
@@ -291,16 +301,34 @@ impl<R> View<R> {
         self.ops.push(ViewOp::SetPixel(color, x, y));
     }
 
-    pub fn yank(&mut self, area: Rect<i32>) {
-        self.ops.push(ViewOp::Yank(area));
+    pub fn set_pixels(&mut self, pixels: Vec<Rgba8>) {
+        self.ops.push(ViewOp::SetPixels(pixels));
     }
 
-    pub fn flip(&mut self, area: Rect<i32>, dir: Axis) {
-        self.ops.push(ViewOp::Flip(area, dir));
+    pub fn yank(&mut self, area: Rect<i32>, mask: Option<Mask>) {
+        self.ops.push(ViewOp::Yank(area, mask));
+    }
+
+    pub fn flip(&mut self, area: Rect<i32>, dir: Axis, mask: Option<Mask>) {
+        self.ops.push(ViewOp::Flip(area, dir, mask));
+    }
+
+    pub fn transform(&mut self, area: Rect<i32>, t: Transform, mask: Option<Mask>) {
+        self.ops.push(ViewOp::Transform(area, t, mask));
     }
 
     pub fn paste(&mut self, area: Rect<i32>) {
-        self.ops.push(ViewOp::Paste(area));
+        self.ops.push(ViewOp::Paste(area, None));
+        self.touch();
+    }
+
+    /// Paste into the given area, only keeping the part of the paste buffer
+    /// that falls within `clip`.
+    pub fn paste_clipped(&mut self, area: Rect<i32>, clip: Rect<i32>) {
+        if !area.intersects(clip) {
+            return;
+        }
+        self.ops.push(ViewOp::Paste(area, Some(clip)));
         self.touch();
     }
 
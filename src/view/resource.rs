@@ -1,17 +1,16 @@
 use crate::gfx::color::{Rgb8, Rgba8};
 use crate::gfx::rect::Rect;
 use crate::image;
-use crate::pixels;
+use crate::palette::IndexedPalette;
 use crate::util;
 use crate::view::{ViewCoords, ViewExtent};
 
 use nonempty::NonEmpty;
 
-use gif::{self, SetParameter};
-
 use std::fmt;
 use std::fs::File;
 use std::io;
+use std::mem;
 use std::path::Path;
 use std::time;
 
@@ -21,6 +20,9 @@ pub struct ViewResource {
     pub history: NonEmpty<Edit>,
     pub cursor: usize,
     pub extent: ViewExtent,
+    /// Number of pixels that weren't in the palette of an indexed view, and
+    /// were replaced, when recording edits.
+    snapped: usize,
 }
 
 impl ViewResource {
@@ -30,31 +32,68 @@ impl ViewResource {
             history: NonEmpty::new(Edit::Initial),
             cursor: 0,
             extent,
+            snapped: 0,
         }
     }
 
+    /// Return the palette of the view, if it is indexed.
+    pub fn indexed(&self) -> Option<&IndexedPalette> {
+        self.layer.palette.as_ref()
+    }
+
+    /// Set the palette of the view, making it indexed, or make it RGBA with `None`.
+    /// The view's pixels are restored from the current snapshot with the new palette.
+    pub fn set_indexed(&mut self, palette: Option<IndexedPalette>) {
+        self.layer.palette = palette;
+        self.layer.load_snapshot();
+    }
+
+    /// Return the number of pixels replaced with palette colors since this was
+    /// last called.
+    pub fn take_snapped(&mut self) -> usize {
+        mem::take(&mut self.snapped)
+    }
+
     pub fn save<P: AsRef<Path>>(&self, rect: Rect<u32>, path: P) -> io::Result<(EditId, usize)> {
-        let (_, pixels) = self
-            .layer
-            .get_snapshot_rect(&rect.map(|n| n as i32))
-            .expect("rect should be within view");
         let (w, h) = (rect.width(), rect.height());
 
-        image::save_as(path, w, h, 1, &pixels)?;
+        self.write_png(path, rect, 1)?;
 
         Ok((self.cursor, (w * h) as usize))
     }
 
+    /// Write part of the view as a png, which is indexed if the view is.
+    fn write_png<P: AsRef<Path>>(&self, path: P, rect: Rect<u32>, scale: u32) -> io::Result<()> {
+        let rect = rect.map(|n| n as i32);
+        let (w, h) = (rect.width() as u32, rect.height() as u32);
+
+        match (&self.layer.palette, self.layer.get_indices_rect(&rect)) {
+            (Some(palette), Some(indices)) => {
+                let colors = palette.image_colors(&indices);
+
+                image::save_indexed(path, w, h, scale, &indices, &colors)
+            }
+            _ => {
+                let (_, pixels) = self
+                    .layer
+                    .get_snapshot_rect(&rect)
+                    .expect("rect should be within view");
+
+                image::save_as(path, w, h, scale, &pixels)
+            }
+        }
+    }
+
     pub fn record_view_resized(&mut self, pixels: Vec<Rgba8>, extent: ViewExtent) {
         self.history_record(Edit::ViewResized(self.extent, extent));
         self.extent = extent;
-        self.layer.push_snapshot(pixels, extent);
+        self.snapped += self.layer.push_snapshot(pixels, extent);
     }
 
     pub fn record_view_painted(&mut self, pixels: Vec<Rgba8>) {
         let extent = self.extent;
         self.history_record(Edit::ViewPainted);
-        self.layer.push_snapshot(pixels, extent);
+        self.snapped += self.layer.push_snapshot(pixels, extent);
     }
 
     pub fn history_truncate(&mut self) {
@@ -124,10 +163,10 @@ impl ViewResource {
     }
 
     pub fn save_png<P: AsRef<Path>>(&self, path: P, scale: u32) -> io::Result<usize> {
-        let (snapshot, pixels) = self.layer.current_snapshot();
+        let (snapshot, _) = self.layer.current_snapshot();
         let (w, h) = (snapshot.width(), snapshot.height());
 
-        image::save_as(path, w, h, scale, pixels)?;
+        self.write_png(path, Rect::origin(w, h), scale)?;
 
         Ok((w * h * scale) as usize)
     }
@@ -175,76 +214,24 @@ impl ViewResource {
         palette: &[Rgba8],
         scale: u32,
     ) -> io::Result<usize> {
-        assert!(scale >= 1);
-
-        // The gif encoder expects the frame delay in units of 10ms.
-        let frame_delay = frame_delay.as_millis() / 10;
-        // If the passed in delay is larger than a `u16` can hold,
-        // we ensure it doesn't overflow.
-        let frame_delay = u128::min(frame_delay, u16::max_value() as u128) as u16;
-
         let (snapshot, pixels) = self.layer.current_snapshot();
         let extent = snapshot.extent;
-        let nframes = extent.nframes;
-
-        // Create a color palette for the gif, where the zero index is used
-        // for transparency.
-        let transparent: u8 = 0;
-        let mut palette = palette.to_vec();
-        palette.push(Rgba8::TRANSPARENT);
-        palette.sort();
-
-        assert!(palette[transparent as usize] == Rgba8::TRANSPARENT);
-        assert!(palette.len() <= 256);
-
-        // Convert RGBA pixels into indexed pixels.
-        let mut image: Vec<u8> = Vec::with_capacity(snapshot.size);
-        for rgba in pixels {
-            if let Ok(index) = palette.binary_search(rgba) {
-                image.push(index as u8);
-            } else {
-                image.push(transparent);
-            }
-        }
-        if scale > 1 {
-            image = pixels::scale(&image, extent.width(), extent.height(), scale);
-        }
-
-        let (fw, fh) = ((extent.fw * scale) as usize, (extent.fh * scale) as usize);
-        let mut frames: Vec<Vec<u8>> = Vec::with_capacity(nframes);
-        frames.resize(nframes, Vec::with_capacity(fw * fh));
-
-        {
-            // Convert animation strip into discrete frames for gif encoder.
-            let nrows = fh as usize * nframes;
-            let row_nbytes = fw as usize;
-
-            for i in 0..nrows {
-                let offset = i * row_nbytes;
-                let row = &image[offset..offset + row_nbytes];
+        let frames: Vec<_> = (0..extent.nframes)
+            .map(|i| (Self::frame_pixels(pixels, extent, i), frame_delay))
+            .collect();
 
-                frames[i % nframes].extend_from_slice(row);
-            }
-        }
-
-        // Discard alpha channel and convert to a `&[u8]`.
-        let palette: Vec<Rgb8> = palette.into_iter().map(Rgb8::from).collect();
-        let palette = util::align_u8(&palette);
-
-        let mut f = File::create(path.as_ref())?;
-        let mut encoder = gif::Encoder::new(&mut f, fw as u16, fh as u16, palette)?;
-        encoder.set(gif::Repeat::Infinite)?;
-
-        for frame in frames.iter_mut() {
-            let mut frame =
-                gif::Frame::from_indexed_pixels(fw as u16, fh as u16, frame, Some(transparent));
-            frame.delay = frame_delay;
-            frame.dispose = gif::DisposalMethod::Background;
+        image::save_gif(path, extent.fw, extent.fh, &frames, palette, scale)
+    }
 
-            encoder.write_frame(&frame)?;
-        }
+    /// Return the pixels of a single frame, given the pixels of the whole view.
+    pub fn frame_pixels(pixels: &[Rgba8], extent: ViewExtent, n: usize) -> Vec<Rgba8> {
+        let (fw, width) = (extent.fw as usize, extent.width() as usize);
 
-        Ok(fw * fh * nframes)
+        pixels
+            .chunks(width)
+            .flat_map(|row| &row[n * fw..n * fw + fw])
+            .cloned()
+            .collect()
     }
 }
 
@@ -257,6 +244,12 @@ pub struct LayerResource {
     /// Current layer pixels. We keep a separate decompressed
     /// cache of the view pixels for performance reasons.
     pixels: Vec<Rgba8>,
+    /// Palette of the layer, if it is indexed.
+    palette: Option<IndexedPalette>,
+    /// Current layer palette indices, if the layer is indexed.
+    indices: Vec<u8>,
+    /// Incremented every time the current layer pixels are replaced.
+    revision: u64,
 }
 
 impl LayerResource {
@@ -265,9 +258,24 @@ impl LayerResource {
             snapshots: NonEmpty::new(Snapshot::new(SnapshotId(0), &pixels, extent)),
             snapshot: 0,
             pixels,
+            palette: None,
+            indices: Vec::new(),
+            revision: 0,
         }
     }
 
+    /// Return the revision of the current layer pixels, which changes every
+    /// time they are replaced.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Return the palette and the current palette indices of the layer, if
+    /// it is indexed.
+    pub fn current_indices(&self) -> Option<(&IndexedPalette, &[u8])> {
+        self.palette.as_ref().map(|p| (p, self.indices.as_slice()))
+    }
+
     pub fn current_snapshot(&self) -> (&Snapshot, &[Rgba8]) {
         (
             self.snapshots
@@ -288,41 +296,22 @@ impl LayerResource {
 
     pub fn get_snapshot_rect(&self, rect: &Rect<i32>) -> Option<(&Snapshot, Vec<Rgba8>)> {
         let (snapshot, pixels) = self.current_snapshot();
-        let snapshot_rect = snapshot.extent.rect().map(|n| n as i32);
-
-        // Fast path.
-        if snapshot_rect == *rect {
-            return Some((snapshot, pixels.into()));
-        }
-
-        let w = rect.width() as usize;
-        let h = rect.height() as usize;
-
-        let total_w = snapshot.width() as usize;
-        let total_h = snapshot.height() as usize;
-
-        if !(snapshot_rect.x1 <= rect.x1 && snapshot_rect.y1 <= rect.y1)
-            || !(snapshot_rect.x2 >= rect.x2 && snapshot_rect.y2 >= rect.y2)
-        {
-            return None;
-        }
-        debug_assert!(w * h <= total_w * total_h);
 
-        let mut buffer: Vec<Rgba8> = Vec::with_capacity(w * h);
-
-        for y in (rect.y1 as usize..rect.y2 as usize).rev() {
-            let y = total_h - y - 1;
-            let offset = y * total_w + rect.x1 as usize;
-            let row = &pixels[offset..offset + w];
+        self::crop(pixels, snapshot, rect).map(|buffer| (snapshot, buffer))
+    }
 
-            buffer.extend_from_slice(row);
-        }
-        assert!(buffer.len() == w * h);
+    /// Return the palette indices of the given area, if the layer is indexed.
+    pub fn get_indices_rect(&self, rect: &Rect<i32>) -> Option<Vec<u8>> {
+        let (snapshot, _) = self.current_snapshot();
+        let (_, indices) = self.current_indices()?;
 
-        Some((snapshot, buffer))
+        self::crop(indices, snapshot, rect)
     }
 
-    pub fn push_snapshot(&mut self, pixels: Vec<Rgba8>, extent: ViewExtent) {
+    /// Push a snapshot of the given pixels. If the layer is indexed, pixels that
+    /// aren't in its palette are replaced with the nearest palette color, and the
+    /// number of pixels replaced is returned.
+    pub fn push_snapshot(&mut self, pixels: Vec<Rgba8>, extent: ViewExtent) -> usize {
         // FIXME: If pixels match current snapshot exactly, don't add the snapshot.
 
         // If we try to add a snapshot when we're not at the
@@ -331,36 +320,115 @@ impl LayerResource {
             self.snapshots.truncate(self.snapshot + 1);
             self.snapshot = self.snapshots.len() - 1;
         }
+        let id = SnapshotId(self.snapshot + 1);
+
+        let (snapshot, snapped) = if let Some(palette) = &self.palette {
+            // Pixels keep their index if their color didn't change.
+            let (current, _) = self.current_snapshot();
+            let previous = Some(self.indices.as_slice()).filter(|_| current.extent == extent);
+            let (indices, snapped) = palette.index(&pixels, previous);
+
+            self.pixels = palette.pixels(&indices);
+            self.indices = indices;
+
+            (
+                Snapshot::indexed(id, &self.indices, &palette.colors, extent),
+                snapped,
+            )
+        } else {
+            let snapshot = Snapshot::new(id, &pixels, extent);
+            self.pixels = pixels;
+
+            (snapshot, 0)
+        };
         self.snapshot += 1;
-        self.snapshots
-            .push(Snapshot::new(SnapshotId(self.snapshot), &pixels, extent));
-        self.pixels = pixels;
+        self.snapshots.push(snapshot);
+        self.revision += 1;
+
+        snapped
     }
 
     pub fn prev_snapshot(&mut self) -> Option<&Snapshot> {
         if self.snapshot == 0 {
             return None;
         }
-        if let Some(snapshot) = self.snapshots.get(self.snapshot - 1) {
-            self.snapshot -= 1;
-            self.pixels = snapshot.pixels();
+        self.snapshot -= 1;
+        self.load_snapshot();
+        self.snapshots.get(self.snapshot)
+    }
 
-            Some(snapshot)
-        } else {
-            None
+    pub fn next_snapshot(&mut self) -> Option<&Snapshot> {
+        if self.snapshot + 1 >= self.snapshots.len() {
+            return None;
         }
+        self.snapshot += 1;
+        self.load_snapshot();
+        self.snapshots.get(self.snapshot)
     }
 
-    pub fn next_snapshot(&mut self) -> Option<&Snapshot> {
-        if let Some(snapshot) = self.snapshots.get(self.snapshot + 1) {
-            self.snapshot += 1;
-            self.pixels = snapshot.pixels();
+    /// Load the pixels of the current snapshot. The pixels of indexed layers
+    /// are always in the layer palette: if the snapshot is indexed, its indices
+    /// are used with the layer palette, otherwise its pixels are indexed.
+    fn load_snapshot(&mut self) {
+        let snapshot = self
+            .snapshots
+            .get(self.snapshot)
+            .expect("there must always be a current snapshot");
+
+        match (&self.palette, snapshot.indices()) {
+            (Some(palette), Some(indices)) => {
+                self.pixels = palette.pixels(&indices);
+                self.indices = indices;
+            }
+            (Some(palette), None) => {
+                let (indices, _) = palette.index(&snapshot.pixels(), None);
 
-            Some(snapshot)
-        } else {
-            None
+                self.pixels = palette.pixels(&indices);
+                self.indices = indices;
+            }
+            (None, _) => {
+                self.pixels = snapshot.pixels();
+                self.indices = Vec::new();
+            }
         }
+        self.revision += 1;
+    }
+}
+
+/// Return the given area of a snapshot's pixel data, stored top row first.
+fn crop<T: Copy>(data: &[T], snapshot: &Snapshot, rect: &Rect<i32>) -> Option<Vec<T>> {
+    let snapshot_rect = snapshot.extent.rect().map(|n| n as i32);
+
+    // Fast path.
+    if snapshot_rect == *rect {
+        return Some(data.into());
+    }
+
+    let w = rect.width() as usize;
+    let h = rect.height() as usize;
+
+    let total_w = snapshot.width() as usize;
+    let total_h = snapshot.height() as usize;
+
+    if !(snapshot_rect.x1 <= rect.x1 && snapshot_rect.y1 <= rect.y1)
+        || !(snapshot_rect.x2 >= rect.x2 && snapshot_rect.y2 >= rect.y2)
+    {
+        return None;
+    }
+    debug_assert!(w * h <= total_w * total_h);
+
+    let mut buffer: Vec<T> = Vec::with_capacity(w * h);
+
+    for y in (rect.y1 as usize..rect.y2 as usize).rev() {
+        let y = total_h - y - 1;
+        let offset = y * total_w + rect.x1 as usize;
+        let row = &data[offset..offset + w];
+
+        buffer.extend_from_slice(row);
     }
+    assert!(buffer.len() == w * h);
+
+    Some(buffer)
 }
 
 #[derive(Debug, Clone)]
@@ -386,26 +454,47 @@ pub struct Snapshot {
     pub id: SnapshotId,
     pub extent: ViewExtent,
 
-    size: usize,
-    pixels: Compressed<Box<[u8]>>,
+    pixels: SnapshotPixels,
+}
+
+/// Compressed snapshot pixels.
+#[derive(Debug)]
+enum SnapshotPixels {
+    /// RGBA pixels.
+    Rgba(Compressed<Box<[u8]>>),
+    /// Palette indices, with the palette colors they were recorded with.
+    Indexed(Compressed<Box<[u8]>>, Vec<Rgba8>),
 }
 
 impl Snapshot {
     pub fn new(id: SnapshotId, pixels: &[Rgba8], extent: ViewExtent) -> Self {
-        let size = pixels.len();
-        let pixels =
-            Compressed::from(pixels).expect("compressing snapshot shouldn't result in an error");
-
         debug_assert!(
-            (extent.fw * extent.fh) as usize * extent.nframes == size,
+            (extent.fw * extent.fh) as usize * extent.nframes == pixels.len(),
             "the pixel buffer has the expected size"
         );
+        let pixels = Compressed::from(util::align_u8(pixels))
+            .expect("compressing snapshot shouldn't result in an error");
 
         Self {
             id,
             extent,
-            size,
-            pixels,
+            pixels: SnapshotPixels::Rgba(pixels),
+        }
+    }
+
+    /// Create a snapshot of an indexed layer, from palette indices.
+    pub fn indexed(id: SnapshotId, indices: &[u8], colors: &[Rgba8], extent: ViewExtent) -> Self {
+        debug_assert!(
+            (extent.fw * extent.fh) as usize * extent.nframes == indices.len(),
+            "the index buffer has the expected size"
+        );
+        let indices =
+            Compressed::from(indices).expect("compressing snapshot shouldn't result in an error");
+
+        Self {
+            id,
+            extent,
+            pixels: SnapshotPixels::Indexed(indices, colors.to_vec()),
         }
     }
 
@@ -427,11 +516,39 @@ impl Snapshot {
     ////////////////////////////////////////////////////////////////////////////
 
     fn pixels(&self) -> Vec<Rgba8> {
-        let bytes = self
-            .pixels
-            .decompress()
-            .expect("decompressing snapshot shouldn't result in an error");
-        Rgba8::align(&bytes).into()
+        match &self.pixels {
+            SnapshotPixels::Rgba(pixels) => {
+                let bytes = pixels
+                    .decompress()
+                    .expect("decompressing snapshot shouldn't result in an error");
+                Rgba8::align(&bytes).into()
+            }
+            SnapshotPixels::Indexed(_, colors) => {
+                let indices = self.indices().expect("the snapshot is indexed");
+
+                indices
+                    .iter()
+                    .map(|i| {
+                        colors
+                            .get(*i as usize)
+                            .cloned()
+                            .unwrap_or(Rgba8::TRANSPARENT)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Return the palette indices of the snapshot, if it was recorded indexed.
+    fn indices(&self) -> Option<Vec<u8>> {
+        match &self.pixels {
+            SnapshotPixels::Rgba(_) => None,
+            SnapshotPixels::Indexed(indices, _) => Some(
+                indices
+                    .decompress()
+                    .expect("decompressing snapshot shouldn't result in an error"),
+            ),
+        }
     }
 }
 
@@ -441,9 +558,8 @@ impl Snapshot {
 pub struct Compressed<T>(T);
 
 impl Compressed<Box<[u8]>> {
-    fn from(input: &[Rgba8]) -> snap::Result<Self> {
+    fn from(bytes: &[u8]) -> snap::Result<Self> {
         let mut enc = snap::Encoder::new();
-        let bytes = util::align_u8(input);
         enc.compress_vec(bytes).map(|v| Self(v.into_boxed_slice()))
     }
 